@@ -0,0 +1,245 @@
+use mail_client::config::{self, Config, Profile};
+use mail_client::credentials::{self, CredentialStore};
+use mail_client::smtp::{self, SMTPMail, Attachment, parse_address_list};
+use mail_client::pop3;
+use mail_client::error::{MailError, MessageError};
+use crate::ui::describe;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+
+//退出码: 成功、操作失败(连接、服务器拒绝等)、参数错误、配置或凭据错误
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_CONFIG: i32 = 3;
+
+//非交互运行时从环境变量读取授权码或凭据文件的主口令
+const PASSWORD_ENV: &str = "MAIL_CLIENT_PASSWORD";
+const PASSPHRASE_ENV: &str = "MAIL_CLIENT_PASSPHRASE";
+
+const USAGE: &str = "用法: mail_client <command> [options]
+
+命令:
+  send   发送一封邮件
+         --to <地址> [--cc <地址>] [--bcc <地址>] [--from <地址>] --subject <主题>
+         --body-file <路径|-> [--html-file <路径>] [--attach <路径>]...
+         地址参数可重复，也可以逗号分隔多个地址；--from 省略时使用配置中的邮箱账号
+  list   列出服务器上的邮件摘要
+  fetch  将服务器上的新邮件下载到本地存储，并按保留策略删除到期邮件
+  help   显示本帮助
+
+通用选项:
+  --profile <名称>  使用指定配置，省略时使用默认配置
+  --config <路径>   配置文件路径，省略时使用默认路径
+
+授权码依次从环境变量 MAIL_CLIENT_PASSWORD、凭据文件中读取，
+读取凭据文件时主口令从环境变量 MAIL_CLIENT_PASSPHRASE 读取。
+结果以一行 JSON 输出到标准输出，\"ok\" 字段表示是否成功。
+退出码: 0 成功，1 操作失败，2 参数错误，3 配置或凭据错误";
+
+#[derive(Debug, Default)]
+struct Options{
+    command: String,
+    profile: Option<String>,
+    config: Option<String>,
+    from: Option<String>,
+    to: Vec<String>,
+    cc: Vec<String>,
+    bcc: Vec<String>,
+    subject: Option<String>,
+    body_file: Option<String>,
+    html_file: Option<String>,
+    attachments: Vec<String>
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String>{
+    let mut options = Options::default();
+    let mut args = args.iter();
+    options.command = match args.next(){
+        Some(command) => command.clone(),
+        None => { return Err(String::from("缺少命令")); }
+    };
+    while let Some(flag) = args.next(){
+        let mut value = ||{
+            match args.next(){
+                Some(value) => Ok(value.clone()),
+                None => Err(format!("{} 缺少参数", flag))
+            }
+        };
+        match flag.as_str(){
+            "--profile" => { options.profile.replace(value()?); }
+            "--config" => { options.config.replace(value()?); }
+            "--from" => { options.from.replace(value()?); }
+            "--to" => { options.to.append(&mut parse_address_list(&value()?)); }
+            "--cc" => { options.cc.append(&mut parse_address_list(&value()?)); }
+            "--bcc" => { options.bcc.append(&mut parse_address_list(&value()?)); }
+            "--subject" => { options.subject.replace(value()?); }
+            "--body-file" => { options.body_file.replace(value()?); }
+            "--html-file" => { options.html_file.replace(value()?); }
+            "--attach" => { options.attachments.push(value()?); }
+            _ => { return Err(format!("无法识别的参数 {}", flag)); }
+        }
+    }
+    Ok(options)
+}
+
+pub fn run(args: &[String]) -> i32{
+    let options = match parse_args(args){
+        Ok(options) => options,
+        Err(error) => { return usage_error(&error); }
+    };
+    let result = match options.command.as_str(){
+        "help" | "--help" | "-h" => { println!("{}", USAGE); return EXIT_OK; }
+        "send" => send(&options),
+        "list" => list(&options),
+        "fetch" => fetch(&options),
+        command => { return usage_error(&format!("无法识别的命令 {}", command)); }
+    };
+    match result{
+        Ok(mut output) => {
+            output["ok"] = Value::Bool(true);
+            println!("{}", output);
+            EXIT_OK
+        }
+        Err(error) => {
+            let code = match error{
+                MailError::Config(_) | MailError::Credential(_) | MailError::ConfigFile{ .. } | MailError::CredentialFile{ .. } => EXIT_CONFIG,
+                _ => EXIT_FAILURE
+            };
+            println!("{}", json!({ "ok": false, "error": describe(&error) }));
+            code
+        }
+    }
+}
+
+fn usage_error(message: &str) -> i32{
+    println!("{}", json!({ "ok": false, "error": message }));
+    eprintln!("{}", USAGE);
+    EXIT_USAGE
+}
+
+fn load_profile(options: &Options) -> Result<(PathBuf, String, Profile), MailError>{
+    let config_path = options.config.as_ref().map(PathBuf::from).unwrap_or_else(config::default_path);
+    let config = Config::load(&config_path)?;
+    let name = match options.profile.clone().or_else(|| config.default_profile.clone()){
+        Some(name) => name,
+        None => { return Err(MailError::Config(String::from("未指定配置，且没有默认配置"))); }
+    };
+    match config.profiles.get(&name){
+        Some(profile) => Ok((config_path, name, profile.clone())),
+        None => Err(MailError::Config(format!("配置 \"{}\" 不存在", name)))
+    }
+}
+
+fn load_password(config_path: &std::path::Path, profile_name: &str) -> Result<String, MailError>{
+    if let Ok(password) = std::env::var(PASSWORD_ENV){
+        return Ok(password);
+    }
+    let mut store = CredentialStore::open(&credentials::default_path(config_path))?;
+    if !store.contains(profile_name){
+        return Err(MailError::Credential(format!("未设置 {}，且凭据文件中没有配置 \"{}\" 的授权码", PASSWORD_ENV, profile_name)));
+    }
+    let passphrase = match std::env::var(PASSPHRASE_ENV){
+        Ok(passphrase) => passphrase,
+        Err(_) => { return Err(MailError::Credential(format!("读取凭据文件需要设置 {}", PASSPHRASE_ENV))); }
+    };
+    store.unlock(&passphrase)?;
+    Ok(store.get(profile_name)?.unwrap_or_default())
+}
+
+fn read_text(path: &str) -> Result<String, MailError>{
+    //"-" 表示从标准输入读取
+    let result = if path == "-"{
+        std::io::read_to_string(std::io::stdin())
+    }else{
+        std::fs::read_to_string(path)
+    };
+    result.map_err(|error| MessageError::File{ path: path.to_string(), source: error }.into())
+}
+
+fn build_mail(options: &Options, profile: &Profile) -> Result<SMTPMail, MailError>{
+    let mut mail = SMTPMail::new();
+    mail.from = options.from.clone().or_else(|| profile.account.clone());
+    mail.to = options.to.clone();
+    mail.cc = options.cc.clone();
+    mail.bcc = options.bcc.clone();
+    mail.subject = options.subject.clone();
+    if let Some(path) = &options.body_file{
+        mail.content.replace(read_text(path)?.lines().map(|line| line.to_string()).collect());
+    }
+    if let Some(path) = &options.html_file{
+        mail.html.replace(read_text(path)?);
+    }
+    for path in &options.attachments{
+        mail.attachments.push(Attachment::from_path(path)?);
+    }
+    Ok(mail)
+}
+
+fn send(options: &Options) -> Result<Value, MailError>{
+    let (config_path, profile_name, profile) = load_profile(options)?;
+    let mail = build_mail(options, &profile)?;
+    mail.validate()?;
+    let mut client = smtp::Client::from_profile(&profile, load_password(&config_path, &profile_name)?)?;
+    client.open()?;
+    client.authorize()?;
+    let delivery = client.deliver(&mail)?;
+    let _ = client.close();
+    Ok(json!({ "accepted": delivery.accepted, "rejected": delivery.rejected }))
+}
+
+fn list(options: &Options) -> Result<Value, MailError>{
+    let (config_path, profile_name, profile) = load_profile(options)?;
+    let password = load_password(&config_path, &profile_name)?;
+    let mut client = pop3::Client::from_profile(&profile, password.clone())?;
+    client.open()?;
+    client.authorize(profile.account.clone().unwrap_or_default(), password)?;
+    let summaries = client.summaries()?;
+    let _ = client.close();
+    Ok(json!({ "messages": summaries }))
+}
+
+fn fetch(options: &Options) -> Result<Value, MailError>{
+    let (config_path, profile_name, profile) = load_profile(options)?;
+    let password = load_password(&config_path, &profile_name)?;
+    let mut client = pop3::Client::from_profile(&profile, password.clone())?;
+    client.open()?;
+    client.authorize(profile.account.clone().unwrap_or_default(), password)?;
+    let report = client.fetch_new()?;
+    //QUIT 成功后服务器才会删除到期邮件
+    let deleted = client.close()?;
+    let mut output = json!(report);
+    output["deleted"] = json!(deleted);
+    Ok(output)
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn args(line: &str) -> Vec<String>{
+        line.split_whitespace().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_send_options(){
+        let options = parse_args(&args("send --profile work --to a@example.com,b@example.com --to c@example.com --subject hi --attach x.pdf --attach y.pdf")).unwrap();
+        assert_eq!(options.command, "send");
+        assert_eq!(options.profile.as_deref(), Some("work"));
+        assert_eq!(options.to, vec!["a@example.com", "b@example.com", "c@example.com"]);
+        assert_eq!(options.attachments, vec!["x.pdf", "y.pdf"]);
+        assert!(parse_args(&args("send --subject")).is_err());
+        assert!(parse_args(&args("send --bogus 1")).is_err());
+        assert!(parse_args(&[]).is_err());
+    }
+
+    #[test]
+    fn reports_configuration_errors(){
+        let path = std::env::temp_dir().join(format!("mail_client_cli_{}", std::process::id())).join("config.toml");
+        let options = parse_args(&args(&format!("list --config {} --profile work", path.display()))).unwrap();
+        assert!(matches!(load_profile(&options), Err(MailError::Config(_))));
+        assert_eq!(run(&args(&format!("fetch --config {}", path.display()))), EXIT_CONFIG);
+        assert_eq!(run(&args("receive")), EXIT_USAGE);
+    }
+}
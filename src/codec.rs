@@ -0,0 +1,278 @@
+use encoding::label::encoding_from_whatwg_label;
+use encoding::{DecoderTrap, Encoding, EncodingRef};
+use encoding::all::GB18030;
+use regex::Regex;
+
+//WHATWG 标签表之外的常见别名；GBK 系列统一按其超集 GB18030 解码
+const CHARSET_ALIASES: [(&str, &str); 12] = [
+    ("gbk", "gb18030"), ("gb2312", "gb18030"), ("x-gbk", "gb18030"), ("csgb2312", "gb18030"),
+    ("cp936", "gb18030"), ("ms936", "gb18030"), ("euc-cn", "gb18030"),
+    ("cp950", "big5"), ("cp932", "shift_jis"), ("cp949", "euc-kr"),
+    ("latin-1", "iso-8859-1"), ("utf_8", "utf-8"),
+];
+
+const ASCII_LABELS: [&str; 4] = ["us-ascii", "ascii", "ansi_x3.4-1968", "iso646-us"];
+
+//RFC 2045 6.7: quoted-printable 编码后每行不超过 76 个字符(含软换行符)
+const QP_LINE_LIMIT: usize = 76;
+
+//单个 encoded-word 的最大长度，RFC 2047 规定不超过 75，这里为头部名称预留空间
+const MAX_ENCODED_WORD: usize = 66;
+
+pub fn needs_encoding(text: &str) -> bool{
+    //非 ASCII 字符、控制字符，或本身形似 encoded-word 的文本都需要编码
+    !text.is_ascii() || text.chars().any(|c| c.is_ascii_control() && c != '\t') || text.contains("=?")
+}
+
+pub fn encode_header(text: &str) -> String{
+    //将头部文本编码为以空格分隔的一组 encoded-word，可直接交给 message::fold_header 折行
+    //fold_header 只在空白处折行，因此过长的单词也需编码拆分
+    if !needs_encoding(text) && !text.split_whitespace().any(|word| word.len() > MAX_ENCODED_WORD){
+        return text.to_string();
+    }
+    let non_ascii = text.chars().filter(|c| !c.is_ascii()).count();
+    let q_mode = non_ascii * 3 < text.chars().count();
+    let mut words: Vec<String> = Vec::new();
+    let mut chunk = String::new();
+    for c in text.chars(){
+        let mut candidate = chunk.clone();
+        candidate.push(c);
+        let length = if q_mode { q_encode(&candidate).len() } else { base64_length(candidate.len()) };
+        if length + 12 > MAX_ENCODED_WORD && !chunk.is_empty(){
+            words.push(encoded_word(&chunk, q_mode));
+            chunk.clear();
+        }
+        chunk.push(c);
+    }
+    if !chunk.is_empty(){
+        words.push(encoded_word(&chunk, q_mode));
+    }
+    words.join(" ")
+}
+
+fn encoded_word(text: &str, q_mode: bool) -> String{
+    if q_mode{
+        format!("=?utf-8?Q?{}?=", q_encode(text))
+    }else{
+        format!("=?utf-8?B?{}?=", base64::encode(text))
+    }
+}
+
+fn base64_length(bytes: usize) -> usize{
+    bytes.div_ceil(3) * 4
+}
+
+fn q_encode(text: &str) -> String{
+    //RFC 2047 5(3): 在短语中只允许字母数字及 !*+-/ 原样出现
+    let mut result = String::new();
+    for byte in text.bytes(){
+        match byte{
+            b' ' => result.push('_'),
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'!' | b'*' | b'+' | b'-' | b'/' => result.push(byte as char),
+            _ => result.push_str(&format!("={:02X}", byte))
+        }
+    }
+    result
+}
+
+fn q_decode(text: &str) -> Vec<u8>{
+    unescape_hex(text.as_bytes(), true)
+}
+
+fn unescape_hex(bytes: &[u8], underscore: bool) -> Vec<u8>{
+    //还原 =XX 转义，Q 编码中 _ 表示空格；不合法的转义保持原样
+    let mut result: Vec<u8> = Vec::new();
+    let mut index = 0;
+    while index < bytes.len(){
+        match bytes[index]{
+            b'_' if underscore => { result.push(b' '); }
+            b'=' if index + 2 < bytes.len() => {
+                match (hex_value(bytes[index + 1]), hex_value(bytes[index + 2])){
+                    (Some(high), Some(low)) => {
+                        result.push(high << 4 | low);
+                        index += 2;
+                    }
+                    _ => { result.push(b'='); }
+                }
+            }
+            byte => { result.push(byte); }
+        }
+        index += 1;
+    }
+    result
+}
+
+fn hex_value(byte: u8) -> Option<u8>{
+    (byte as char).to_digit(16).map(|value| value as u8)
+}
+
+pub fn qp_encode_line(line: &[u8]) -> Vec<String>{
+    //编码一个逻辑行(不含换行符)，超过 76 列时以软换行 = 拆分；行尾的空白必须编码，否则可能在传输中被删除
+    let mut lines: Vec<String> = Vec::new();
+    let mut current = String::new();
+    for (index, byte) in line.iter().enumerate(){
+        let last = index + 1 == line.len();
+        let token = match byte{
+            b' ' | b'\t' if !last => (*byte as char).to_string(),
+            33..=60 | 62..=126 => (*byte as char).to_string(),
+            _ => format!("={:02X}", byte)
+        };
+        //行内最后一个字符之后不需要软换行符，可以占满 76 列
+        let limit = if last { QP_LINE_LIMIT } else { QP_LINE_LIMIT - 1 };
+        if current.len() + token.len() > limit{
+            current.push('=');
+            lines.push(current);
+            current = String::new();
+        }
+        current.push_str(&token);
+    }
+    lines.push(current);
+    lines
+}
+
+#[derive(Default)]
+pub struct QpDecoder{
+    //尚未遇到换行符的不完整行
+    pending: Vec<u8>
+}
+
+impl QpDecoder{
+    pub fn new() -> QpDecoder{
+        QpDecoder::default()
+    }
+
+    //输入任意长度的数据块，返回其中完整行的解码结果，行尾换行符保持原样
+    pub fn feed(&mut self, data: &[u8]) -> Vec<u8>{
+        self.pending.extend_from_slice(data);
+        let mut result: Vec<u8> = Vec::new();
+        while let Some(position) = self.pending.iter().position(|byte| *byte == b'\n'){
+            let line: Vec<u8> = self.pending.drain(..position + 1).collect();
+            let (content, ending): (&[u8], &[u8]) = match line.strip_suffix(b"\r\n"){
+                Some(content) => (content, b"\r\n"),
+                None => (&line[..line.len() - 1], b"\n")
+            };
+            QpDecoder::decode_line(content, ending, &mut result);
+        }
+        result
+    }
+
+    pub fn finish(mut self) -> Vec<u8>{
+        let mut result: Vec<u8> = Vec::new();
+        let line = std::mem::take(&mut self.pending);
+        QpDecoder::decode_line(&line, b"", &mut result);
+        result
+    }
+
+    fn decode_line(line: &[u8], ending: &[u8], result: &mut Vec<u8>){
+        //RFC 2045 6.7: 行尾空白是传输中添加的填充，应忽略；以 = 结尾的是软换行
+        let mut end = line.len();
+        while end > 0 && (line[end - 1] == b' ' || line[end - 1] == b'\t'){
+            end -= 1;
+        }
+        match line[..end].strip_suffix(b"="){
+            Some(content) => { result.append(&mut unescape_hex(content, false)); }
+            None => {
+                result.append(&mut unescape_hex(&line[..end], false));
+                result.extend_from_slice(ending);
+            }
+        }
+    }
+}
+
+pub fn qp_decode(data: &[u8]) -> Vec<u8>{
+    let mut decoder = QpDecoder::new();
+    let mut result = decoder.feed(data);
+    result.append(&mut decoder.finish());
+    result
+}
+
+pub fn lookup_charset(label: &str) -> Option<EncodingRef>{
+    //不区分大小写；RFC 2231 的语言后缀(charset*lang)被忽略
+    let label = label.split('*').next().unwrap_or(label).trim().trim_matches('"').to_lowercase();
+    let label = CHARSET_ALIASES.iter().find(|(alias, _)| *alias == label).map(|(_, name)| *name).unwrap_or(&label);
+    encoding_from_whatwg_label(label)
+}
+
+pub fn decode_charset(bytes: &[u8], charset: &str) -> Option<String>{
+    //按字符集标签解码，未知字符集返回 None
+    let encoding = lookup_charset(charset)?;
+    encoding.decode(bytes, DecoderTrap::Replace).ok()
+}
+
+pub fn decode_text(bytes: &[u8], charset: Option<&str>) -> (String, String){
+    //返回解码后的文本与实际使用的字符集，无法解码的字节以替换字符表示而不是报错；
+    //字符集未声明、无法识别，或声明为 ASCII 却含有 8 位字节时，依次尝试 UTF-8 与 GB18030
+    let ascii = charset.is_none_or(|label| ASCII_LABELS.contains(&label.trim().trim_matches('"').to_lowercase().as_str()));
+    if ascii && bytes.is_ascii(){
+        return (String::from_utf8_lossy(bytes).to_string(), String::from("us-ascii"));
+    }
+    if let Some(encoding) = charset.filter(|_| !ascii).and_then(lookup_charset){
+        let name = encoding.whatwg_name().unwrap_or(encoding.name()).to_string();
+        return (encoding.decode(bytes, DecoderTrap::Replace).unwrap_or_default(), name);
+    }
+    match std::str::from_utf8(bytes){
+        Ok(text) => (text.to_string(), String::from("utf-8")),
+        Err(_) => (GB18030.decode(bytes, DecoderTrap::Replace).unwrap_or_default(), String::from("gb18030"))
+    }
+}
+
+pub fn decode_header(text: &str) -> String{
+    //解码头部中的 encoded-word，相邻 encoded-word 之间的空白被忽略；无法解码的部分保持原样
+    let word_regex = Regex::new(r"=\?([^?\s]+)\?([BbQq])\?([^?\s]*)\?=").unwrap();
+    let mut result = String::new();
+    let mut last_end = 0;
+    let mut last_was_word = false;
+    for cap in word_regex.captures_iter(text){
+        let whole = cap.get(0).unwrap();
+        let between = &text[last_end..whole.start()];
+        let bytes = if cap[2].eq_ignore_ascii_case("b"){
+            base64::decode(&cap[3]).ok()
+        }else{
+            Some(q_decode(&cap[3]))
+        };
+        let decoded = bytes.and_then(|bytes| decode_charset(&bytes, &cap[1]));
+        if !(last_was_word && between.trim().is_empty() && decoded.is_some()){
+            result.push_str(between);
+        }
+        match decoded{
+            Some(decoded) => {
+                result.push_str(&decoded);
+                last_was_word = true;
+            }
+            None => {
+                result.push_str(whole.as_str());
+                last_was_word = false;
+            }
+        }
+        last_end = whole.end();
+    }
+    result.push_str(&text[last_end..]);
+    result
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn encodes_quoted_printable_lines(){
+        let long = "x".repeat(100) + " é";
+        let encoded = qp_encode_line(long.as_bytes());
+        assert!(encoded.iter().all(|line| line.len() <= QP_LINE_LIMIT));
+        assert_eq!(qp_decode(encoded.join("\r\n").as_bytes()), long.as_bytes());
+        assert_eq!(qp_encode_line(b"tail "), vec![String::from("tail=20")]);
+    }
+
+    #[test]
+    fn decodes_quoted_printable_across_chunks(){
+        //软换行与转义序列被拆在不同数据块中
+        let mut decoder = QpDecoder::new();
+        let mut result = decoder.feed(b"Caf=C3=A9 =3D so");
+        assert!(result.is_empty());
+        result.append(&mut decoder.feed(b"ft=\r\nbreak  \r\n=E4=BD"));
+        result.append(&mut decoder.feed(b"=A0=E5=A5=BD=20"));
+        result.append(&mut decoder.finish());
+        assert_eq!(String::from_utf8(result).unwrap(), "Café = softbreak\r\n你好 ");
+    }
+}
@@ -0,0 +1,269 @@
+use crate::error::MailError;
+use crate::tcp::{Security, TlsConfig, Timeouts};
+use crate::store::{Retention, SyncConfig};
+use crate::sasl;
+use crate::util;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+//配置文件位于 XDG 配置目录(通常为 ~/.config)下的 mail_client/config.toml
+const CONFIG_DIR: &str = "mail_client";
+const CONFIG_FILE: &str = "config.toml";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config{
+    //启动时自动使用的配置
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile{
+    pub account: Option<String>,
+    //本地主机名，用于 ehlo 与 Message-ID
+    pub hostname: Option<String>,
+    //PEM 格式的根证书文件路径，为空时使用系统证书
+    #[serde(default)]
+    pub ca_roots: Vec<String>,
+    //本地邮件存储目录与服务器邮件保留策略(keep 或天数)
+    pub store_dir: Option<String>,
+    pub retention: Option<String>,
+    //连接、读取与发送超时及空闲保活间隔(秒)，省略时使用默认值；keepalive = 0 表示不保活
+    pub connect_timeout: Option<u64>,
+    pub read_timeout: Option<u64>,
+    pub write_timeout: Option<u64>,
+    pub keepalive: Option<u64>,
+    //TOML 中表必须位于普通值之后
+    pub pop3: Option<ServerConfig>,
+    pub smtp: Option<ServerConfig>
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig{
+    pub host: String,
+    //省略时按加密方式使用默认端口
+    pub port: Option<u16>,
+    //plain/starttls/tls
+    pub security: Option<String>,
+    //省略时自动选择验证方式
+    pub auth: Option<String>,
+    pub fingerprint: Option<String>,
+    //允许在未加密连接上发送授权码，默认禁止
+    pub allow_plaintext_auth: Option<bool>
+}
+
+impl ServerConfig{
+    pub fn security(&self, default: Security) -> Security{
+        self.security.as_deref().and_then(Security::parse).unwrap_or(default)
+    }
+
+    fn validate(&self, location: &str, errors: &mut Vec<String>){
+        if self.host.trim().is_empty(){
+            errors.push(format!("{}.host: 主机地址不能为空", location));
+        }
+        if self.port == Some(0){
+            errors.push(format!("{}.port: 端口不能为 0", location));
+        }
+        if let Some(security) = &self.security{
+            if Security::parse(security).is_none(){
+                errors.push(format!("{}.security: 无法识别的加密方式 \"{}\"，可选 plain/starttls/tls", location, security));
+            }
+        }
+        if let Some(auth) = &self.auth{
+            if !sasl::is_supported(auth){
+                errors.push(format!("{}.auth: 不支持的验证方式 \"{}\"，可选 {}", location, auth, sasl::SUPPORTED.join("/").to_lowercase()));
+            }
+        }
+    }
+}
+
+impl Profile{
+    pub fn tls_config(&self, server: &ServerConfig, default: Security) -> TlsConfig{
+        let mut tls_config = TlsConfig::new(server.security(default));
+        tls_config.ca_roots = self.ca_roots.clone();
+        tls_config.pinned_fingerprint = server.fingerprint.clone();
+        tls_config
+    }
+
+    pub fn sync_config(&self) -> SyncConfig{
+        let mut sync_config = SyncConfig::new();
+        if let Some(store_dir) = &self.store_dir{
+            sync_config.store_dir = store_dir.clone();
+        }
+        if let Some(retention) = self.retention.as_deref().and_then(Retention::parse){
+            sync_config.retention = retention;
+        }
+        sync_config
+    }
+
+    pub fn timeouts(&self) -> Timeouts{
+        let mut timeouts = Timeouts::default();
+        let seconds = std::time::Duration::from_secs;
+        if let Some(connect) = self.connect_timeout{
+            timeouts.connect = seconds(connect);
+        }
+        if let Some(read) = self.read_timeout{
+            timeouts.read = seconds(read);
+        }
+        if let Some(write) = self.write_timeout{
+            timeouts.write = seconds(write);
+        }
+        match self.keepalive{
+            Some(0) => { timeouts.keepalive = None; }
+            Some(keepalive) => { timeouts.keepalive = Some(seconds(keepalive)); }
+            None => {}
+        }
+        timeouts
+    }
+
+    fn validate(&self, name: &str, errors: &mut Vec<String>){
+        let location = format!("profiles.{}", name);
+        if let Some(hostname) = &self.hostname{
+            if hostname.is_empty() || hostname.contains(char::is_whitespace) || hostname.contains(['<', '>', '@']){
+                errors.push(format!("{}.hostname: 无效的主机名 \"{}\"", location, hostname));
+            }
+        }
+        let timeouts = [("connect_timeout", self.connect_timeout), ("read_timeout", self.read_timeout), ("write_timeout", self.write_timeout)];
+        for (key, value) in timeouts.iter(){
+            if *value == Some(0){
+                errors.push(format!("{}.{}: 超时时间不能为 0", location, key));
+            }
+        }
+        if let Some(pop3) = &self.pop3{
+            pop3.validate(&format!("{}.pop3", location), errors);
+        }
+        if let Some(smtp) = &self.smtp{
+            smtp.validate(&format!("{}.smtp", location), errors);
+        }
+        if let Some(retention) = &self.retention{
+            if Retention::parse(retention).is_none(){
+                errors.push(format!("{}.retention: 无法识别的保留策略 \"{}\"，可选 keep 或天数", location, retention));
+            }
+        }
+    }
+}
+
+impl Config{
+    pub fn parse(content: &str) -> Result<Config, MailError>{
+        let config: Config = match toml::from_str(content){
+            Ok(config) => config,
+            Err(error) => { return Err(MailError::Config(error.to_string())); }
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), MailError>{
+        //一次报告所有错误
+        let mut errors: Vec<String> = Vec::new();
+        if let Some(default) = &self.default_profile{
+            if !self.profiles.contains_key(default){
+                errors.push(format!("default_profile: 配置 \"{}\" 不存在", default));
+            }
+        }
+        for (name, profile) in &self.profiles{
+            profile.validate(name, &mut errors);
+        }
+        if errors.is_empty() { Ok(()) } else { Err(MailError::Config(errors.join("\n"))) }
+    }
+
+    pub fn load(path: &Path) -> Result<Config, MailError>{
+        //文件不存在时视为空配置
+        match std::fs::read_to_string(path){
+            Ok(content) => Config::parse(&content).map_err(|error|{
+                match error{
+                    MailError::Config(message) => MailError::Config(format!("{}: {}", path.display(), message)),
+                    error => error
+                }
+            }),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(error) => Err(MailError::ConfigFile{ path: path.display().to_string(), source: error })
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), MailError>{
+        self.validate()?;
+        let content = match toml::to_string_pretty(self){
+            Ok(content) => content,
+            Err(error) => { return Err(MailError::Config(error.to_string())); }
+        };
+        if let Err(error) = util::atomic_write(path, content.as_bytes()){
+            return Err(MailError::ConfigFile{ path: path.display().to_string(), source: error });
+        }
+        Ok(())
+    }
+}
+
+pub fn default_path() -> PathBuf{
+    //无法确定配置目录时使用当前目录
+    dirs::config_dir().unwrap_or_else(|| PathBuf::from(".")).join(CONFIG_DIR).join(CONFIG_FILE)
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    const EXAMPLE: &str = r#"
+default_profile = "work"
+
+[profiles.work]
+account = "user@example.com"
+hostname = "laptop"
+retention = "30"
+read_timeout = 20
+keepalive = 0
+
+[profiles.work.pop3]
+host = "pop.example.com"
+security = "tls"
+auth = "cram-md5"
+
+[profiles.work.smtp]
+host = "smtp.example.com"
+port = 587
+security = "starttls"
+"#;
+
+    #[test]
+    fn loads_and_saves_profiles(){
+        let config = Config::parse(EXAMPLE).unwrap();
+        let profile = &config.profiles["work"];
+        let pop3 = profile.pop3.as_ref().unwrap();
+        assert_eq!(profile.tls_config(pop3, Security::Plain).security, Security::Tls);
+        assert_eq!(profile.smtp.as_ref().unwrap().port, Some(587));
+        assert_eq!(profile.sync_config().retention, Retention::DeleteAfterDays(30));
+        assert_eq!(profile.sync_config().store_dir, SyncConfig::new().store_dir);
+        let timeouts = profile.timeouts();
+        assert_eq!(timeouts.read, std::time::Duration::from_secs(20));
+        assert_eq!((timeouts.connect, timeouts.keepalive), (Timeouts::default().connect, None));
+
+        let path = std::env::temp_dir().join(format!("mail_client_config_{}", std::process::id())).join(CONFIG_FILE);
+        config.save(&path).unwrap();
+        let reloaded = Config::load(&path).unwrap();
+        assert_eq!(reloaded.default_profile.as_deref(), Some("work"));
+        assert_eq!(reloaded.profiles["work"].pop3.as_ref().unwrap().auth.as_deref(), Some("cram-md5"));
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn reports_invalid_settings(){
+        let content = EXAMPLE.replace("\"tls\"", "\"ssh\"").replace("\"work\"\n", "\"home\"\n").replace("\"30\"", "\"soon\"").replace("read_timeout = 20", "read_timeout = 0").replace("\"laptop\"", "\"my laptop\"");
+        let message = match Config::parse(&content){
+            Err(MailError::Config(message)) => message,
+            _ => panic!("invalid config accepted")
+        };
+        assert!(message.contains("default_profile: 配置 \"home\" 不存在"));
+        assert!(message.contains("profiles.work.pop3.security: 无法识别的加密方式 \"ssh\""));
+        assert!(message.contains("profiles.work.retention"));
+        assert!(message.contains("profiles.work.read_timeout: 超时时间不能为 0"));
+        assert!(message.contains("profiles.work.hostname: 无效的主机名 \"my laptop\""));
+        assert!(matches!(Config::parse("[profiles.work]\nacount = \"x\"\n"), Err(MailError::Config(_))));
+        assert!(Config::load(Path::new("/nonexistent/mail_client/config.toml")).unwrap().profiles.is_empty());
+    }
+}
@@ -0,0 +1,210 @@
+use crate::error::MailError;
+use crate::util;
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, KeyInit, Nonce};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+//与配置文件位于同一目录
+const CREDENTIALS_FILE: &str = "credentials.toml";
+//用于校验主口令的已知明文，解密成功即说明口令正确
+const VERIFIER: &[u8] = b"mail_client credentials";
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 12;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CredentialFile{
+    //Argon2 盐与口令校验值，均为 base64；未设置主口令时为空
+    salt: Option<String>,
+    verifier: Option<String>,
+    //配置名称 -> base64(随机数 || 密文)
+    #[serde(default)]
+    secrets: BTreeMap<String, String>
+}
+
+pub struct CredentialStore{
+    path: PathBuf,
+    file: CredentialFile,
+    //由主口令派生的密钥，解锁后在本次运行中保留
+    key: Option<[u8; 32]>
+}
+
+impl CredentialStore{
+    pub fn open(path: &Path) -> Result<CredentialStore, MailError>{
+        let file = match std::fs::read_to_string(path){
+            Ok(content) => match toml::from_str(&content){
+                Ok(file) => file,
+                Err(error) => { return Err(MailError::Credential(format!("{}: {}", path.display(), error))); }
+            },
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => CredentialFile::default(),
+            Err(error) => { return Err(MailError::CredentialFile{ path: path.display().to_string(), source: error }); }
+        };
+        Ok(CredentialStore{ path: path.to_path_buf(), file, key: None })
+    }
+
+    //尚未设置主口令，首次解锁时将以输入的口令创建
+    pub fn is_new(&self) -> bool{
+        self.file.salt.is_none()
+    }
+
+    pub fn is_unlocked(&self) -> bool{
+        self.key.is_some()
+    }
+
+    pub fn contains(&self, profile: &str) -> bool{
+        self.file.secrets.contains_key(profile)
+    }
+
+    pub fn unlock(&mut self, passphrase: &str) -> Result<(), MailError>{
+        let salt = match &self.file.salt{
+            Some(salt) => decode(salt)?,
+            None => {
+                let mut salt = vec![0u8; SALT_LENGTH];
+                random(&mut salt)?;
+                let key = derive_key(passphrase, &salt)?;
+                self.file.verifier.replace(encrypt(&key, VERIFIER, b"")?);
+                self.file.salt.replace(base64::encode(&salt));
+                self.key.replace(key);
+                return self.save();
+            }
+        };
+        let key = derive_key(passphrase, &salt)?;
+        let verifier = self.file.verifier.clone().unwrap_or_default();
+        if decrypt(&key, &verifier, b"").ok().as_deref() != Some(VERIFIER){
+            return Err(MailError::Credential(String::from("主口令错误")));
+        }
+        self.key.replace(key);
+        Ok(())
+    }
+
+    pub fn get(&self, profile: &str) -> Result<Option<String>, MailError>{
+        let key = self.key()?;
+        match self.file.secrets.get(profile){
+            Some(secret) => match String::from_utf8(decrypt(&key, secret, profile.as_bytes())?){
+                Ok(secret) => Ok(Some(secret)),
+                Err(_) => Err(MailError::Credential(format!("{}: 无法解密", profile)))
+            },
+            None => Ok(None)
+        }
+    }
+
+    pub fn set(&mut self, profile: &str, secret: &str) -> Result<(), MailError>{
+        let key = self.key()?;
+        self.file.secrets.insert(profile.to_string(), encrypt(&key, secret.as_bytes(), profile.as_bytes())?);
+        self.save()
+    }
+
+    fn key(&self) -> Result<[u8; 32], MailError>{
+        match self.key{
+            Some(key) => Ok(key),
+            None => Err(MailError::Credential(String::from("凭据存储尚未解锁")))
+        }
+    }
+
+    fn save(&self) -> Result<(), MailError>{
+        let content = match toml::to_string_pretty(&self.file){
+            Ok(content) => content,
+            Err(error) => { return Err(MailError::Credential(error.to_string())); }
+        };
+        if let Err(error) = util::atomic_write(&self.path, content.as_bytes()){
+            return Err(MailError::CredentialFile{ path: self.path.display().to_string(), source: error });
+        }
+        Ok(())
+    }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<[u8; 32], MailError>{
+    //Argon2id 默认参数
+    let mut key = [0u8; 32];
+    match Argon2::default().hash_password_into(passphrase.as_bytes(), salt, &mut key){
+        Ok(()) => Ok(key),
+        Err(error) => Err(MailError::Credential(error.to_string()))
+    }
+}
+
+fn encrypt(key: &[u8; 32], plaintext: &[u8], associated: &[u8]) -> Result<String, MailError>{
+    //每次加密使用新的随机数，与密文一同保存；配置名称作为附加数据，密文不能挪用到其他配置
+    let mut nonce = [0u8; NONCE_LENGTH];
+    random(&mut nonce)?;
+    let cipher = cipher(key)?;
+    match cipher.encrypt(&Nonce::from(nonce), Payload{ msg: plaintext, aad: associated }){
+        Ok(mut ciphertext) => {
+            let mut data = nonce.to_vec();
+            data.append(&mut ciphertext);
+            Ok(base64::encode(&data))
+        }
+        Err(_) => Err(MailError::Credential(String::from("加密失败")))
+    }
+}
+
+fn decrypt(key: &[u8; 32], encoded: &str, associated: &[u8]) -> Result<Vec<u8>, MailError>{
+    let data = decode(encoded)?;
+    if data.len() < NONCE_LENGTH{
+        return Err(MailError::Credential(String::from("密文格式错误")));
+    }
+    let mut nonce = [0u8; NONCE_LENGTH];
+    nonce.copy_from_slice(&data[..NONCE_LENGTH]);
+    match cipher(key)?.decrypt(&Nonce::from(nonce), Payload{ msg: &data[NONCE_LENGTH..], aad: associated }){
+        Ok(plaintext) => Ok(plaintext),
+        Err(_) => Err(MailError::Credential(String::from("解密失败，主口令错误或文件已损坏")))
+    }
+}
+
+fn cipher(key: &[u8; 32]) -> Result<ChaCha20Poly1305, MailError>{
+    match ChaCha20Poly1305::new_from_slice(key){
+        Ok(cipher) => Ok(cipher),
+        Err(_) => Err(MailError::Credential(String::from("密钥长度错误")))
+    }
+}
+
+fn decode(encoded: &str) -> Result<Vec<u8>, MailError>{
+    match base64::decode(encoded){
+        Ok(data) => Ok(data),
+        Err(_) => Err(MailError::Credential(String::from("凭据文件格式错误")))
+    }
+}
+
+fn random(buffer: &mut [u8]) -> Result<(), MailError>{
+    match getrandom::getrandom(buffer){
+        Ok(()) => Ok(()),
+        Err(error) => Err(MailError::Credential(error.to_string()))
+    }
+}
+
+pub fn default_path(config_path: &Path) -> PathBuf{
+    config_path.with_file_name(CREDENTIALS_FILE)
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn encrypts_secrets_at_rest(){
+        let path = std::env::temp_dir().join(format!("mail_client_credentials_{}", std::process::id())).join(CREDENTIALS_FILE);
+        let mut store = CredentialStore::open(&path).unwrap();
+        assert!(store.is_new());
+        assert!(store.set("work", "secret").is_err());
+        store.unlock("correct horse").unwrap();
+        store.set("work", "authorization-code").unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(!content.contains("authorization-code"));
+
+        let mut reopened = CredentialStore::open(&path).unwrap();
+        assert!(!reopened.is_new() && !reopened.is_unlocked() && reopened.contains("work"));
+        assert!(matches!(reopened.unlock("wrong"), Err(MailError::Credential(_))));
+        reopened.unlock("correct horse").unwrap();
+        assert_eq!(reopened.get("work").unwrap().as_deref(), Some("authorization-code"));
+        assert_eq!(reopened.get("home").unwrap(), None);
+
+        //挪用到其他配置的密文无法解密
+        let moved = reopened.file.secrets["work"].clone();
+        reopened.file.secrets.insert(String::from("home"), moved);
+        assert!(reopened.get("home").is_err());
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
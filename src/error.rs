@@ -0,0 +1,400 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+//客户端的全部错误，按来源分类；Display 只描述本层错误，底层原因通过 source() 获取
+#[derive(Debug)]
+pub enum MailError{
+    //连接、读写与 TLS
+    Transport(TransportError),
+    //服务器以错误状态回复了命令
+    Reply(ReplyError),
+    //无法解析服务器响应或邮件内容
+    Parse(ParseError),
+    //身份验证过程中的错误
+    Auth(AuthError),
+    //待发送或已接收的邮件不完整、被拒绝或无法读取
+    Message(MessageError),
+    //在当前状态下不能执行该操作(如未登录时发送邮件)
+    ClientState(String),
+    Store(String),
+    Config(String),
+    Credential(String),
+    //读写本地邮件存储、mbox、配置或凭据文件失败
+    StoreFile{ path: String, source: io::Error },
+    MboxFile{ path: String, source: io::Error },
+    ConfigFile{ path: String, source: io::Error },
+    CredentialFile{ path: String, source: io::Error }
+}
+
+impl fmt::Display for MailError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self{
+            MailError::Transport(error) => error.fmt(f),
+            MailError::Reply(error) => error.fmt(f),
+            MailError::Parse(error) => error.fmt(f),
+            MailError::Auth(error) => error.fmt(f),
+            MailError::Message(error) => error.fmt(f),
+            MailError::ClientState(message) => write!(f, "客户端状态错误: {}", message),
+            MailError::Store(message) => write!(f, "本地存储错误: {}", message),
+            MailError::Config(message) => write!(f, "配置错误: {}", message),
+            MailError::Credential(message) => write!(f, "凭据错误: {}", message),
+            MailError::StoreFile{ path, .. } => write!(f, "无法读写本地存储 {}", path),
+            MailError::MboxFile{ path, .. } => write!(f, "无法读写 mbox 文件 {}", path),
+            MailError::ConfigFile{ path, .. } => write!(f, "无法读写配置文件 {}", path),
+            MailError::CredentialFile{ path, .. } => write!(f, "无法读写凭据文件 {}", path)
+        }
+    }
+}
+
+impl Error for MailError{
+    fn source(&self) -> Option<&(dyn Error + 'static)>{
+        //分类只是包装，直接返回内层错误的原因，避免同一条信息出现两次
+        match self{
+            MailError::Transport(error) => error.source(),
+            MailError::Reply(error) => error.source(),
+            MailError::Parse(error) => error.source(),
+            MailError::Auth(error) => error.source(),
+            MailError::Message(error) => error.source(),
+            MailError::StoreFile{ source, .. } => Some(source),
+            MailError::MboxFile{ source, .. } => Some(source),
+            MailError::ConfigFile{ source, .. } => Some(source),
+            MailError::CredentialFile{ source, .. } => Some(source),
+            _ => None
+        }
+    }
+}
+
+impl From<TransportError> for MailError{
+    fn from(error: TransportError) -> MailError{
+        MailError::Transport(error)
+    }
+}
+
+impl From<ReplyError> for MailError{
+    fn from(error: ReplyError) -> MailError{
+        MailError::Reply(error)
+    }
+}
+
+impl From<ParseError> for MailError{
+    fn from(error: ParseError) -> MailError{
+        MailError::Parse(error)
+    }
+}
+
+impl From<AuthError> for MailError{
+    fn from(error: AuthError) -> MailError{
+        MailError::Auth(error)
+    }
+}
+
+impl From<MessageError> for MailError{
+    fn from(error: MessageError) -> MailError{
+        MailError::Message(error)
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IoOperation{
+    Connect, Read, Write
+}
+
+#[derive(Debug)]
+pub enum TransportError{
+    NotConnected,
+    Connect{ address: String, source: io::Error },
+    //超过 tcp::Timeouts 中对应的时间仍未完成，连接随后被断开
+    Timeout(IoOperation),
+    //服务器关闭了连接(读取到 EOF)
+    Closed,
+    Write(io::Error),
+    Read(io::Error),
+    InvalidUtf8(std::string::FromUtf8Error),
+    //读取或解析 TlsConfig 中的根证书文件失败
+    CaFile{ path: String, source: io::Error },
+    Certificate{ path: String, source: native_tls::Error },
+    Tls(native_tls::Error),
+    //升级前服务器已发送了数据，可能是明文注入
+    UnexpectedData,
+    FingerprintMismatch(String)
+}
+
+impl fmt::Display for TransportError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self{
+            TransportError::NotConnected => write!(f, "尚未建立连接或连接已断开"),
+            TransportError::Connect{ address, .. } => write!(f, "无法连接到 {}", address),
+            TransportError::Timeout(IoOperation::Connect) => write!(f, "连接超时"),
+            TransportError::Timeout(IoOperation::Read) => write!(f, "等待服务器响应超时"),
+            TransportError::Timeout(IoOperation::Write) => write!(f, "发送数据超时"),
+            TransportError::Closed => write!(f, "服务器已关闭连接"),
+            TransportError::Write(_) => write!(f, "发送数据失败"),
+            TransportError::Read(_) => write!(f, "读取数据失败"),
+            TransportError::InvalidUtf8(_) => write!(f, "服务器响应不是有效的 UTF-8"),
+            TransportError::CaFile{ path, .. } => write!(f, "无法读取根证书文件 {}", path),
+            TransportError::Certificate{ path, .. } => write!(f, "根证书文件 {} 格式错误", path),
+            TransportError::Tls(_) => write!(f, "TLS 连接失败"),
+            TransportError::UnexpectedData => write!(f, "TLS 握手前收到了多余的数据，已断开连接"),
+            TransportError::FingerprintMismatch(actual) => write!(f, "服务器证书指纹不匹配，实际指纹: {}", actual)
+        }
+    }
+}
+
+impl Error for TransportError{
+    fn source(&self) -> Option<&(dyn Error + 'static)>{
+        match self{
+            TransportError::Connect{ source, .. } => Some(source),
+            TransportError::Write(source) => Some(source),
+            TransportError::Read(source) => Some(source),
+            TransportError::InvalidUtf8(source) => Some(source),
+            TransportError::CaFile{ source, .. } => Some(source),
+            TransportError::Certificate{ source, .. } => Some(source),
+            TransportError::Tls(source) => Some(source),
+            _ => None
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protocol{
+    POP3, SMTP
+}
+
+impl Protocol{
+    pub fn name(&self) -> &'static str{
+        match self{
+            Protocol::POP3 => "POP3",
+            Protocol::SMTP => "SMTP"
+        }
+    }
+}
+
+//RFC 3463 增强状态码 class.subject.detail
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EnhancedStatus{
+    pub class: u8,
+    pub subject: u16,
+    pub detail: u16
+}
+
+impl EnhancedStatus{
+    pub fn parse(text: &str) -> Option<(EnhancedStatus, &str)>{
+        //返回状态码与其后的文字
+        let (code, rest) = match text.find(' '){
+            Some(index) => (&text[..index], text[index + 1..].trim_start()),
+            None => (text, "")
+        };
+        let mut parts = code.split('.');
+        let class = parts.next()?.parse::<u8>().ok()?;
+        let subject = parts.next()?.parse::<u16>().ok()?;
+        let detail = parts.next()?.parse::<u16>().ok()?;
+        if parts.next().is_some() || ![2, 4, 5].contains(&class) || subject > 999 || detail > 999{
+            return None;
+        }
+        Some((EnhancedStatus{ class, subject, detail }, rest))
+    }
+}
+
+impl fmt::Display for EnhancedStatus{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        write!(f, "{}.{}.{}", self.class, self.subject, self.detail)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ReplyError{
+    pub protocol: Protocol,
+    //SMTP 回复码；POP3 的 -ERR 没有回复码
+    pub code: Option<usize>,
+    pub enhanced: Option<EnhancedStatus>,
+    pub text: String
+}
+
+impl ReplyError{
+    pub fn pop3(text: &str) -> ReplyError{
+        ReplyError{ protocol: Protocol::POP3, code: None, enhanced: None, text: text.trim().to_string() }
+    }
+
+    pub fn smtp(code: usize, lines: &[String]) -> ReplyError{
+        //多行回复的每一行都可能带有相同的增强状态码，只保留一次
+        let mut enhanced: Option<EnhancedStatus> = None;
+        let mut text: Vec<&str> = Vec::new();
+        for line in lines{
+            let line = line.trim();
+            match EnhancedStatus::parse(line){
+                Some((status, rest)) => {
+                    enhanced.get_or_insert(status);
+                    text.push(rest);
+                }
+                None => { text.push(line); }
+            }
+        }
+        ReplyError{ protocol: Protocol::SMTP, code: Some(code), enhanced, text: text.join(" ") }
+    }
+
+    pub fn is_transient(&self) -> bool{
+        //4xx 表示稍后重试可能成功
+        match self.code{
+            Some(code) => (400..500).contains(&code),
+            None => self.enhanced.is_some_and(|status| status.class == 4)
+        }
+    }
+}
+
+impl fmt::Display for ReplyError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        write!(f, "{} 服务器返回错误", self.protocol.name())?;
+        if let Some(code) = self.code{
+            write!(f, " {}", code)?;
+        }
+        if let Some(enhanced) = &self.enhanced{
+            write!(f, " ({})", enhanced)?;
+        }
+        if !self.text.is_empty(){
+            write!(f, ": {}", self.text)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ReplyError{}
+
+
+#[derive(Debug)]
+pub enum ParseError{
+    //不符合协议格式的状态行或回复行
+    Response(Protocol, String),
+    //STAT/LIST/UIDL 等响应内容
+    Listing(String),
+    ContentDecode(base64::DecodeError),
+    UnsupportedEncoding(String)
+}
+
+impl fmt::Display for ParseError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self{
+            ParseError::Response(protocol, line) => write!(f, "无法解析 {} 服务器响应: [{}]", protocol.name(), line),
+            ParseError::Listing(line) => write!(f, "无法解析邮件列表: [{}]", line),
+            ParseError::ContentDecode(_) => write!(f, "邮件内容解码失败"),
+            ParseError::UnsupportedEncoding(encoding) => write!(f, "不支持的传输编码: {}", encoding)
+        }
+    }
+}
+
+impl Error for ParseError{
+    fn source(&self) -> Option<&(dyn Error + 'static)>{
+        match self{
+            ParseError::ContentDecode(source) => Some(source),
+            _ => None
+        }
+    }
+}
+
+
+#[derive(Debug)]
+pub enum AuthError{
+    //连接未加密：starttls 模式下服务器不支持 STARTTLS/STLS，或未允许在明文连接上使用该验证方式
+    TlsRequired(String),
+    MechanismNotSupported(String),
+    UnexpectedChallenge(String),
+    ChallengeDecode(String),
+    //服务器拒绝了账号或授权码
+    Rejected(ReplyError)
+}
+
+impl fmt::Display for AuthError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self{
+            AuthError::TlsRequired(method) => write!(f, "连接未加密，已拒绝发送身份信息: {}", method),
+            AuthError::MechanismNotSupported(mechanism) => write!(f, "没有可用的身份验证方式: {}", mechanism),
+            AuthError::UnexpectedChallenge(mechanism) => write!(f, "{} 验证过程中收到了多余的质询", mechanism),
+            AuthError::ChallengeDecode(challenge) => write!(f, "无法解码服务器质询: [{}]", challenge),
+            AuthError::Rejected(_) => write!(f, "登录被拒绝，请检查邮箱地址与授权码是否正确")
+        }
+    }
+}
+
+impl Error for AuthError{
+    fn source(&self) -> Option<&(dyn Error + 'static)>{
+        match self{
+            AuthError::Rejected(reply) => Some(reply),
+            _ => None
+        }
+    }
+}
+
+
+#[derive(Debug)]
+pub enum MessageError{
+    MissingHeader(&'static str),
+    MissingRecipients,
+    MissingBody,
+    TooLarge{ size: usize, limit: usize },
+    //头部中不含空白的内容过长，折行后仍超过 998 个字符
+    HeaderTooLong(String),
+    //所有接收方均被服务器拒绝
+    RecipientsRejected(Vec<String>),
+    //附件或正文文件
+    File{ path: String, source: io::Error }
+}
+
+impl fmt::Display for MessageError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self{
+            MessageError::MissingHeader(name) => write!(f, "邮件缺少 {}", name),
+            MessageError::MissingRecipients => write!(f, "邮件没有接收方"),
+            MessageError::MissingBody => write!(f, "邮件没有正文"),
+            MessageError::TooLarge{ size, limit } => write!(f, "邮件大小 ({} 字节) 超过服务器上限 ({} 字节)", size, limit),
+            MessageError::HeaderTooLong(name) => write!(f, "邮件头部 {} 过长，无法折行到 998 个字符以内", name),
+            MessageError::RecipientsRejected(recipients) => write!(f, "所有接收方均被拒绝: {}", recipients.join(", ")),
+            MessageError::File{ path, .. } => write!(f, "无法读取文件 {}", path)
+        }
+    }
+}
+
+impl Error for MessageError{
+    fn source(&self) -> Option<&(dyn Error + 'static)>{
+        match self{
+            MessageError::File{ source, .. } => Some(source),
+            _ => None
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn parses_enhanced_status_from_smtp_reply(){
+        let lines = vec![String::from("5.1.1 <nobody@example.com>: Recipient address rejected"), String::from("5.1.1 User unknown")];
+        let reply = ReplyError::smtp(550, &lines);
+        assert_eq!(reply.enhanced, Some(EnhancedStatus{ class: 5, subject: 1, detail: 1 }));
+        assert_eq!(reply.text, "<nobody@example.com>: Recipient address rejected User unknown");
+        assert_eq!(reply.to_string(), "SMTP 服务器返回错误 550 (5.1.1): <nobody@example.com>: Recipient address rejected User unknown");
+        assert!(!reply.is_transient());
+        assert!(ReplyError::smtp(451, &[String::from("Try again later")]).is_transient());
+        assert_eq!(EnhancedStatus::parse("2.0 OK"), None);
+        assert_eq!(ReplyError::pop3(" [IN-USE] mailbox locked ").to_string(), "POP3 服务器返回错误: [IN-USE] mailbox locked");
+    }
+
+    #[test]
+    fn chains_underlying_causes(){
+        let io_error = io::Error::new(io::ErrorKind::ConnectionRefused, "refused");
+        let error = MailError::from(TransportError::Connect{ address: String::from("mail.example.com:995"), source: io_error });
+        assert_eq!(error.to_string(), "无法连接到 mail.example.com:995");
+        assert_eq!(error.source().unwrap().to_string(), "refused");
+
+        let error = MailError::from(AuthError::Rejected(ReplyError::smtp(535, &[String::from("5.7.8 Authentication failed")])));
+        let reply = error.source().unwrap().downcast_ref::<ReplyError>().unwrap();
+        assert_eq!((reply.code, reply.text.as_str()), (Some(535), "Authentication failed"));
+
+        let error = MailError::StoreFile{ path: String::from("mail_store/index"), source: io::Error::new(io::ErrorKind::PermissionDenied, "denied") };
+        assert_eq!(error.to_string(), "无法读写本地存储 mail_store/index");
+        assert_eq!(error.source().unwrap().downcast_ref::<io::Error>().unwrap().kind(), io::ErrorKind::PermissionDenied);
+    }
+}
@@ -0,0 +1,15 @@
+//邮件客户端核心库: 协议客户端、报文解析与本地存储，不进行任何终端输入输出
+pub mod tcp;
+pub mod smtp;
+pub mod util;
+pub mod error;
+pub mod pop3;
+pub mod sasl;
+pub mod message;
+pub mod codec;
+pub mod mime;
+pub mod store;
+pub mod maildir;
+pub mod mbox;
+pub mod config;
+pub mod credentials;
@@ -0,0 +1,153 @@
+use crate::error::MailError;
+use chrono::prelude::*;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static DELIVERY_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone)]
+pub struct MaildirMessage{
+    //不含 :2,标志 部分的唯一文件名，在邮件的整个生命周期内保持不变
+    pub key: String,
+    pub path: PathBuf,
+    //按字母顺序排列的标志，如 S(已读)、R(已回复)、F(标记)、T(已删除)
+    pub flags: String,
+    //位于 new 目录，即尚未被任何客户端看到
+    pub is_new: bool
+}
+
+pub struct Maildir{
+    root: PathBuf
+}
+
+impl Maildir{
+    pub fn open(root: &Path) -> Result<Maildir, MailError>{
+        for directory in ["tmp", "new", "cur"].iter(){
+            if let Err(error) = std::fs::create_dir_all(root.join(directory)){
+                return Err(MailError::StoreFile{ path: root.join(directory).display().to_string(), source: error });
+            }
+        }
+        Ok(Maildir{ root: root.to_path_buf() })
+    }
+
+    pub fn deliver(&self, raw: &[u8]) -> Result<String, MailError>{
+        //先完整写入 tmp 并同步到磁盘，再重命名到 new，其他程序不会读到写了一半的邮件
+        let key = unique_name();
+        let temporary = self.root.join("tmp").join(&key);
+        let result = std::fs::OpenOptions::new().write(true).create_new(true).open(&temporary)
+            .and_then(|mut file| { file.write_all(raw)?; file.sync_all() })
+            .and_then(|_| std::fs::rename(&temporary, self.root.join("new").join(&key)));
+        if let Err(error) = result{
+            let _ = std::fs::remove_file(&temporary);
+            return Err(MailError::StoreFile{ path: temporary.display().to_string(), source: error });
+        }
+        Ok(key)
+    }
+
+    pub fn list(&self) -> Result<Vec<MaildirMessage>, MailError>{
+        let mut messages: Vec<MaildirMessage> = Vec::new();
+        for (directory, is_new) in [("new", true), ("cur", false)].iter(){
+            let entries = match std::fs::read_dir(self.root.join(directory)){
+                Ok(entries) => entries,
+                Err(error) => { return Err(MailError::StoreFile{ path: self.root.join(directory).display().to_string(), source: error }); }
+            };
+            for entry in entries.flatten(){
+                let filename = entry.file_name().to_string_lossy().to_string();
+                //以 . 开头的文件不是邮件
+                if filename.starts_with('.'){
+                    continue;
+                }
+                let (key, flags) = split_info(&filename);
+                messages.push(MaildirMessage{ key, path: entry.path(), flags, is_new: *is_new });
+            }
+        }
+        messages.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(messages)
+    }
+
+    pub fn find(&self, key: &str) -> Result<Option<MaildirMessage>, MailError>{
+        Ok(self.list()?.into_iter().find(|message| message.key == key))
+    }
+
+    pub fn read(&self, key: &str) -> Result<Vec<u8>, MailError>{
+        let message = match self.find(key)?{
+            Some(message) => message,
+            None => { return Err(MailError::Store(format!("邮件不存在: {}", key))); }
+        };
+        match std::fs::read(&message.path){
+            Ok(raw) => Ok(raw),
+            Err(error) => Err(MailError::StoreFile{ path: message.path.display().to_string(), source: error })
+        }
+    }
+
+    pub fn set_flags(&self, key: &str, flags: &str) -> Result<(), MailError>{
+        //设置标志后邮件移动到 cur 目录，文件名为 key:2,标志
+        let message = match self.find(key)?{
+            Some(message) => message,
+            None => { return Err(MailError::Store(format!("邮件不存在: {}", key))); }
+        };
+        let mut flags: Vec<char> = flags.chars().filter(|c| c.is_ascii_alphabetic()).collect();
+        flags.sort_unstable();
+        flags.dedup();
+        let target = self.root.join("cur").join(format!("{}:2,{}", key, flags.iter().collect::<String>()));
+        if target == message.path{
+            return Ok(());
+        }
+        match std::fs::rename(&message.path, &target){
+            Ok(()) => Ok(()),
+            Err(error) => Err(MailError::StoreFile{ path: message.path.display().to_string(), source: error })
+        }
+    }
+
+    pub fn add_flag(&self, key: &str, flag: char) -> Result<(), MailError>{
+        let flags = match self.find(key)?{
+            Some(message) => message.flags,
+            None => { return Err(MailError::Store(format!("邮件不存在: {}", key))); }
+        };
+        self.set_flags(key, &format!("{}{}", flags, flag))
+    }
+}
+
+fn split_info(filename: &str) -> (String, String){
+    //cur 中的文件名形如 key:2,FS；没有 info 部分时标志为空
+    match filename.rfind(":2,"){
+        Some(position) => (filename[..position].to_string(), filename[position + 3..].to_string()),
+        None => (filename.to_string(), String::new())
+    }
+}
+
+fn unique_name() -> String{
+    //格式为 时间.M微秒P进程号Q计数.主机名，主机名中的 / 与 : 需转义
+    let now = Utc::now();
+    let counter = DELIVERY_COUNTER.fetch_add(1, Ordering::SeqCst);
+    let host = std::env::var("HOSTNAME").ok().filter(|host| !host.is_empty()).unwrap_or_else(|| String::from("localhost"));
+    let host = host.replace('/', "\\057").replace(':', "\\072");
+    format!("{}.M{}P{}Q{}.{}", now.timestamp(), now.timestamp_subsec_micros(), std::process::id(), counter, host)
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn delivers_and_flags_messages(){
+        let root = std::env::temp_dir().join(format!("mail_client_maildir_{}", std::process::id()));
+        let maildir = Maildir::open(&root).unwrap();
+        let key = maildir.deliver(b"Subject: a\r\n\r\nbody\r\n").unwrap();
+        assert!(root.join("new").join(&key).is_file());
+        assert_eq!(std::fs::read_dir(root.join("tmp")).unwrap().count(), 0);
+        let messages = maildir.list().unwrap();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].is_new && messages[0].flags.is_empty());
+        maildir.add_flag(&key, 'S').unwrap();
+        maildir.add_flag(&key, 'F').unwrap();
+        assert!(root.join("cur").join(format!("{}:2,FS", key)).is_file());
+        let message = maildir.find(&key).unwrap().unwrap();
+        assert!(!message.is_new);
+        assert_eq!(message.flags, "FS");
+        assert_eq!(maildir.read(&key).unwrap(), b"Subject: a\r\n\r\nbody\r\n");
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,30 +1,54 @@
-mod tcp;
-mod smtp;
-mod util;
-mod pop3;
-
-extern crate regex;
-extern crate chrono;
-
-use std::net::{TcpStream, Shutdown};
-use std::io::{Write, Read, BufReader, BufRead};
-use util::print_error;
-use chrono::prelude::*;
-use crate::smtp::SMTPMail;
+mod cli;
+mod ui;
+
+use mail_client::{pop3, smtp, sasl, message, mbox, config, credentials};
+use mail_client::error::MailError;
+use mail_client::smtp::SMTPMail;
+use mail_client::tcp::Security;
+use mail_client::store::{Retention, SyncConfig};
+use mail_client::config::{Config, Profile, ServerConfig};
+use mail_client::credentials::CredentialStore;
+use ui::{print_error, describe};
 //pop3:MVHEFVZAMGXLJXBR
 
 fn main() {
 
+    //带参数启动时以非交互方式执行单个命令，结果以 JSON 输出
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty(){
+        std::process::exit(cli::run(&args));
+    }
+
     let mut mail_list: Vec<SMTPMail> = Vec::new();
-    let mut smtp_host: Option<String> = None;
-    let mut pop3_host: Option<String> = None;
+    //授权码不写入配置文件，只能以主口令加密后保存到凭据文件
     let mut password: Option<String> = None;
-    let mut account: Option<String> = None;
-    let mut name: Option<String> = None;
+    let config_path = config::default_path();
+    let mut credentials: Option<CredentialStore> = match CredentialStore::open(&credentials::default_path(&config_path)){
+        Ok(store) => Some(store),
+        Err(error) => {
+            println!("凭据文件加载失败，本次运行不会读取或保存授权码，错误信息:");
+            println!("{}", describe(&error));
+            None
+        }
+    };
+    //配置文件有误时不覆盖，避免丢失用户手动编辑的内容
+    let (mut config, config_valid) = match Config::load(&config_path){
+        Ok(config) => (config, true),
+        Err(error) => {
+            println!("配置文件加载失败，本次运行不会写入配置文件，请修正后重新启动，错误信息:");
+            println!("{}", describe(&error));
+            (Config::default(), false)
+        }
+    };
+    let mut profile_name: Option<String> = config.default_profile.clone();
+    let mut profile: Profile = profile_name.as_ref().and_then(|name| config.profiles.get(name).cloned()).unwrap_or_default();
+    if let Some(name) = &profile_name{
+        println!("已加载配置 [{}] ({})", name, config_path.display());
+    }
 
     loop {
         println!("正在使用: 邮箱客户端主菜单，使用 -help 获得帮助");
-        let _input = util::input();
+        let _input = ui::input();
         let mut __input = _input.split_whitespace();
         if let Some(head) = __input.next(){
             match head{
@@ -33,25 +57,57 @@ fn main() {
                     println!("-smtp  进入smtp发件客户端");
                     println!("-editor  进入邮件编辑器");
                     println!("-list  查看已保存邮件列表");
-                    println!("-config  配置用户信息");
+                    println!("-mbox [path:mbox 文件路径] <order number(int):邮件序号>  浏览 mbox 文件中的邮件列表或指定邮件");
+                    println!("-import [path:mbox 文件路径]  将 mbox 文件中的邮件导入已保存邮件列表，用于重新发送");
+                    println!("-export [path:mbox 文件路径]  将已保存邮件列表导出到 mbox 文件");
+                    println!("-config  配置用户信息并保存到配置文件");
+                    println!("-profile <name:配置名称>  切换到指定配置，不带参数时列出所有配置");
                     println!("-quit  退出邮件客户端");
                 }
                 "-pop3" => {
-                    if let (Some(n), Some(acc), Some(pass), Some(p3h)) = (&name, &account, &password, &pop3_host){
-                        pop3::run(n.clone(), acc.clone(), pass.clone(), p3h.clone());
+                    if let (Some(_), Some(_), Some(_)) = (&profile.hostname, &profile.account, &profile.pop3){
+                        let pass = input_password(&mut password, &mut credentials, &profile_name);
+                        ui::pop3::run(&profile, pass);
                     }else{
                         println!("配置信息不完整，请配置后重试");
                     }
                 }
                 "-smtp" => {
-                    if let (Some(n), Some(acc), Some(pass), Some(sh)) = (&name, &account, &password, &smtp_host){
-                        smtp::run(n.clone(), acc.clone(), pass.clone(), sh.clone(), &mut mail_list);
+                    if let (Some(_), Some(_), Some(_)) = (&profile.hostname, &profile.account, &profile.smtp){
+                        let pass = input_password(&mut password, &mut credentials, &profile_name);
+                        ui::smtp::run(&profile, pass, &mut mail_list);
                     }else{
                         println!("配置信息不完整，请配置后重试");
                     }
                 }
+                "-profile" => {
+                    match __input.next(){
+                        None => {
+                            if config.profiles.is_empty(){
+                                println!("配置文件中没有保存的配置，可使用 -config 创建"); continue;
+                            }
+                            println!("---已保存的配置 ({})---", config_path.display());
+                            for (name, saved) in &config.profiles{
+                                let current = if profile_name.as_ref() == Some(name) { " (当前)" } else { "" };
+                                let default = if config.default_profile.as_ref() == Some(name) { " (默认)" } else { "" };
+                                println!("[{}]{}{} {}", name, current, default, saved.account.clone().unwrap_or_else(||String::from("No info")));
+                            }
+                            println!();
+                        }
+                        Some(name) => {
+                            if let Some(saved) = config.profiles.get(name){
+                                profile = saved.clone();
+                                profile_name.replace(name.to_string());
+                                password = None;
+                                println!("已切换到配置 [{}]", name);
+                            }else{
+                                println!("未找到配置 [{}]，可使用 -profile 查看所有配置", name);
+                            }
+                        }
+                    }
+                }
                 "-editor" => {
-                    util::MailEditor::run(&mut mail_list);
+                    ui::editor::MailEditor::run(&mut mail_list);
                 }
                 "-list" => {
                     if mail_list.len() == 0{
@@ -69,33 +125,84 @@ fn main() {
                     }
                     println!();
                 }
+                "-mbox" => {
+                    match (__input.next(), __input.next().map(|param| param.trim().parse::<usize>())){
+                        (Some(path), None) => { ui::pop3::browse_mbox(path, None); }
+                        (Some(path), Some(Ok(index))) => { ui::pop3::browse_mbox(path, Some(index)); }
+                        _ => { print_error(); }
+                    }
+                }
+                "-import" => {
+                    match __input.next(){
+                        Some(path) => {
+                            match pop3::import_mbox(path){
+                                Ok(mut import) => {
+                                    for (index, error) in &import.skipped{
+                                        println!("mbox [{}] 号邮件无法解析，已跳过，错误信息: [{}]", index, describe(error));
+                                    }
+                                    println!("已从 {} 导入 [{}] 封邮件，可使用 -list 查看", path, import.drafts.len());
+                                    mail_list.append(&mut import.drafts);
+                                }
+                                Err(error) => { println!("导入 mbox 文件失败，错误信息: [{}]", describe(&error)); }
+                            }
+                        }
+                        None => { print_error(); }
+                    }
+                }
+                "-export" => {
+                    match __input.next(){
+                        Some(path) => { export_mail_list(&mail_list, path); }
+                        None => { print_error(); }
+                    }
+                }
                 "-config" => {
-                    println!("---当前配置信息---");
-                    println!("pop3: {}", pop3_host.clone().unwrap_or_else(||String::from("No info")));
-                    println!("smtp: {}", smtp_host.clone().unwrap_or_else(||String::from("No info")));
-                    println!("account: {}", account.clone().unwrap_or_else(||String::from("No info")));
-                    println!("password: {}", password.clone().unwrap_or_else(||String::from("No info")));
-                    println!("name: {}", name.clone().unwrap_or_else(||String::from("No info")));
+                    let no_info = ||String::from("No info");
+                    let sync_config = profile.sync_config();
+                    println!("---当前配置信息 [{}]---", profile_name.clone().unwrap_or_else(no_info));
+                    for (protocol, server, default) in [("pop3", &profile.pop3, Security::Tls), ("smtp", &profile.smtp, Security::StartTls)].iter(){
+                        match server{
+                            Some(server) => {
+                                let security = server.security(default.clone());
+                                let default_port = if *protocol == "pop3" { pop3::default_port(&security) } else { smtp::default_port(&security) };
+                                println!("{}: {}", protocol, server.host);
+                                println!("{} port: {} ({})", protocol, server.port.unwrap_or(default_port), security.name());
+                                println!("{} auth: {}", protocol, server.auth.clone().unwrap_or_else(||String::from("auto")));
+                                println!("{} fingerprint: {}", protocol, server.fingerprint.clone().unwrap_or_else(no_info));
+                            }
+                            None => { println!("{}: No info", protocol); }
+                        }
+                    }
+                    println!("pop3 store: {} ({})", sync_config.store_dir, sync_config.retention.name());
+                    println!("ca roots: {:?}", profile.ca_roots);
+                    println!("account: {}", profile.account.clone().unwrap_or_else(no_info));
+                    println!("password: {}", if password.is_some() { "******" } else { "No info" });
+                    println!("name: {}", profile.hostname.clone().unwrap_or_else(no_info));
                     println!("已配置的用户信息将会被覆盖，确认要进行配置吗？(yes/no)");
-                    let _input = util::input();
+                    let _input = ui::input();
                     if _input == "yes"{
-                        println!("请输入 pop3 主机地址");
-                        pop3_host.replace(util::input());
-                        println!("请输入 smtp 主机地址");
-                        smtp_host.replace(util::input());
+                        profile.pop3.replace(input_server("pop3", Security::Tls, pop3::default_port));
+                        println!("请输入 本地邮件存储目录 (留空使用 {})", SyncConfig::new().store_dir);
+                        let store_dir = ui::input();
+                        profile.store_dir = if store_dir.is_empty() { None } else { Some(store_dir) };
+                        profile.retention.replace(input_retention().setting());
+                        profile.smtp.replace(input_server("smtp", Security::StartTls, smtp::default_port));
+                        println!("请输入 CA 根证书路径(PEM 格式，多个路径以空格分隔，留空使用系统证书)");
+                        profile.ca_roots = ui::input().split_whitespace().map(|path| path.to_string()).collect();
                         println!("请输入 邮箱账号");
-                        account.replace(util::input());
-                        println!("请输入 授权码");
-                        password.replace(util::input());
+                        profile.account.replace(ui::input());
+                        println!("请输入 授权码 (输入时不回显，不会写入配置文件)");
+                        password.replace(ui::input_secret());
                         println!("请输入 本地主机名");
-                        name.replace(util::input());
+                        profile.hostname.replace(ui::input());
                         println!("配置完成");
+                        save_profile(&mut config, config_valid, &config_path, &mut profile_name, &profile);
+                        save_password(&mut credentials, &profile_name, &password.clone().unwrap_or_default());
                         println!();
                     }else if _input == "no"{ continue; } else { print_error(); continue;}
                 }
                 "-quit" => {
                     println!("确定要退出邮箱客户端吗？(yes/no)");
-                    let _input = util::input();
+                    let _input = ui::input();
                     if _input == "yes"{ break; }else if _input == "no"{ continue; } else { print_error(); continue;}
                 }
                 _ => { print_error(); }
@@ -104,3 +211,171 @@ fn main() {
     }
 
 }
+
+fn export_mail_list(mail_list: &[SMTPMail], path: &str){
+    //不完整的邮件无法生成邮件内容，跳过
+    let mut messages: Vec<mbox::MboxMessage> = Vec::new();
+    for (index, mail) in mail_list.iter().enumerate(){
+        if let Err(error) = mail.validate(){
+            println!("[{}] 号邮件不完整 ({})，已跳过", index + 1, error); continue;
+        }
+        match message::MessageBuilder::from_mail(mail).render(){
+            Ok(raw) => { messages.push(mbox::MboxMessage::new(raw.as_bytes())); }
+            Err(error) => { println!("[{}] 号邮件生成失败，已跳过，错误信息: [{}]", index + 1, describe(&error)); }
+        }
+    }
+    match mbox::append(std::path::Path::new(path), &messages){
+        Ok(()) => { println!("已将 [{}] 封邮件导出到 {}", messages.len(), path); }
+        Err(error) => { println!("导出邮件失败，错误信息: [{}]", describe(&error)); }
+    }
+}
+
+fn input_password(password: &mut Option<String>, credentials: &mut Option<CredentialStore>, profile_name: &Option<String>) -> String{
+    //首次连接时优先从凭据文件读取，没有保存时输入，之后在本次运行中复用
+    if password.is_none(){
+        if let (Some(store), Some(name)) = (credentials.as_mut(), profile_name){
+            if store.contains(name) && unlock_credentials(store){
+                match store.get(name){
+                    Ok(saved) => { *password = saved; }
+                    Err(error) => { print_credential_error("读取授权码失败", error); }
+                }
+            }
+        }
+    }
+    if password.is_none(){
+        println!("请输入 授权码 (输入时不回显)");
+        let secret = ui::input_secret();
+        save_password(credentials, profile_name, &secret);
+        password.replace(secret);
+    }
+    password.clone().unwrap_or_default()
+}
+
+fn save_password(credentials: &mut Option<CredentialStore>, profile_name: &Option<String>, secret: &str){
+    //只有已保存的配置才能关联授权码
+    let (store, name) = match (credentials.as_mut(), profile_name){
+        (Some(store), Some(name)) if !secret.is_empty() => (store, name),
+        _ => { return; }
+    };
+    println!("是否使用主口令加密保存配置 [{}] 的授权码？(yes/no)", name);
+    if ui::input() != "yes" || !unlock_credentials(store){ return; }
+    match store.set(name, secret){
+        Ok(()) => { println!("授权码已加密保存"); }
+        Err(error) => { print_credential_error("保存授权码失败", error); }
+    }
+}
+
+fn unlock_credentials(store: &mut CredentialStore) -> bool{
+    //每次运行只需解锁一次
+    if store.is_unlocked(){ return true; }
+    if store.is_new(){
+        println!("请设置 主口令 (用于加密保存授权码，输入时不回显)");
+        let passphrase = ui::input_secret();
+        println!("请再次输入 主口令");
+        if passphrase.is_empty() || ui::input_secret() != passphrase{
+            println!("主口令为空或两次输入不一致"); return false;
+        }
+        return match store.unlock(&passphrase){
+            Ok(()) => true,
+            Err(error) => { print_credential_error("设置主口令失败", error); false }
+        };
+    }
+    for _ in 0..3{
+        println!("请输入 主口令 (输入时不回显)");
+        match store.unlock(&ui::input_secret()){
+            Ok(()) => { return true; }
+            Err(error) => { print_credential_error("解锁失败", error); }
+        }
+    }
+    false
+}
+
+fn print_credential_error(action: &str, error: MailError){
+    match error{
+        MailError::Credential(message) => { println!("{}，错误信息: {}", action, message); }
+        error => { println!("{}，错误信息: {}", action, describe(&error)); }
+    }
+}
+
+fn input_server(protocol: &str, default: Security, default_port: fn(&Security) -> u16) -> ServerConfig{
+    println!("请输入 {} 主机地址", protocol);
+    let host = ui::input();
+    let security = input_security(protocol, default);
+    let port = input_port(protocol, default_port(&security));
+    ServerConfig{
+        host,
+        port: Some(port),
+        security: Some(security.name().to_string()),
+        auth: input_auth_mechanism(protocol),
+        fingerprint: input_fingerprint(protocol),
+        allow_plaintext_auth: None
+    }
+}
+
+fn save_profile(config: &mut Config, config_valid: bool, path: &std::path::Path, profile_name: &mut Option<String>, profile: &Profile){
+    if !config_valid{
+        println!("配置文件存在错误，本次配置不会保存"); return;
+    }
+    let current = profile_name.clone().unwrap_or_else(|| String::from("default"));
+    println!("请输入 配置名称 (留空使用 {})", current);
+    let _input = ui::input();
+    let name = if _input.trim().is_empty() { current } else { _input.trim().to_string() };
+    config.profiles.insert(name.clone(), profile.clone());
+    if config.default_profile.is_none(){
+        config.default_profile.replace(name.clone());
+    }
+    match config.save(path){
+        Ok(()) => {
+            println!("配置 [{}] 已保存到 {}", name, path.display());
+            profile_name.replace(name);
+        }
+        Err(MailError::Config(message)) => { println!("配置保存失败，错误信息:\n{}", message); }
+        Err(error) => { println!("配置保存失败，错误信息: {}", describe(&error)); }
+    }
+}
+
+fn input_security(protocol: &str, default: Security) -> Security{
+    loop{
+        println!("请输入 {} 加密方式 (plain/starttls/tls，留空使用 {})", protocol, default.name());
+        let _input = ui::input();
+        if _input.is_empty(){ return default; }
+        if let Some(security) = Security::parse(&_input){ return security; }
+        print_error();
+    }
+}
+
+fn input_port(protocol: &str, default: u16) -> u16{
+    loop{
+        println!("请输入 {} 端口 (留空使用默认端口 {})", protocol, default);
+        let _input = ui::input();
+        if _input.is_empty(){ return default; }
+        if let Ok(port) = _input.trim().parse::<u16>(){ return port; }
+        print_error();
+    }
+}
+
+fn input_auth_mechanism(protocol: &str) -> Option<String>{
+    loop{
+        println!("请输入 {} 验证方式 ({}，留空则自动选择)", protocol, sasl::SUPPORTED.join("/").to_lowercase());
+        let _input = ui::input();
+        if _input.is_empty() || _input == "auto"{ return None; }
+        if sasl::is_supported(&_input){ return Some(_input.to_uppercase()); }
+        print_error();
+    }
+}
+
+fn input_retention() -> Retention{
+    loop{
+        println!("请输入 服务器邮件保留策略 (keep: 保留在服务器上 / N: 下载 N 天后从服务器删除，0 表示下载后立即删除，留空使用 keep)");
+        let _input = ui::input();
+        if _input.is_empty(){ return Retention::LeaveOnServer; }
+        if let Some(retention) = Retention::parse(&_input){ return retention; }
+        print_error();
+    }
+}
+
+fn input_fingerprint(protocol: &str) -> Option<String>{
+    println!("请输入 {} 服务器证书的 SHA-256 指纹 (留空则不固定证书)", protocol);
+    let _input = ui::input();
+    if _input.is_empty(){ None }else{ Some(_input) }
+}
@@ -0,0 +1,164 @@
+use crate::error::MailError;
+use crate::mime::MimePart;
+use crate::message::Address;
+use chrono::prelude::*;
+use std::io::Write;
+use std::path::Path;
+
+#[derive(Debug, Clone)]
+pub struct MboxMessage{
+    //分隔行 "From 发件人 时间" 中的发件人地址
+    pub sender: String,
+    //分隔行中的 asctime 格式时间，如 "Sat Oct 18 05:08:23 2026"
+    pub date: String,
+    //以 CRLF 换行的原始邮件，已去除 mboxrd 引用
+    pub raw: Vec<u8>
+}
+
+impl MboxMessage{
+    pub fn new(raw: &[u8]) -> MboxMessage{
+        //分隔行的发件人优先取 Return-Path，其次取 From；时间取 Date 头，无法解析时使用当前时间
+        let part = MimePart::parse(raw);
+        let sender = part.header("Return-Path").or_else(|| part.header("From"))
+            .map(|value| Address::parse(value.split(',').next().unwrap_or_default()).email)
+            .filter(|email| !email.is_empty() && !email.contains(char::is_whitespace))
+            .unwrap_or_else(|| String::from("MAILER-DAEMON"));
+        let date = part.header("Date")
+            .and_then(|date| DateTime::parse_from_rfc2822(date.trim()).ok())
+            .map(|date| date.with_timezone(&Utc))
+            .unwrap_or_else(Utc::now);
+        MboxMessage{
+            sender,
+            date: date.format("%a %b %e %H:%M:%S %Y").to_string(),
+            raw: raw.to_vec()
+        }
+    }
+
+    pub fn separator_line(&self) -> String{
+        format!("From {} {}", self.sender, self.date)
+    }
+}
+
+pub fn parse(data: &[u8]) -> Vec<MboxMessage>{
+    //mboxrd: 以 "From " 开头的行为分隔行，正文中的 ">*From " 行在写入时多加了一个 >，读取时去掉一个
+    let mut messages: Vec<MboxMessage> = Vec::new();
+    let mut current: Option<(String, String, Vec<u8>)> = None;
+    for line in split_lines(data){
+        if line.starts_with(b"From "){
+            if let Some(message) = current.take(){
+                messages.push(finish_message(message));
+            }
+            let header = String::from_utf8_lossy(&line[5..]).trim().to_string();
+            let (sender, date) = match header.find(' '){
+                Some(space) => (header[..space].to_string(), header[space + 1..].trim().to_string()),
+                None => (header, String::new())
+            };
+            current.replace((sender, date, Vec::new()));
+            continue;
+        }
+        //第一个分隔行之前的内容不属于任何邮件
+        if let Some((_, _, raw)) = current.as_mut(){
+            let quote = line.iter().take_while(|c| **c == b'>').count();
+            if quote > 0 && line[quote..].starts_with(b"From "){
+                raw.extend_from_slice(&line[1..]);
+            }else{
+                raw.extend_from_slice(line);
+            }
+            raw.extend_from_slice(b"\r\n");
+        }
+    }
+    if let Some(message) = current.take(){
+        messages.push(finish_message(message));
+    }
+    messages
+}
+
+fn split_lines(data: &[u8]) -> Vec<&[u8]>{
+    //同时支持 LF 与 CRLF 换行，返回不含换行符的各行
+    let mut lines: Vec<&[u8]> = data.split(|c| *c == b'\n').map(|line| line.strip_suffix(b"\r").unwrap_or(line)).collect();
+    if data.ends_with(b"\n"){
+        lines.pop();
+    }
+    lines
+}
+
+fn finish_message((sender, date, mut raw): (String, String, Vec<u8>)) -> MboxMessage{
+    //写入时每封邮件后追加了一个空行作为分隔，读取时去掉
+    if raw.ends_with(b"\r\n\r\n"){
+        raw.truncate(raw.len() - 2);
+    }
+    MboxMessage{ sender, date, raw }
+}
+
+pub fn render(message: &MboxMessage) -> Vec<u8>{
+    //mbox 文件使用 LF 换行，正文中的 ">*From " 行加一个 > 引用，邮件末尾补一个空行
+    let mut output: Vec<u8> = Vec::new();
+    output.extend_from_slice(message.separator_line().as_bytes());
+    output.push(b'\n');
+    for line in split_lines(&message.raw){
+        let quote = line.iter().take_while(|c| **c == b'>').count();
+        if line[quote..].starts_with(b"From "){
+            output.push(b'>');
+        }
+        output.extend_from_slice(line);
+        output.push(b'\n');
+    }
+    output.push(b'\n');
+    output
+}
+
+pub fn read(path: &Path) -> Result<Vec<MboxMessage>, MailError>{
+    match std::fs::read(path){
+        Ok(data) => Ok(parse(&data)),
+        Err(error) => Err(MailError::MboxFile{ path: path.display().to_string(), source: error })
+    }
+}
+
+pub fn append(path: &Path, messages: &[MboxMessage]) -> Result<(), MailError>{
+    //追加到已有文件末尾，文件不存在时创建
+    let mut data: Vec<u8> = Vec::new();
+    for message in messages{
+        data.append(&mut render(message));
+    }
+    let result = std::fs::OpenOptions::new().create(true).append(true).open(path)
+        .and_then(|mut file| { file.write_all(&data)?; file.sync_all() });
+    if let Err(error) = result{
+        return Err(MailError::MboxFile{ path: path.display().to_string(), source: error });
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn round_trips_mboxrd_quoting(){
+        let raw = b"From: \"Alice\" <alice@example.com>\r\nDate: Sat, 18 Oct 2025 13:08:23 +0800\r\nSubject: hi\r\n\r\nFrom here on\r\n>From quoted\r\n\r\n";
+        let message = MboxMessage::new(raw);
+        assert_eq!(message.separator_line(), "From alice@example.com Sat Oct 18 05:08:23 2025");
+        let rendered = render(&message);
+        assert!(rendered.windows(15).any(|window| window == b"\n>From here on\n"));
+        assert!(rendered.windows(15).any(|window| window == b"\n>>From quoted\n"));
+        let mut data = rendered.clone();
+        data.extend_from_slice(&render(&MboxMessage::new(b"Subject: second\r\n\r\nbody\r\n")));
+        let messages = parse(&data);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].raw, raw.to_vec());
+        assert_eq!(messages[0].sender, "alice@example.com");
+        assert_eq!(messages[0].date, "Sat Oct 18 05:08:23 2025");
+        assert_eq!(messages[1].sender, "MAILER-DAEMON");
+        assert_eq!(messages[1].raw, b"Subject: second\r\n\r\nbody\r\n".to_vec());
+    }
+
+    #[test]
+    fn reads_lf_mbox_files(){
+        let data = b"preamble\nFrom bob@example.com Thu Jan  1 00:00:00 1970\nSubject: a\n\n>>From b\n\nFrom carol@example.com Thu Jan  1 00:00:01 1970\nSubject: c\n\nc\n";
+        let messages = parse(data);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].date, "Thu Jan  1 00:00:00 1970");
+        assert_eq!(messages[0].raw, b"Subject: a\r\n\r\n>From b\r\n".to_vec());
+        assert_eq!(messages[1].raw, b"Subject: c\r\n\r\nc\r\n".to_vec());
+    }
+}
@@ -0,0 +1,758 @@
+use crate::smtp::{SMTPMail, Attachment};
+use crate::codec;
+use crate::error::{MailError, MessageError};
+use chrono::prelude::*;
+use regex::Regex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+//RFC 5322 2.1.1: 每行建议不超过 78 个字符，且不得超过 998 个字符(均不含 CRLF)
+pub const SOFT_LINE_LIMIT: usize = 78;
+pub const HARD_LINE_LIMIT: usize = 998;
+
+static MESSAGE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Address{
+    pub name: Option<String>,
+    pub email: String
+}
+
+impl Address{
+    pub fn parse(input: &str) -> Address{
+        //支持 "显示名" <地址>、显示名 <地址>、<地址> 与 地址 四种形式
+        let input = input.trim();
+        if let (Some(start), true) = (input.rfind('<'), input.ends_with('>')){
+            let email = input[start + 1..input.len() - 1].trim().to_string();
+            let name = input[..start].trim();
+            let name = if name.len() >= 2 && name.starts_with('"') && name.ends_with('"'){
+                name[1..name.len() - 1].replace("\\\"", "\"").replace("\\\\", "\\")
+            }else{
+                name.to_string()
+            };
+            return Address{
+                name: if name.is_empty() { None } else { Some(name) },
+                email
+            };
+        }
+        Address{
+            name: None,
+            email: input.to_string()
+        }
+    }
+
+    pub fn to_header(&self) -> String{
+        match &self.name{
+            Some(name) if codec::needs_encoding(name) => format!("{} <{}>", codec::encode_header(name), self.email),
+            Some(name) => format!("{} <{}>", quote_phrase(name), self.email),
+            None => self.email.clone()
+        }
+    }
+}
+
+pub struct MessageBuilder{
+    from: Option<Address>,
+    to: Vec<Address>,
+    cc: Vec<Address>,
+    subject: Option<String>,
+    date: Option<DateTime<FixedOffset>>,
+    message_id: Option<String>,
+    domain: String,
+    boundary: Option<String>,
+    eight_bit: bool,
+    body: Vec<String>,
+    html: Option<String>,
+    attachments: Vec<Attachment>,
+    inline_images: Vec<Attachment>
+}
+
+struct Part{
+    headers: Vec<(String, String)>,
+    body: PartBody
+}
+
+enum PartBody{
+    Lines(Vec<String>),
+    Multipart(String, Vec<Part>)
+}
+
+impl Default for MessageBuilder{
+    fn default() -> MessageBuilder{
+        MessageBuilder::new()
+    }
+}
+
+impl MessageBuilder{
+    pub fn new() -> MessageBuilder{
+        MessageBuilder{
+            from: None,
+            to: Vec::new(),
+            cc: Vec::new(),
+            subject: None,
+            date: None,
+            message_id: None,
+            domain: String::from("localhost"),
+            boundary: None,
+            eight_bit: false,
+            body: Vec::new(),
+            html: None,
+            attachments: Vec::new(),
+            inline_images: Vec::new()
+        }
+    }
+
+    pub fn from_mail(mail: &SMTPMail) -> MessageBuilder{
+        //密送地址只用于 rcpt to，不写入邮件头
+        let mut builder = MessageBuilder::new()
+            .to(mail.to.iter().map(|address| Address::parse(address)).collect())
+            .cc(mail.cc.iter().map(|address| Address::parse(address)).collect());
+        if let Some(from) = &mail.from{
+            builder = builder.from(Address::parse(from));
+        }
+        if let Some(subject) = &mail.subject{
+            builder = builder.subject(subject.clone());
+        }
+        if let Some(content) = &mail.content{
+            builder = builder.body(content.clone());
+        }
+        if let Some(html) = &mail.html{
+            builder = builder.html(html.clone());
+        }
+        builder.attachments(mail.attachments.clone()).inline_images(mail.inline_images.clone())
+    }
+
+    pub fn from(mut self, from: Address) -> MessageBuilder{
+        self.from.replace(from);
+        self
+    }
+
+    pub fn to(mut self, to: Vec<Address>) -> MessageBuilder{
+        self.to = to;
+        self
+    }
+
+    pub fn cc(mut self, cc: Vec<Address>) -> MessageBuilder{
+        self.cc = cc;
+        self
+    }
+
+    pub fn subject(mut self, subject: String) -> MessageBuilder{
+        self.subject.replace(subject);
+        self
+    }
+
+    pub fn date(mut self, date: DateTime<FixedOffset>) -> MessageBuilder{
+        self.date.replace(date);
+        self
+    }
+
+    pub fn message_id(mut self, message_id: String) -> MessageBuilder{
+        self.message_id.replace(message_id);
+        self
+    }
+
+    //生成 Message-ID 时使用的域名，一般为本地主机名
+    pub fn domain(mut self, domain: String) -> MessageBuilder{
+        self.domain = domain;
+        self
+    }
+
+    pub fn body(mut self, body: Vec<String>) -> MessageBuilder{
+        self.body = body;
+        self
+    }
+
+    //HTML 正文，body 为空时由 html_to_text 生成纯文本备选内容
+    pub fn html(mut self, html: String) -> MessageBuilder{
+        self.html.replace(html);
+        self
+    }
+
+    pub fn attachments(mut self, attachments: Vec<Attachment>) -> MessageBuilder{
+        self.attachments = attachments;
+        self
+    }
+
+    //HTML 正文中以 cid: 引用的内嵌资源，需设置 content_id
+    pub fn inline_images(mut self, inline_images: Vec<Attachment>) -> MessageBuilder{
+        self.inline_images = inline_images;
+        self
+    }
+
+    //服务器支持 8BITMIME 时，非 ASCII 文本可以不经编码以 8bit 发送
+    pub fn eight_bit(mut self, eight_bit: bool) -> MessageBuilder{
+        self.eight_bit = eight_bit;
+        self
+    }
+
+    //multipart 分隔符的前缀，未设置时随机生成
+    pub fn boundary(mut self, boundary: String) -> MessageBuilder{
+        self.boundary.replace(boundary);
+        self
+    }
+
+    pub fn build(&self) -> Result<Vec<String>, MailError>{
+        //返回不含 CRLF 的各行，头部与正文之间以一个空行分隔
+        let mut lines: Vec<String> = Vec::new();
+        let date = self.date.unwrap_or_else(||{
+            let now = Local::now();
+            now.with_timezone(now.offset())
+        });
+        lines.append(&mut fold_header("Date", &date.format("%a, %d %b %Y %H:%M:%S %z").to_string())?);
+        if let Some(from) = &self.from{
+            lines.append(&mut fold_header("From", &from.to_header())?);
+        }
+        if !self.to.is_empty(){
+            lines.append(&mut fold_header("To", &format_address_list(&self.to))?);
+        }
+        if !self.cc.is_empty(){
+            lines.append(&mut fold_header("Cc", &format_address_list(&self.cc))?);
+        }
+        if let Some(subject) = &self.subject{
+            lines.append(&mut fold_header("Subject", &codec::encode_header(subject))?);
+        }
+        let message_id = self.message_id.clone().unwrap_or_else(|| generate_message_id(&self.domain));
+        lines.append(&mut fold_header("Message-ID", &message_id)?);
+        lines.push(String::from("MIME-Version: 1.0"));
+        let part = self.build_part()?;
+        render_part(&part, &mut lines)?;
+        Ok(lines)
+    }
+
+    pub fn render(&self) -> Result<String, MailError>{
+        let mut message = self.build()?.join("\r\n");
+        message.push_str("\r\n");
+        Ok(message)
+    }
+
+    fn build_part(&self) -> Result<Part, MailError>{
+        //结构: mixed(alternative(text/plain, related(text/html, 内嵌资源...)), 附件...)，缺少的层级省略
+        let mut attachments: Vec<&Attachment> = self.attachments.iter().collect();
+        let body = match &self.html{
+            Some(html) => {
+                let depth = if attachments.is_empty() { 0 } else { 1 };
+                let plain = if self.body.is_empty() { html_to_text(html) } else { self.body.clone() };
+                let mut html_part = text_part("html", std::slice::from_ref(html), self.eight_bit);
+                if !self.inline_images.is_empty(){
+                    let mut parts = vec![html_part];
+                    for image in &self.inline_images{
+                        parts.push(attachment_part(image)?);
+                    }
+                    html_part = self.multipart("related", parts, depth + 1);
+                    //RFC 2387 3.1: type 参数为根部分的类型
+                    html_part.headers[0].1.push_str("; type=\"text/html\"");
+                }
+                self.multipart("alternative", vec![text_part("plain", &plain, self.eight_bit), html_part], depth)
+            }
+            None => {
+                //没有 HTML 正文时内嵌资源无处引用，作为普通附件发送
+                attachments.extend(self.inline_images.iter());
+                text_part("plain", &self.body, self.eight_bit)
+            }
+        };
+        if attachments.is_empty(){
+            return Ok(body);
+        }
+        let mut parts = vec![body];
+        for attachment in attachments{
+            parts.push(attachment_part(attachment)?);
+        }
+        Ok(self.multipart("mixed", parts, 0))
+    }
+
+    fn multipart(&self, subtype: &str, parts: Vec<Part>, depth: usize) -> Part{
+        let boundary = match &self.boundary{
+            Some(prefix) => format!("{}_{}", prefix, depth),
+            None => generate_boundary(depth)
+        };
+        Part{
+            headers: vec![(String::from("Content-Type"), format!("multipart/{}; boundary=\"{}\"", subtype, boundary))],
+            body: PartBody::Multipart(boundary, parts)
+        }
+    }
+}
+
+fn text_part(subtype: &str, body: &[String], eight_bit: bool) -> Part{
+    let mut lines: Vec<String> = Vec::new();
+    for line in body{
+        for line in line.split('\n'){
+            lines.push(line.trim_end_matches('\r').to_string());
+        }
+    }
+    let (encoding, lines) = encode_text(lines, eight_bit);
+    Part{
+        headers: vec![
+            (String::from("Content-Type"), format!("text/{}; charset=utf-8", subtype)),
+            (String::from("Content-Transfer-Encoding"), String::from(encoding)),
+        ],
+        body: PartBody::Lines(lines)
+    }
+}
+
+fn encode_text(lines: Vec<String>, eight_bit: bool) -> (&'static str, Vec<String>){
+    //7bit/8bit 要求每行不超过 998 字节且不含 NUL，否则在 quoted-printable 与 base64 中选择编码后较短的一种
+    let plain = lines.iter().all(|line| line.len() <= HARD_LINE_LIMIT && !line.contains('\0'));
+    if plain && lines.iter().all(|line| line.is_ascii()){
+        return ("7bit", lines);
+    }
+    if plain && eight_bit{
+        return ("8bit", lines);
+    }
+    let quoted: Vec<String> = lines.iter().flat_map(|line| codec::qp_encode_line(line.as_bytes())).collect();
+    let base64 = base64_lines(lines.join("\r\n").as_bytes());
+    let length = |lines: &Vec<String>| lines.iter().map(|line| line.len() + 2).sum::<usize>();
+    if length(&quoted) <= length(&base64){
+        ("quoted-printable", quoted)
+    }else{
+        ("base64", base64)
+    }
+}
+
+fn attachment_part(attachment: &Attachment) -> Result<Part, MailError>{
+    let data = match std::fs::read(&attachment.path){
+        Ok(data) => data,
+        Err(error) => { return Err(MessageError::File{ path: attachment.path.clone(), source: error }.into()); }
+    };
+    let disposition = if attachment.content_id.is_some() { "inline" } else { "attachment" };
+    let mut headers = vec![
+        (String::from("Content-Type"), format!("{}; {}", attachment.mime_type, format_parameter("name", &attachment.filename, false))),
+        (String::from("Content-Disposition"), format!("{}; {}", disposition, format_parameter("filename", &attachment.filename, true))),
+    ];
+    if let Some(content_id) = &attachment.content_id{
+        headers.push((String::from("Content-ID"), format!("<{}>", content_id)));
+    }
+    headers.push((String::from("Content-Transfer-Encoding"), String::from("base64")));
+    Ok(Part{
+        headers,
+        body: PartBody::Lines(base64_lines(&data))
+    })
+}
+
+fn render_part(part: &Part, lines: &mut Vec<String>) -> Result<(), MessageError>{
+    for (name, value) in &part.headers{
+        lines.append(&mut fold_header(name, value)?);
+    }
+    lines.push(String::new());
+    match &part.body{
+        PartBody::Lines(body) => {
+            lines.extend(body.iter().cloned());
+        }
+        PartBody::Multipart(boundary, parts) => {
+            //分隔符前的 CRLF 属于分隔符本身(RFC 2046 5.1.1)，因此各部分之间不再额外插入空行
+            lines.push(String::from("This is a multi-part message in MIME format."));
+            for child in parts{
+                lines.push(format!("--{}", boundary));
+                render_part(child, lines)?;
+            }
+            lines.push(format!("--{}--", boundary));
+        }
+    }
+    Ok(())
+}
+
+pub fn html_to_text(html: &str) -> Vec<String>{
+    //去除标签、注释与 script/style 等不可见内容，块级元素换行，链接地址附在文字之后
+    let mut text = String::new();
+    let mut hidden: Option<String> = None;
+    let mut link: Option<(String, usize)> = None;
+    let mut rest = html;
+    while let Some(start) = rest.find('<'){
+        if hidden.is_none(){
+            push_html_text(&mut text, &rest[..start]);
+        }
+        rest = &rest[start..];
+        if rest.starts_with("<!--"){
+            rest = match rest.find("-->"){
+                Some(end) => &rest[end + 3..],
+                None => ""
+            };
+            continue;
+        }
+        let end = match rest.find('>'){
+            Some(end) => end,
+            None => { rest = ""; break; }
+        };
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+        let closing = tag.starts_with('/');
+        let name = tag.trim_start_matches('/').chars().take_while(|c| c.is_ascii_alphanumeric()).collect::<String>().to_lowercase();
+        if let Some(hidden_name) = &hidden{
+            if closing && name == *hidden_name{
+                hidden = None;
+            }
+            continue;
+        }
+        match name.as_str(){
+            "head" | "script" | "style" | "title" if !closing => { hidden.replace(name); }
+            "br" => text.push('\n'),
+            "p" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "table" | "ul" | "ol" | "blockquote" | "pre" | "hr" => text.push_str("\n\n"),
+            "div" | "tr" => text.push('\n'),
+            "li" if !closing => text.push_str("\n- "),
+            "td" | "th" if closing => text.push(' '),
+            "img" => {
+                if let Some(alt) = html_attribute(tag, "alt"){
+                    if !alt.is_empty(){
+                        text.push_str(&format!("[{}]", alt));
+                    }
+                }
+            }
+            "a" if !closing => { link = html_attribute(tag, "href").map(|href| (href, text.len())); }
+            "a" => {
+                if let Some((href, position)) = link.take(){
+                    //锚点链接与文字即为地址的链接不再重复
+                    if !href.starts_with('#') && text[position..].trim() != href.trim_start_matches("mailto:"){
+                        text.push_str(&format!(" ({})", href));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if hidden.is_none(){
+        push_html_text(&mut text, rest);
+    }
+    //合并多余的空白与空行
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.split('\n'){
+        let line = line.split_whitespace().collect::<Vec<&str>>().join(" ");
+        if line.is_empty() && lines.last().is_none_or(|last| last.is_empty()){
+            continue;
+        }
+        lines.push(line);
+    }
+    while lines.last().is_some_and(|last| last.is_empty()){
+        lines.pop();
+    }
+    lines
+}
+
+fn push_html_text(text: &mut String, raw: &str){
+    //HTML 中的换行与空白等同于一个空格
+    let mut space = false;
+    for c in decode_html_entities(raw).chars(){
+        if c.is_whitespace() && c != '\u{a0}'{
+            if !space{
+                text.push(' ');
+            }
+            space = true;
+        }else{
+            text.push(if c == '\u{a0}' { ' ' } else { c });
+            space = false;
+        }
+    }
+}
+
+fn html_attribute(tag: &str, name: &str) -> Option<String>{
+    let regex = Regex::new(&format!(r#"(?i)\s{}\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s>]+))"#, name)).unwrap();
+    let cap = regex.captures(tag)?;
+    let value = cap.get(1).or_else(|| cap.get(2)).or_else(|| cap.get(3))?;
+    Some(decode_html_entities(value.as_str()))
+}
+
+fn decode_html_entities(text: &str) -> String{
+    //常用命名实体与数字实体，无法识别的实体保持原样
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('&'){
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest[1..].find(';').filter(|end| *end <= 10).and_then(|end|{
+            let entity = &rest[1..end + 1];
+            let c = match entity{
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some('\u{a0}'),
+                "copy" => Some('©'),
+                "reg" => Some('®'),
+                "mdash" => Some('—'),
+                "ndash" => Some('–'),
+                "hellip" => Some('…'),
+                _ if entity.starts_with("#x") || entity.starts_with("#X") => u32::from_str_radix(&entity[2..], 16).ok().and_then(char::from_u32),
+                _ if entity.starts_with('#') => entity[1..].parse::<u32>().ok().and_then(char::from_u32),
+                _ => None
+            };
+            c.map(|c| (c, end + 2))
+        });
+        match decoded{
+            Some((c, length)) => {
+                result.push(c);
+                rest = &rest[length..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+pub fn base64_lines(data: &[u8]) -> Vec<String>{
+    //RFC 2045 6.8: base64 编码后每行不超过 76 个字符
+    base64::encode(data).as_bytes().chunks(76).map(|chunk| String::from_utf8_lossy(chunk).to_string()).collect()
+}
+
+pub fn format_parameter(name: &str, value: &str, extended: bool) -> String{
+    //ASCII 参数值使用引号形式；非 ASCII 值在 Content-Disposition 中使用 RFC 2231 扩展形式，
+    //在 Content-Type 的 name 参数中沿用常见客户端兼容的 RFC 2047 形式
+    if !codec::needs_encoding(value){
+        return format!("{}=\"{}\"", name, value.replace('\\', "\\\\").replace('"', "\\\""));
+    }
+    if !extended{
+        return format!("{}=\"{}\"", name, codec::encode_header(value));
+    }
+    let encoded = percent_encode(value);
+    if encoded.len() + name.len() < 50{
+        return format!("{}*=utf-8''{}", name, encoded);
+    }
+    //过长时拆分为 RFC 2231 续行参数 name*0*、name*1*...，不拆分 %XX
+    let mut sections: Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut chars = encoded.chars().peekable();
+    while let Some(c) = chars.next(){
+        let mut token = c.to_string();
+        if c == '%'{
+            token.extend(chars.next());
+            token.extend(chars.next());
+        }
+        if current.len() + token.len() > 45{
+            sections.push(current);
+            current = String::new();
+        }
+        current.push_str(&token);
+    }
+    sections.push(current);
+    sections.iter().enumerate().map(|(index, section)|{
+        if index == 0{
+            format!("{}*0*=utf-8''{}", name, section)
+        }else{
+            format!("{}*{}*={}", name, index, section)
+        }
+    }).collect::<Vec<String>>().join("; ")
+}
+
+fn percent_encode(value: &str) -> String{
+    //RFC 2231 7: attribute-char 之外的字节以 %XX 表示
+    let mut result = String::new();
+    for byte in value.bytes(){
+        match byte{
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'!' | b'#' | b'$' | b'&' | b'+' | b'-' | b'.' | b'^' | b'_' | b'`' | b'|' | b'~' => result.push(byte as char),
+            _ => result.push_str(&format!("%{:02X}", byte))
+        }
+    }
+    result
+}
+
+pub fn dot_stuff(lines: Vec<String>) -> Vec<String>{
+    //RFC 5321 4.5.2: DATA 阶段以 . 开头的行需额外添加一个 .
+    lines.into_iter().map(|line| if line.starts_with('.') { format!(".{}", line) } else { line }).collect()
+}
+
+pub fn fold_header(name: &str, value: &str) -> Result<Vec<String>, MessageError>{
+    //RFC 5322 2.2.3: 只在已有空白之前折行，续行以该空白开头，去掉换行即可还原原值
+    //每行尽量不超过 78 个字符；不含空白的内容无法折行，折行后仍超过 998 个字符时返回错误
+    let mut boundaries: Vec<usize> = value.char_indices()
+        .filter(|(index, c)| c.is_whitespace() && *index > 0 && !value[..*index].ends_with(char::is_whitespace))
+        .map(|(index, _)| index)
+        .collect();
+    boundaries.push(value.len());
+    let mut lines: Vec<String> = Vec::new();
+    let mut current = format!("{}:", name);
+    if !value.is_empty() && !value.starts_with(char::is_whitespace){
+        current.push(' ');
+    }
+    let mut start = 0;
+    for end in boundaries{
+        let segment = &value[start..end];
+        if start > 0 && current.len() + segment.len() > SOFT_LINE_LIMIT{
+            lines.push(std::mem::take(&mut current));
+        }
+        current.push_str(segment);
+        start = end;
+    }
+    lines.push(current);
+    if lines.iter().any(|line| line.len() > HARD_LINE_LIMIT){
+        return Err(MessageError::HeaderTooLong(name.to_string()));
+    }
+    Ok(lines)
+}
+
+fn format_address_list(addresses: &[Address]) -> String{
+    addresses.iter().map(|address| address.to_header()).collect::<Vec<String>>().join(", ")
+}
+
+fn quote_phrase(phrase: &str) -> String{
+    //显示名中包含 RFC 5322 特殊字符时需使用引号
+    let specials = ['(', ')', '<', '>', '[', ']', ':', ';', '@', '\\', ',', '.', '"'];
+    if phrase.chars().any(|c| specials.contains(&c)){
+        format!("\"{}\"", phrase.replace('\\', "\\\\").replace('"', "\\\""))
+    }else{
+        phrase.to_string()
+    }
+}
+
+fn generate_boundary(depth: usize) -> String{
+    let now = Utc::now();
+    let counter = MESSAGE_COUNTER.fetch_add(1, Ordering::SeqCst);
+    format!("----=_Part_{}_{}_{}_{}", now.timestamp_subsec_nanos(), std::process::id(), counter, depth)
+}
+
+fn generate_message_id(domain: &str) -> String{
+    let now = Utc::now();
+    let counter = MESSAGE_COUNTER.fetch_add(1, Ordering::SeqCst);
+    format!("<{}.{}.{}.{}@{}>", now.timestamp(), now.timestamp_subsec_nanos(), std::process::id(), counter, domain)
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn golden_date() -> DateTime<FixedOffset>{
+        FixedOffset::east(8 * 3600).ymd(2020, 5, 1).and_hms(12, 0, 0)
+    }
+
+    fn golden_mail() -> SMTPMail{
+        let mut mail = SMTPMail::new();
+        mail.from.replace(String::from("alice@example.com"));
+        mail.to.push(String::from("bob@example.com"));
+        mail.subject.replace(String::from("Hello"));
+        mail.content.replace(vec![String::from("Hi Bob,"), String::new(), String::from("See you.")]);
+        mail
+    }
+
+    fn render(mail: &SMTPMail) -> String{
+        MessageBuilder::from_mail(mail)
+            .date(golden_date())
+            .message_id(String::from("<golden@localhost>"))
+            .boundary(String::from("golden"))
+            .render()
+            .unwrap()
+    }
+
+    #[test]
+    fn renders_simple_message(){
+        assert_eq!(render(&golden_mail()), include_str!("../tests/golden/simple.eml"));
+    }
+
+    #[test]
+    fn renders_display_names_without_bcc(){
+        let mut mail = golden_mail();
+        mail.from.replace(String::from("Alice Liddell <alice@example.com>"));
+        mail.to = vec![String::from("Bob <bob@example.com>"), String::from("\"Smith, John\" <john@example.com>")];
+        mail.cc = vec![String::from("<carol@example.com>")];
+        mail.bcc = vec![String::from("dave@example.com")];
+        assert_eq!(render(&mail), include_str!("../tests/golden/display_names.eml"));
+    }
+
+    #[test]
+    fn folds_long_lines(){
+        let mut mail = golden_mail();
+        mail.to = (1..=6).map(|i| format!("Recipient Number {} <recipient{}@example.com>", i, i)).collect();
+        mail.subject.replace(String::from("A rather long subject line that certainly will not fit into the seventy eight character limit"));
+        mail.content.replace(vec![String::from("short line"), "x".repeat(1200)]);
+        let rendered = render(&mail);
+        assert!(rendered.split("\r\n").all(|line| line.len() <= HARD_LINE_LIMIT));
+        assert_eq!(rendered, include_str!("../tests/golden/folding.eml"));
+    }
+
+    #[test]
+    fn folds_words_longer_than_hard_limit(){
+        let mut mail = golden_mail();
+        mail.subject.replace(format!("Token {} end", "x".repeat(1000)));
+        assert_eq!(render(&mail), include_str!("../tests/golden/long_word.eml"));
+        let subject = fold_header("Subject", &codec::encode_header(mail.subject.as_ref().unwrap())).unwrap().concat();
+        assert_eq!(codec::decode_header(&subject["Subject: ".len()..]), *mail.subject.as_ref().unwrap());
+
+        //不含空白的内容保持原样，折行只插在已有空白之前，续行均以空白开头
+        let value = format!("a\tb  {} c", "y".repeat(900));
+        let lines = fold_header("X-Token", &value).unwrap();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1..].iter().all(|line| line.starts_with(char::is_whitespace)));
+        assert_eq!(lines.concat(), format!("X-Token: {}", value));
+
+        //无法折行到 998 个字符以内的头部(如过长的地址或 Message-ID)不能发送
+        let value = format!("<{}@example.com>", "y".repeat(1000));
+        assert!(matches!(fold_header("Message-ID", &value), Err(MessageError::HeaderTooLong(name)) if name == "Message-ID"));
+        let mut mail = golden_mail();
+        mail.to.push(format!("{}@example.com", "z".repeat(1000)));
+        assert!(matches!(MessageBuilder::from_mail(&mail).build(), Err(MailError::Message(MessageError::HeaderTooLong(name))) if name == "To"));
+    }
+
+    #[test]
+    fn encodes_non_ascii_headers(){
+        let mut mail = golden_mail();
+        mail.from.replace(String::from("张三 <zhangsan@example.com>"));
+        mail.subject.replace(String::from("你好，世界"));
+        mail.content.replace(vec![String::from("你好！")]);
+        assert_eq!(render(&mail), include_str!("../tests/golden/non_ascii.eml"));
+    }
+
+    #[test]
+    fn builds_non_ascii_body_as_8bit(){
+        let mut mail = golden_mail();
+        mail.content.replace(vec![String::from("你好")]);
+        let lines = MessageBuilder::from_mail(&mail).eight_bit(true).build().unwrap();
+        assert!(lines.contains(&String::from("Content-Transfer-Encoding: 8bit")));
+        assert!(lines.iter().any(|line| line.starts_with("Message-ID: <") && line.ends_with("@localhost>")));
+    }
+
+    #[test]
+    fn encodes_non_ascii_body_for_seven_bit_servers(){
+        let mut mail = golden_mail();
+        mail.content.replace(vec![String::from("Café au lait")]);
+        let lines = MessageBuilder::from_mail(&mail).build().unwrap();
+        assert!(lines.contains(&String::from("Content-Transfer-Encoding: quoted-printable")));
+        assert_eq!(lines.last().unwrap(), "Caf=C3=A9 au lait");
+        mail.content.replace(vec![String::from("你好，世界")]);
+        let lines = MessageBuilder::from_mail(&mail).build().unwrap();
+        assert!(lines.contains(&String::from("Content-Transfer-Encoding: base64")));
+        assert_eq!(lines.last().unwrap(), "5L2g5aW977yM5LiW55WM");
+        assert!(lines.iter().all(|line| line.is_ascii()));
+    }
+
+    #[test]
+    fn renders_attachments_as_multipart_mixed(){
+        let mut mail = golden_mail();
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/hello.txt");
+        mail.attachments.push(Attachment::from_path(path).unwrap());
+        let mut report = Attachment::from_path(path).unwrap();
+        report.filename = String::from("二〇二〇年第一季度财务报告（最终版）.txt");
+        mail.attachments.push(report);
+        assert_eq!(render(&mail), include_str!("../tests/golden/attachments.eml"));
+    }
+
+    #[test]
+    fn renders_html_with_plain_alternative_and_inline_image(){
+        let mut mail = golden_mail();
+        mail.content = None;
+        mail.html.replace(String::from(concat!(
+            "<html>\n",
+            "<head><title>Hello</title><style>p { color: red; }</style></head>\n",
+            "<body>\n",
+            "<p>Hi Bob,</p>\n",
+            "<p>See <a href=\"https://example.com/\">our site</a> &amp; the logo:<br>\n",
+            "<img src=\"cid:pixel.png\" alt=\"logo\"></p>\n",
+            "</body>\n",
+            "</html>"
+        )));
+        mail.inline_images.push(Attachment::inline(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/pixel.png")).unwrap());
+        assert_eq!(render(&mail), include_str!("../tests/golden/html.eml"));
+    }
+
+    #[test]
+    fn dot_stuffs_leading_dots(){
+        let lines = vec![String::from("."), String::from(".hidden"), String::from("a.b")];
+        assert_eq!(dot_stuff(lines), vec![String::from(".."), String::from("..hidden"), String::from("a.b")]);
+    }
+
+    #[test]
+    fn parses_addresses(){
+        assert_eq!(Address::parse("\"Smith, John\" <john@example.com>"), Address{ name: Some(String::from("Smith, John")), email: String::from("john@example.com") });
+        assert_eq!(Address::parse(" bob@example.com "), Address{ name: None, email: String::from("bob@example.com") });
+        assert_eq!(Address::parse("<carol@example.com>").to_header(), "carol@example.com");
+    }
+}
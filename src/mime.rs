@@ -0,0 +1,480 @@
+use crate::codec;
+use crate::error::{MailError, ParseError};
+
+//multipart 最大嵌套层数，超过后按单一部分处理，防止畸形邮件导致栈溢出
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug, Clone)]
+pub struct MimePart{
+    //已展开折行的头部，名称保持原样，值去除首尾空白
+    pub headers: Vec<(String, String)>,
+    //小写的 type/subtype
+    pub mime_type: String,
+    pub parameters: Vec<(String, String)>,
+    //未经传输编码解码的正文，multipart 部分为空
+    pub body: Vec<u8>,
+    pub children: Vec<MimePart>
+}
+
+impl MimePart{
+    pub fn parse(raw: &[u8]) -> MimePart{
+        MimePart::parse_part(raw, "text/plain", 0)
+    }
+
+    fn parse_part(raw: &[u8], default_type: &str, depth: usize) -> MimePart{
+        let (headers, body) = split_header(raw);
+        let (mime_type, parameters) = match headers.iter().find(|(name, _)| name.eq_ignore_ascii_case("Content-Type")){
+            Some((_, value)) => {
+                let (mime_type, parameters) = parse_parameters(value);
+                let mime_type = mime_type.to_lowercase();
+                //RFC 2045 5.2: 无法识别的 Content-Type 按默认类型处理
+                if mime_type.contains('/') { (mime_type, parameters) } else { (default_type.to_string(), Vec::new()) }
+            }
+            None => (default_type.to_string(), Vec::new())
+        };
+        let mut part = MimePart{
+            headers,
+            mime_type,
+            parameters,
+            body: Vec::new(),
+            children: Vec::new()
+        };
+        let boundary = part.parameter("boundary");
+        match boundary{
+            Some(boundary) if part.is_multipart() && !boundary.is_empty() && depth < MAX_DEPTH => {
+                //RFC 2046 5.1.5: multipart/digest 中子部分的默认类型为 message/rfc822
+                let child_type = if part.mime_type == "multipart/digest" { "message/rfc822" } else { "text/plain" };
+                for child in split_multipart(body, &boundary){
+                    part.children.push(MimePart::parse_part(child, child_type, depth + 1));
+                }
+            }
+            _ => {
+                part.body = body.to_vec();
+            }
+        }
+        part
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str>{
+        self.headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    //Content-Type 参数，名称不区分大小写
+    pub fn parameter(&self, name: &str) -> Option<String>{
+        self.parameters.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.clone())
+    }
+
+    pub fn is_multipart(&self) -> bool{
+        self.mime_type.starts_with("multipart/")
+    }
+
+    pub fn transfer_encoding(&self) -> String{
+        self.header("Content-Transfer-Encoding").map(|value| value.trim().to_lowercase()).unwrap_or_else(|| String::from("7bit"))
+    }
+
+    //Content-Disposition 的类型(小写)与参数
+    pub fn disposition(&self) -> Option<(String, Vec<(String, String)>)>{
+        let (disposition, parameters) = parse_parameters(self.header("Content-Disposition")?);
+        Some((disposition.to_lowercase(), parameters))
+    }
+
+    pub fn is_attachment(&self) -> bool{
+        match self.disposition(){
+            Some((disposition, _)) => disposition == "attachment",
+            None => false
+        }
+    }
+
+    pub fn filename(&self) -> Option<String>{
+        //优先使用 Content-Disposition 的 filename，其次为 Content-Type 的 name；兼容以 RFC 2047 编码的文件名
+        let from_disposition = self.disposition().and_then(|(_, parameters)|{
+            parameters.into_iter().find(|(key, _)| key.eq_ignore_ascii_case("filename")).map(|(_, value)| value)
+        });
+        from_disposition.or_else(|| self.parameter("name")).map(|name| codec::decode_header(&name))
+    }
+
+    pub fn decoded_body(&self) -> Result<Vec<u8>, MailError>{
+        match self.transfer_encoding().as_str(){
+            "7bit" | "8bit" | "binary" => Ok(self.body.clone()),
+            "base64" => {
+                let data: Vec<u8> = self.body.iter().filter(|byte| !byte.is_ascii_whitespace()).cloned().collect();
+                match base64::decode(&data){
+                    Ok(decoded) => Ok(decoded),
+                    Err(error) => Err(ParseError::ContentDecode(error).into())
+                }
+            }
+            "quoted-printable" => Ok(codec::qp_decode(&self.body)),
+            encoding => Err(ParseError::UnsupportedEncoding(encoding.to_string()).into())
+        }
+    }
+
+    pub fn text(&self) -> Result<String, MailError>{
+        self.decode_text().map(|(text, _)| text)
+    }
+
+    //返回文本与实际使用的字符集，字符集问题不会导致失败，只有传输编码错误才返回 Err
+    pub fn decode_text(&self) -> Result<(String, String), MailError>{
+        let data = self.decoded_body()?;
+        Ok(codec::decode_text(&data, self.parameter("charset").as_deref()))
+    }
+
+    //传输编码错误时按原始内容解码，用于显示损坏的正文
+    pub fn decode_text_lossy(&self) -> (String, String){
+        let data = self.decoded_body().unwrap_or_else(|_| self.body.clone());
+        codec::decode_text(&data, self.parameter("charset").as_deref())
+    }
+
+    //深度优先遍历，包含自身
+    pub fn walk(&self) -> Vec<&MimePart>{
+        let mut parts = vec![self];
+        for child in &self.children{
+            parts.append(&mut child.walk());
+        }
+        parts
+    }
+
+    //第一个指定类型且不是附件的单一部分，用于查找正文
+    pub fn find_body(&self, mime_type: &str) -> Option<&MimePart>{
+        self.walk().into_iter().find(|part| !part.is_multipart() && part.mime_type == mime_type && !part.is_attachment())
+    }
+}
+
+fn split_lines(data: &[u8]) -> Vec<&[u8]>{
+    //按 LF 拆分，每行保留行尾
+    let mut lines: Vec<&[u8]> = Vec::new();
+    let mut start = 0;
+    for (index, byte) in data.iter().enumerate(){
+        if *byte == b'\n'{
+            lines.push(&data[start..index + 1]);
+            start = index + 1;
+        }
+    }
+    if start < data.len(){
+        lines.push(&data[start..]);
+    }
+    lines
+}
+
+fn trim_line_end(line: &[u8]) -> &[u8]{
+    let mut end = line.len();
+    while end > 0 && (line[end - 1] == b'\n' || line[end - 1] == b'\r'){
+        end -= 1;
+    }
+    &line[..end]
+}
+
+fn split_header(raw: &[u8]) -> (Vec<(String, String)>, &[u8]){
+    //头部与正文以第一个空行分隔，以空白开头的行是上一头部的续行
+    let mut headers: Vec<(String, String)> = Vec::new();
+    let mut offset = 0;
+    for line in split_lines(raw){
+        let content = trim_line_end(line);
+        offset += line.len();
+        if content.is_empty(){
+            break;
+        }
+        let text = String::from_utf8_lossy(content);
+        if content[0] == b' ' || content[0] == b'\t'{
+            if let Some((_, value)) = headers.last_mut(){
+                value.push_str(&text);
+            }
+            continue;
+        }
+        match text.find(':'){
+            Some(colon) => { headers.push((text[..colon].trim().to_string(), text[colon + 1..].to_string())); }
+            None => {
+                //不是合法头部，视为没有头部的正文
+                if headers.is_empty(){
+                    return (headers, raw);
+                }
+            }
+        }
+    }
+    for (_, value) in headers.iter_mut(){
+        *value = value.trim().to_string();
+    }
+    (headers, &raw[offset..])
+}
+
+fn split_multipart<'a>(body: &'a [u8], boundary: &str) -> Vec<&'a [u8]>{
+    //RFC 2046 5.1.1: 分隔行前的换行属于分隔符，分隔行末尾允许有空白
+    let delimiter = format!("--{}", boundary);
+    let close = format!("--{}--", boundary);
+    let mut parts: Vec<&[u8]> = Vec::new();
+    let mut start: Option<usize> = None;
+    let mut offset = 0;
+    for line in split_lines(body){
+        let content = trim_line_end(line);
+        let content = String::from_utf8_lossy(content);
+        let content = content.trim_end();
+        let is_delimiter = content == delimiter;
+        let is_close = content == close;
+        if is_delimiter || is_close{
+            if let Some(start) = start{
+                let mut end = offset;
+                if end > start && body[end - 1] == b'\n'{ end -= 1; }
+                if end > start && body[end - 1] == b'\r'{ end -= 1; }
+                parts.push(&body[start..end.max(start)]);
+            }
+            if is_close{
+                return parts;
+            }
+            start = Some(offset + line.len());
+        }
+        offset += line.len();
+    }
+    //缺少结束分隔符时保留已读取的最后一部分
+    if let Some(start) = start{
+        parts.push(&body[start.min(body.len())..]);
+    }
+    parts
+}
+
+pub fn parse_parameters(value: &str) -> (String, Vec<(String, String)>){
+    //解析 "值; 名称=参数; ..." 形式的头部，支持引号、反斜杠转义以及 RFC 2231 的续行与字符集扩展
+    let mut segments = split_unquoted(value, ';').into_iter();
+    let main = segments.next().unwrap_or_default().trim().to_string();
+    let mut raw: Vec<(String, String, bool)> = Vec::new();
+    for segment in segments{
+        let (name, value) = match segment.find('='){
+            Some(equal) => (segment[..equal].trim().to_lowercase(), segment[equal + 1..].trim().to_string()),
+            None => { continue; }
+        };
+        if name.is_empty(){
+            continue;
+        }
+        let quoted = value.starts_with('"');
+        raw.push((name, if quoted { unquote(&value) } else { value }, quoted));
+    }
+    let mut parameters: Vec<(String, String)> = Vec::new();
+    let mut extended: Vec<String> = Vec::new();
+    for (name, value, _) in &raw{
+        if !name.contains('*'){
+            if !parameters.iter().any(|(key, _)| key == name){
+                parameters.push((name.clone(), value.clone()));
+            }
+            continue;
+        }
+        let base = name.split('*').next().unwrap_or_default().to_string();
+        if !extended.contains(&base){
+            extended.push(base);
+        }
+    }
+    for base in extended{
+        if let Some(value) = combine_extended(&base, &raw){
+            //RFC 2231 形式优先于同名的普通参数
+            match parameters.iter_mut().find(|(key, _)| *key == base){
+                Some((_, old)) => { *old = value; }
+                None => { parameters.push((base, value)); }
+            }
+        }
+    }
+    (main, parameters)
+}
+
+fn combine_extended(base: &str, raw: &[(String, String, bool)]) -> Option<String>{
+    //name* 或 name*0、name*1*... 按序号拼接，带 * 的部分需百分号解码，字符集在第一个部分中声明
+    let mut sections: Vec<(usize, &str, bool)> = Vec::new();
+    for (name, value, _) in raw{
+        let rest = match name.strip_prefix(base).and_then(|rest| rest.strip_prefix('*')){
+            Some(rest) => rest,
+            None => { continue; }
+        };
+        if rest.is_empty(){
+            sections.push((0, value, true));
+        }else{
+            let encoded = rest.ends_with('*');
+            if let Ok(index) = rest.trim_end_matches('*').parse::<usize>(){
+                sections.push((index, value, encoded));
+            }
+        }
+    }
+    sections.sort_by_key(|(index, _, _)| *index);
+    let mut charset = String::from("us-ascii");
+    let mut bytes: Vec<u8> = Vec::new();
+    for (position, (_, value, encoded)) in sections.iter().enumerate(){
+        if !encoded{
+            bytes.extend_from_slice(value.as_bytes());
+            continue;
+        }
+        let mut value: &str = value;
+        if position == 0{
+            let mut pieces = value.splitn(3, '\'');
+            if let (Some(declared), Some(_language), Some(rest)) = (pieces.next(), pieces.next(), pieces.next()){
+                if !declared.is_empty(){
+                    charset = declared.to_string();
+                }
+                value = rest;
+            }
+        }
+        bytes.append(&mut percent_decode(value));
+    }
+    if sections.is_empty(){
+        return None;
+    }
+    Some(codec::decode_charset(&bytes, &charset).unwrap_or_else(|| String::from_utf8_lossy(&bytes).to_string()))
+}
+
+fn percent_decode(text: &str) -> Vec<u8>{
+    let bytes = text.as_bytes();
+    let mut result: Vec<u8> = Vec::new();
+    let mut index = 0;
+    while index < bytes.len(){
+        if bytes[index] == b'%' && index + 2 < bytes.len(){
+            let high = (bytes[index + 1] as char).to_digit(16);
+            let low = (bytes[index + 2] as char).to_digit(16);
+            if let (Some(high), Some(low)) = (high, low){
+                result.push((high << 4 | low) as u8);
+                index += 3;
+                continue;
+            }
+        }
+        result.push(bytes[index]);
+        index += 1;
+    }
+    result
+}
+
+fn unquote(value: &str) -> String{
+    let mut result = String::new();
+    let mut chars = value.trim().chars();
+    chars.next();
+    while let Some(c) = chars.next(){
+        match c{
+            '\\' => { result.extend(chars.next()); }
+            '"' => { break; }
+            _ => { result.push(c); }
+        }
+    }
+    result
+}
+
+pub fn split_unquoted(value: &str, separator: char) -> Vec<String>{
+    //按分隔符拆分，忽略引号与尖括号内的分隔符
+    let mut parts: Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut angle = false;
+    let mut escaped = false;
+    for c in value.chars(){
+        if escaped{
+            current.push(c);
+            escaped = false;
+            continue;
+        }
+        match c{
+            '\\' if quoted => { escaped = true; }
+            '"' => { quoted = !quoted; }
+            '<' if !quoted => { angle = true; }
+            '>' if !quoted => { angle = false; }
+            _ if c == separator && !quoted && !angle => {
+                parts.push(current);
+                current = String::new();
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    parts.push(current);
+    parts
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn parses_single_part_message(){
+        let raw = b"Subject: =?utf-8?B?5L2g5aW9?=\r\nContent-Type: text/plain;\r\n charset=\"utf-8\"\r\n\r\nHello\r\n";
+        let part = MimePart::parse(raw);
+        assert_eq!(part.mime_type, "text/plain");
+        assert_eq!(part.parameter("charset"), Some(String::from("utf-8")));
+        assert_eq!(part.header("subject"), Some("=?utf-8?B?5L2g5aW9?="));
+        assert_eq!(part.text().unwrap(), "Hello\r\n");
+        assert!(part.children.is_empty());
+    }
+
+    #[test]
+    fn parses_nested_multiparts(){
+        let raw = concat!(
+            "Content-Type: multipart/mixed; boundary=outer\r\n",
+            "\r\n",
+            "preamble\r\n",
+            "--outer\r\n",
+            "Content-Type: multipart/alternative; boundary=\"inner\"\r\n",
+            "\r\n",
+            "--inner\r\n",
+            "Content-Type: text/plain; charset=utf-8\r\n",
+            "\r\n",
+            "plain\r\n",
+            "--inner\r\n",
+            "Content-Type: text/html; charset=utf-8\r\n",
+            "Content-Transfer-Encoding: base64\r\n",
+            "\r\n",
+            "PHA+aHRtbDwvcD4=\r\n",
+            "--inner--\r\n",
+            "--outer \r\n",
+            "Content-Type: application/octet-stream\r\n",
+            "Content-Disposition: attachment;\r\n",
+            "\tfilename*0*=utf-8''%E6%8A%A5%E5%91%8A;\r\n",
+            "\tfilename*1=\".txt\"\r\n",
+            "\r\n",
+            "data\r\n",
+            "--outer--\r\n",
+            "epilogue\r\n"
+        );
+        let root = MimePart::parse(raw.as_bytes());
+        assert_eq!(root.children.len(), 2);
+        assert_eq!(root.children[0].children.len(), 2);
+        assert_eq!(root.find_body("text/plain").unwrap().text().unwrap(), "plain");
+        assert_eq!(root.find_body("text/html").unwrap().text().unwrap(), "<p>html</p>");
+        let attachment = &root.children[1];
+        assert!(attachment.is_attachment());
+        assert_eq!(attachment.filename(), Some(String::from("报告.txt")));
+        assert_eq!(attachment.decoded_body().unwrap(), b"data");
+        assert_eq!(attachment.decode_text().unwrap(), (String::from("data"), String::from("us-ascii")));
+        assert_eq!(root.walk().len(), 5);
+    }
+
+    #[test]
+    fn decodes_declared_and_undeclared_charsets(){
+        let part = |content_type: &str, body: &[u8]|{
+            let mut raw = format!("Content-Type: {}\r\n\r\n", content_type).into_bytes();
+            raw.extend_from_slice(body);
+            MimePart::parse(&raw).decode_text().unwrap()
+        };
+        assert_eq!(part("text/plain; charset=GB2312", b"\xc4\xe3\xba\xc3"), (String::from("你好"), String::from("gb18030")));
+        assert_eq!(part("text/plain; charset=\"Big5\"", b"\xa7\x41\xa6\x6e"), (String::from("你好"), String::from("big5")));
+        assert_eq!(part("text/plain; charset=Shift_JIS", b"\x82\xb1\x82\xf1"), (String::from("こん"), String::from("shift_jis")));
+        assert_eq!(part("text/plain; charset=iso-8859-1", b"caf\xe9"), (String::from("café"), String::from("windows-1252")));
+        assert_eq!(part("text/plain", "你好".as_bytes()), (String::from("你好"), String::from("utf-8")));
+        assert_eq!(part("text/plain; charset=x-unknown", b"\xc4\xe3\xba\xc3"), (String::from("你好"), String::from("gb18030")));
+        assert_eq!(part("text/plain; charset=utf-8", b"ok\xff").0, "ok\u{fffd}");
+    }
+
+    #[test]
+    fn decodes_quoted_printable(){
+        let raw = concat!(
+            "Content-Type: text/plain; charset=utf-8\r\n",
+            "Content-Transfer-Encoding: Quoted-Printable\r\n",
+            "\r\n",
+            "Caf=C3=A9 =3D soft=\r\n",
+            "break  \r\n",
+            "=E4=BD=A0=E5=A5=BD=20\r\n"
+        );
+        assert_eq!(MimePart::parse(raw.as_bytes()).text().unwrap(), "Café = softbreak\r\n你好 \r\n");
+    }
+
+    #[test]
+    fn parses_parameters(){
+        let (value, parameters) = parse_parameters("attachment; filename=\"a \\\"b\\\"; c.txt\"; size=10; name*=gbk'zh'%C4%E3%BA%C3");
+        assert_eq!(value, "attachment");
+        assert_eq!(parameters, vec![
+            (String::from("filename"), String::from("a \"b\"; c.txt")),
+            (String::from("size"), String::from("10")),
+            (String::from("name"), String::from("你好")),
+        ]);
+    }
+}
@@ -1,14 +1,21 @@
 use crate::tcp;
+use crate::codec;
+use crate::mime::{self, MimePart};
+use crate::message::{self, Address};
+use crate::tcp::{Security, Timeouts};
 use crate::pop3::State::TCPConnected;
-use crate::util;
-use crate::util::MailError;
-use crate::util::print_error;
+use crate::sasl;
+use crate::error::{MailError, TransportError, ReplyError, ParseError, AuthError, MessageError, Protocol};
 use regex::Regex;
-use crate::util::MailError::POP3ResponseParseError;
 use std::ops::Add;
-use base64;
-use encoding::{DecoderTrap, Encoding};
-use encoding::all::GBK;
+use std::io::Write;
+use crate::store::{Store, SyncConfig};
+use crate::config::Profile;
+use chrono::{DateTime, Local, Utc};
+use crate::smtp::SMTPMail;
+use crate::mbox::{self, MboxMessage};
+use serde::Serialize;
+use std::time::Duration;
 
 #[derive(Debug)]
 enum Response{
@@ -16,7 +23,7 @@ enum Response{
     Err(String)
 }
 
-enum State{
+pub enum State{
     Init, TCPConnected, Authorized
 }
 
@@ -27,7 +34,46 @@ pub struct Client{
     account: Option<String>,
     password: Option<String>,
     dele_list: Vec<usize>,
-    debug: bool
+    sync_config: SyncConfig,
+    //CAPA 命令返回的扩展，首次使用时请求；STLS 升级与登录后需重新请求
+    capabilities: Option<Capabilities>,
+    //服务器问候语，APOP 使用其中的时间戳
+    greeting: Option<String>,
+    auth_mechanism: Option<String>,
+    allow_plaintext_auth: bool
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Capabilities{
+    //扩展名称，如 TOP、UIDL、STLS
+    pub names: Vec<String>,
+    //SASL 扩展声明的验证方式
+    pub sasl: Vec<String>
+}
+
+impl Capabilities{
+    pub fn parse(response: &str) -> Capabilities{
+        let mut capabilities = Capabilities::default();
+        for line in response.lines(){
+            let mut fields = line.split_whitespace();
+            if let Some(name) = fields.next(){
+                let name = name.to_uppercase();
+                if name == "SASL"{
+                    capabilities.sasl = fields.map(|mechanism| mechanism.to_uppercase()).collect();
+                }
+                capabilities.names.push(name);
+            }
+        }
+        capabilities
+    }
+
+    pub fn has(&self, name: &str) -> bool{
+        self.names.iter().any(|capability| capability.eq_ignore_ascii_case(name))
+    }
+}
+
+enum LoginMethod{
+    Sasl(String), Apop(String), UserPass
 }
 
 impl Client{
@@ -40,25 +86,79 @@ impl Client{
             account: None,
             password: None,
             dele_list: Vec::new(),
-            debug: false
+            sync_config: SyncConfig::new(),
+            capabilities: None,
+            greeting: None,
+            auth_mechanism: None,
+            allow_plaintext_auth: false
         };
         c.client.set_end_pointer(String::from("\r\n"));
         return c
     }
 
-    pub fn print_debug(&mut self, response: &Response){
-        if self.debug{
-            println!("Response: {:?}", response);
+    pub fn from_profile(profile: &Profile, password: String) -> Result<Client, MailError>{
+        let server = match &profile.pop3{
+            Some(server) => server,
+            None => { return Err(MailError::Config(String::from("未配置 pop3 服务器"))); }
+        };
+        let tls_config = profile.tls_config(server, Security::Tls);
+        let mut client = Client::new(server.host.clone(), profile.hostname.clone().unwrap_or_default());
+        client.auth_mechanism = server.auth.as_ref().map(|auth| auth.to_uppercase());
+        client.allow_plaintext_auth = server.allow_plaintext_auth.unwrap_or(false);
+        client.client.set_port(server.port.unwrap_or_else(|| default_port(&tls_config.security)));
+        client.client.set_tls_config(tls_config);
+        client.client.set_timeouts(profile.timeouts());
+        client.sync_config = profile.sync_config();
+        client.account = profile.account.clone();
+        client.password.replace(password);
+        Ok(client)
+    }
+
+    fn log_debug(&mut self, response: &Response){
+        if self.client.is_debug(){
+            self.client.log_debug(format!("Response: {:?}", response));
+        }
+    }
+
+    fn _send(&mut self, content: String) -> Result<(), MailError>{
+        let result = self.client.send(content);
+        self.check_connection();
+        result
+    }
+
+    fn _receive(&mut self) -> Result<String, MailError>{
+        let result = self.client.receive();
+        self.check_connection();
+        result
+    }
+
+    fn _receive_bytes(&mut self) -> Result<Vec<u8>, MailError>{
+        let result = self.client.receive_bytes();
+        self.check_connection();
+        result
+    }
+
+    fn check_connection(&mut self){
+        //连接已断开(超时或服务器关闭连接)时回到初始状态；服务器不会执行未 QUIT 会话中的删除
+        if !self.client.is_connected(){
+            self.state = State::Init;
+            self.dele_list.clear();
+            self.capabilities = None;
+            self.greeting = None;
         }
     }
 
     fn _connect(&mut self) -> Result<Response, MailError>{
         return match self.client.connect() {
             Ok(s) => {
-                return match self.client.receive() {
+                return match self._receive() {
                     Ok(s) => {
                         self.state = State::TCPConnected;
-                        parse_response(s)
+                        let response = parse_response(s);
+                        if let Ok(Response::Ok(greeting)) = &response{
+                            self.greeting.replace(greeting.clone());
+                        }
+                        response
                     }
                     Err(s) => {
                         Err(s)
@@ -72,453 +172,568 @@ impl Client{
     }
 
     fn _noop(&mut self) -> Result<Response, MailError>{
-        self.client.send(String::from("noop"))?;
-        let _response = self.client.receive();
+        self._send(String::from("noop"))?;
+        let _response = self._receive();
         if let Ok(response) = _response{
             return parse_response(response)
         }else if let Err(error) = _response{ return Err(error); }
-        return Err(MailError::TCPConnectFailError)
+        return Err(MailError::Transport(TransportError::NotConnected))
     }
 
     fn _user(&mut self, account: String) -> Result<Response, MailError>{
-        self.client.send("user ".to_owned() + &account)?;
-        let _response = self.client.receive();
+        self._send("user ".to_owned() + &account)?;
+        let _response = self._receive();
         if let Ok(response) = _response{
             return parse_response(response)
         }else if let Err(error) = _response{ return Err(error); }
-        return Err(MailError::TCPConnectFailError)
+        return Err(MailError::Transport(TransportError::NotConnected))
     }
 
     fn _pass(&mut self, password: String) -> Result<Response, MailError>{
-        self.client.send("pass ".to_owned() + &password)?;
-        let _response = self.client.receive();
+        self._send("pass ".to_owned() + &password)?;
+        let _response = self._receive();
         if let Ok(response) = _response{
             return parse_response(response)
         }else if let Err(error) = _response{ return Err(error); }
-        return Err(MailError::TCPConnectFailError)
+        return Err(MailError::Transport(TransportError::NotConnected))
+    }
+
+    fn _apop(&mut self, account: String, digest: String) -> Result<Response, MailError>{
+        self._send(format!("apop {} {}", account, digest))?;
+        parse_response(self._receive()?)
+    }
+
+    fn _auth(&mut self, mechanism: String, initial_response: Option<Vec<u8>>) -> Result<String, MailError>{
+        //RFC 5034: 服务器以 "+ 质询" 继续，以 +OK/-ERR 结束；空的初始响应以 = 表示
+        if let Some(initial) = initial_response{
+            let encoded = if initial.is_empty() { String::from("=") } else { base64::encode(initial) };
+            self._send(format!("auth {} {}", mechanism.to_lowercase(), encoded))?;
+        }else{
+            self._send(format!("auth {}", mechanism.to_lowercase()))?;
+        }
+        self._receive()
+    }
+
+    fn _send_base64(&mut self, bytes: Vec<u8>) -> Result<String, MailError>{
+        self._send(base64::encode(bytes))?;
+        self._receive()
+    }
+
+    fn _stls(&mut self) -> Result<Response, MailError>{
+        self._send(String::from("stls"))?;
+        parse_response(self._receive()?)
     }
 
     fn _stat(&mut self) -> Result<Response, MailError>{
-        self.client.send(String::from("stat"))?;
-        let _response = self.client.receive();
+        self._send(String::from("stat"))?;
+        let _response = self._receive();
         if let Ok(response) = _response{
             return parse_response(response)
         }else if let Err(error) = _response{ return Err(error); }
-        return Err(MailError::TCPConnectFailError)
+        return Err(MailError::Transport(TransportError::NotConnected))
     }
 
     fn _list(&mut self, order_number: usize) -> Result<Response, MailError>{
         //list命令必须携带参数以和stat进行区别
-        self.client.send(format!("list {}", order_number))?;
-        let _response = self.client.receive();
+        self._send(format!("list {}", order_number))?;
+        let _response = self._receive();
         if let Ok(response) = _response{
             return parse_response(response);
         }else if let Err(error) = _response{ return Err(error); }
-        return Err(MailError::TCPConnectFailError)
+        return Err(MailError::Transport(TransportError::NotConnected))
     }
 
-    fn _top(&mut self, order_number: usize, top_line: usize) -> Result<Response, MailError>{
-        self.client.send(format!("top {} {}", order_number, top_line))?;
-        let mut counter = top_line;
-        let mut result = String::new();
-        let _response = self.client.receive();
-        if let Ok(response) = _response{
-            let response = parse_response(response);
-            if let Ok(res) = response{
-                if let Response::Ok(ok_res) = res{
-                    result += &format!("{}\r\n", ok_res);
-                }else if let Response::Err(error) = res{
-                    return Ok(Response::Err(error));
-                }
-            }else if let Err(error) = response{
-                return Err(error);
-            }
-        }else if let Err(error) = _response{
-            return Err(error);
-        }
+    fn _list_all(&mut self) -> Result<Response, MailError>{
+        //不带参数时为多行响应，每行为 "序号 字节数"
+        self._send(String::from("list"))?;
+        self._receive_multiline()
+    }
 
+    fn _top(&mut self, order_number: usize, top_line: usize) -> Result<Result<Vec<u8>, String>, MailError>{
+        //返回邮件头部及正文的前 top_line 行
+        self._send(format!("top {} {}", order_number, top_line))?;
+        self._receive_data()
+    }
 
-        while counter > 0{
-            let _response = self.client.receive();
-            if let Ok(response) = _response{
-                result += &response;
-            }else if let Err(error) = _response{
-                return Err(error);
-            }
-            counter -= 1;
-        }
-
-        return Ok(Response::Ok(result));
-    }
-
-    fn _retr(&mut self, order_number: usize) -> Result<Response, MailError>{
-        self.client.send(format!("retr {}", order_number))?;
-        let octets_info = self.client.receive();
-        if let Ok(info) = octets_info{
-            let parse_result = parse_response(info);
-            if let Ok(response) = parse_result{
-                if let Response::Ok(ok_response) = response{
-                    let parse_result = parse_retr_octets_count(ok_response);
-                    if let Ok(parse_result) = parse_result{
-                        let mut line: String = String::new();
-                        while line.len() <= parse_result{
-                            let r = self.client.receive();
-                            if let Ok(_line) = r{
-                                line += &_line;
-                            }else if let Err(error) = r{
-                                return Err(error)
-                            }
-                        }
-                        return Ok(Response::Ok(line));
-                    }else if let Err(error) = parse_result{
-                        return Err(error);
-                    }
-                }else if let Response::Err(err_response) = response{
-                    return Ok(Response::Err(err_response));
-                }
-            }else if let Err(error) = parse_result{
-                return Err(error);
-            }
-        }else if let Err(error) = octets_info{
-            return Err(error);
+    fn _capa(&mut self) -> Result<Response, MailError>{
+        //RFC 2449: 多行响应，每行为一个扩展名称及其参数
+        self._send(String::from("capa"))?;
+        self._receive_multiline()
+    }
+
+    fn _retr(&mut self, order_number: usize) -> Result<Result<Vec<u8>, String>, MailError>{
+        //"+OK n octets" 中的字节数只是参考，部分服务器不提供，以结束行为准
+        self._send(format!("retr {}", order_number))?;
+        self._receive_data()
+    }
+
+    fn _uidl(&mut self) -> Result<Response, MailError>{
+        //多行响应，每行为 "序号 唯一标识"，以单独的 . 行结束
+        self._send(String::from("uidl"))?;
+        self._receive_multiline()
+    }
+
+    fn _receive_multiline(&mut self) -> Result<Response, MailError>{
+        //用于 LIST/UIDL/CAPA，内容均为 ASCII
+        match self._receive_data()?{
+            Ok(data) => Ok(Response::Ok(String::from_utf8_lossy(&data).to_string())),
+            Err(error) => Ok(Response::Err(error))
+        }
+    }
+
+    fn _receive_data(&mut self) -> Result<Result<Vec<u8>, String>, MailError>{
+        //读取状态行，-ERR 时没有后续内容；+OK 时按字节读取到结束行，返回不含状态行与结束行的内容
+        let status = self._receive()?;
+        if let Response::Err(error) = parse_response(status)?{
+            return Ok(Err(error));
+        }
+        let mut reader = MultilineReader::new();
+        while !reader.is_finished(){
+            let line = self._receive_bytes()?;
+            reader.feed(&line);
         }
-        return Err(MailError::NotHandledError);
+        self.client.log_debug(format!("Response: {} 字节", reader.data.len()));
+        Ok(Ok(reader.data))
+    }
+
+    fn _uidl_one(&mut self, order_number: usize) -> Result<Response, MailError>{
+        self._send(format!("uidl {}", order_number))?;
+        parse_response(self._receive()?)
     }
 
     fn _quit(&mut self) -> Result<Response, MailError>{
-        self.client.send(String::from("quit"))?;
-        let _response = self.client.receive();
+        self._send(String::from("quit"))?;
+        let _response = self._receive();
         if let Ok(response) = _response{
             return parse_response(response);
         }else if let Err(error) = _response{ return Err(error); }
-        return Err(MailError::TCPConnectFailError)
+        return Err(MailError::Transport(TransportError::NotConnected))
     }
 
     fn _dele(&mut self, order_number: usize) -> Result<Response, MailError>{
-        self.client.send(format!("dele {}", order_number))?;
-        let _response = self.client.receive();
+        self._send(format!("dele {}", order_number))?;
+        let _response = self._receive();
         if let Ok(response) = _response{
             return parse_response(response);
         }else if let Err(error) = _response{ return Err(error); }
-        return Err(MailError::TCPConnectFailError)
+        return Err(MailError::Transport(TransportError::NotConnected))
     }
 
     fn _rset(&mut self) -> Result<Response, MailError>{
-        self.client.send(String::from("rset"))?;
-        let _response = self.client.receive();
+        self._send(String::from("rset"))?;
+        let _response = self._receive();
         if let Ok(response) = _response{
             return parse_response(response);
         }else if let Err(error) = _response{ return Err(error); }
-        return Err(MailError::TCPConnectFailError)
+        return Err(MailError::Transport(TransportError::NotConnected))
     }
 
 
     //-----------------以下为业务函数------------------------------------------
 
-
-    pub fn connect(&mut self){
-        if let State::Init = self.state{
-            if let Ok(response) = self._connect(){
-                println!("客户端 <{}> 连接成功", self.name);
-                self.print_debug(&response);
-            }else{
-                println!("客户端 <{}> 发起连接失败，请使用 -reset 进行重置", self.name);
-            }
-        }else{
-            println!("客户端 <{}> 处于已连接状态，若要重置连接，请使用 -reset 命令", self.name);
+    fn capabilities(&mut self) -> Result<Capabilities, MailError>{
+        //服务器以 -ERR 回应 CAPA 时视为没有任何扩展，连接错误照常返回且不缓存
+        if let Some(capabilities) = &self.capabilities{
+            return Ok(capabilities.clone());
         }
+        let capabilities = match self._capa()?{
+            Response::Ok(response) => Capabilities::parse(&response),
+            Response::Err(_) => Capabilities::default()
+        };
+        self.capabilities = Some(capabilities.clone());
+        Ok(capabilities)
     }
 
-    pub fn connection_test(&mut self){
-        if let State::Init = self.state{
-            println!("客户端 <{}> 尚未发起连接，请先尝试连接", self.name);
-            return;
-        }
-        println!("正在测试客户端 <{}> 的连接状态，该操作耗时较长，请耐心等待", self.name);
-        if let Ok(response) = self._noop(){
-            println!("客户端 <{}> 的连接正常", self.name);
-            self.print_debug(&response);
-        }else{
-            println!("客户端 <{}> 的连接已超时或丢失，请使用 -reset 进行重置", self.name);
-        }
+    fn supports(&mut self, capability: &str) -> Result<bool, MailError>{
+        Ok(self.capabilities()?.has(capability))
+    }
+
+    pub fn name(&self) -> &str{
+        &self.name
+    }
+
+    pub fn state(&self) -> &State{
+        &self.state
+    }
+
+    pub fn account(&self) -> Option<&str>{
+        self.account.as_deref()
+    }
+
+    pub fn security(&self) -> Security{
+        self.client.security()
     }
 
-    pub fn login(&mut self, account: String, password: String){
+    pub fn is_secure(&self) -> bool{
+        self.client.is_secure()
+    }
+
+    pub fn sync_config(&self) -> &SyncConfig{
+        &self.sync_config
+    }
+
+    pub fn delete_list(&self) -> &[usize]{
+        &self.dele_list
+    }
 
+    pub fn allows_plaintext_auth(&self) -> bool{
+        self.allow_plaintext_auth
+    }
+
+    pub fn set_allow_plaintext_auth(&mut self, allow: bool){
+        self.allow_plaintext_auth = allow;
+    }
+
+    pub fn set_timeouts(&mut self, timeouts: Timeouts){
+        self.client.set_timeouts(timeouts);
+    }
+
+    pub fn keepalive_interval(&self) -> Option<Duration>{
+        self.client.keepalive_interval()
+    }
+
+    pub fn is_debug(&self) -> bool{
+        self.client.is_debug()
+    }
+
+    pub fn set_debug(&mut self, debug: bool){
+        self.client.set_debug(debug);
+    }
+
+    pub fn take_debug_log(&mut self) -> Vec<String>{
+        self.client.take_debug_log()
+    }
+
+    fn require_authorized(&self) -> Result<(), MailError>{
         match &self.state{
-            State::Init => {
-                println!("客户端 <{}> 尚未发起连接，请先尝试连接", self.name);
-            }
-            State::TCPConnected => {
-                self.account = Some(account.clone());
-                self.password = Some(password.clone());
-                let user_result = self._user(account);
-                if let Ok(response) = user_result{
-                    self.print_debug(&response);
-                    if let Response::Err(info) = response{
-                        println!("客户端 <{}> 邮箱地址发送失败，服务器返回错误信息: <{}> ，请检查邮箱地址格式是否正确", self.name, info);
-                    }
-                }else if let Err(error) = user_result{
-                    println!("客户端 <{}> 邮箱地址发送失败，错误类型: <{:?}>", self.name, error);
-                }
-                let pass_result = self._pass(password);
-                if let Ok(response) = pass_result{
-                    self.print_debug(&response);
-                    if let Response::Err(info) = response{
-                        println!("客户端 <{}> 授权码发送失败，服务器返回错误信息: <{}> ，请检查邮箱地址格式是否正确", self.name, info);
-                    }else{
-                        println!("客户端 <{}> 已登录为 <{}>", self.name, self.account.as_ref().unwrap());
-                        self.state = State::Authorized;
-                    }
-                }else if let Err(error) = pass_result{
-                    println!("客户端 <{}> 授权码发送失败，错误类型: <{:?}>", self.name, error);
-                }
-            }
-            State::Authorized => {
-                if let Some(account) = &self.account{
-                    println!("客户端 <{}> 已登录为 <{}> ，若要重新登录请使用 -reset 进行重置", self.name, account);
-                }else{
-                    println!("未找到客户端 <{}> 的邮箱账号，请尝试使用 -reset 进行重置", self.name);
-                }
-            }
-            _ => {
-                println!("客户端 <{}> 登录时出现意外状态，请使用 -reset 进行重置", self.name);
-            }
+            State::Authorized => Ok(()),
+            _ => Err(MailError::ClientState(String::from("尚未登录")))
         }
     }
 
-    pub fn status_query(&mut self){
-        match &self.state{
-            State::Init => { println!("客户端 <{}> 尚未发起连接，请先尝试连接", self.name); return; },
-            State::TCPConnected => { println!("客户端 <{}> 尚未完成登录，请先尝试登录", self.name); return; },
-            State::Authorized => {
-                let status_result = self._stat();
-                if let Ok(response) = status_result{
-                    self.print_debug(&response);
-                    if let Response::Ok(info) = response{
-                        let parse_result = parse_status_response(info);
-                        if let Ok(status) = parse_result{
-                            println!("客户端 <{}> 的邮箱状态: 邮件总数:[{}] 邮件总字节数: [{}]", self.name, status.0, status.1);
-                        }else if let Err(error) = parse_result{
-                            println!("客户端 <{}> 邮箱状态获取失败，错误信息: {:?}", self.name, error);
-                        }
-                    }else if let Response::Err(info) = response{
-                        println!("邮箱状态获取失败，错误信息: {}", info);
-                    }
-                }else if let Err(error) = status_result{
-                    println!("客户端 <{}> 的连接已超时或丢失，请使用 -reset 进行重置", self.name);
-                }
-            }
-            _ => {
-                println!("客户端 <{}> 登录时出现意外状态，请使用 -reset 进行重置", self.name);
-            }
+    pub fn open(&mut self) -> Result<(), MailError>{
+        if let State::Init = self.state{}else{
+            return Err(MailError::ClientState(String::from("已连接")));
         }
+        let response = self._connect()?;
+        self.log_debug(&response);
+        expect_ok(response)?;
+        Ok(())
     }
 
-    pub fn debug(&mut self){
-        self.debug = !self.debug;
-        if self.debug{
-            println!("已切换至 debug 模式");
-        }else{
-            println!("已切换至 普通 模式");
+    pub fn noop(&mut self) -> Result<(), MailError>{
+        //RFC 1939: NOOP 只能在登录后的 TRANSACTION 状态使用
+        self.require_authorized()?;
+        let response = self._noop()?;
+        self.log_debug(&response);
+        expect_ok(response)?;
+        Ok(())
+    }
+
+    pub fn keepalive(&mut self) -> Result<bool, MailError>{
+        if !matches!(self.state, State::Authorized) || !self.client.needs_keepalive(){
+            return Ok(false);
         }
+        self.noop()?;
+        Ok(true)
     }
 
-    pub fn mail_info_short(&mut self, order_number: usize){
+    pub fn authorize(&mut self, account: String, password: String) -> Result<(), MailError>{
         match &self.state{
-            State::Init => { println!("客户端 <{}> 尚未发起连接，请先尝试连接", self.name); return; },
-            State::TCPConnected => { println!("客户端 <{}> 尚未完成登录，请先尝试登录", self.name); return; },
-            State::Authorized => {
-                let list_result = self._list(order_number);
-                if let Ok(response) = list_result{
-                    self.print_debug(&response);
-                    if let Response::Ok(info) = response{
-                        let parse_result = parse_status_response(info);
-                        if let Ok(status) = parse_result{
-                            println!("客户端 <{}> 的邮件信息: 第 <{}> 封邮件总字节数: [{}]", self.name, status.0, status.1);
-                        }else if let Err(error) = parse_result{
-                            println!("客户端 <{}> 邮箱状态获取失败，错误信息: {:?}", self.name, error);
-                        }
-                    }else if let Response::Err(info) = response{
-                        println!("邮箱状态获取失败，错误信息: {}", info);
-                    }
-                }else if let Err(error) = list_result{
-                    println!("客户端 <{}> 的连接已超时或丢失，请使用 -reset 进行重置", self.name);
-                }
+            State::Init => { return Err(MailError::ClientState(String::from("尚未发起连接"))); }
+            State::TCPConnected => {}
+            State::Authorized => { return Err(MailError::ClientState(String::from("已登录"))); }
+        }
+        self.account = Some(account.clone());
+        self.password = Some(password.clone());
+
+        //starttls 模式下通过 STLS 升级连接，升级后之前的 CAPA 结果作废；服务器不支持时不降级为明文
+        if self.client.security() == Security::StartTls && !self.client.is_secure(){
+            if !self.supports("STLS")?{
+                return Err(AuthError::TlsRequired(String::from("STLS")).into());
             }
-            _ => {
-                println!("客户端 <{}> 登录时出现意外状态，请使用 -reset 进行重置", self.name);
+            let response = self._stls()?;
+            self.log_debug(&response);
+            expect_ok(response)?;
+            if let Err(error) = self.client.starttls(){
+                self.state = State::Init;
+                return Err(error);
             }
+            self.capabilities = None;
+        }
+
+        //未加密连接上除非用户明确允许，否则拒绝任何登录方式
+        let capabilities = self.capabilities()?;
+        let login = self.select_login(&capabilities)?;
+        if !self.client.is_secure() && !self.allow_plaintext_auth{
+            let method = match &login{
+                LoginMethod::Sasl(mechanism) => mechanism.clone(),
+                LoginMethod::Apop(_) => String::from("APOP"),
+                LoginMethod::UserPass => String::from("USER/PASS")
+            };
+            return Err(AuthError::TlsRequired(method).into());
         }
+        match login{
+            LoginMethod::Sasl(mechanism) => self.authenticate(mechanism, account, password)?,
+            LoginMethod::Apop(timestamp) => self.apop(account, &timestamp, &password)?,
+            LoginMethod::UserPass => self.user_pass(account, password)?
+        }
+        //登录后服务器可能声明不同的扩展
+        self.capabilities = None;
+        self.state = State::Authorized;
+        Ok(())
     }
 
-    pub fn get_mail_by_order(&mut self, order_number: usize){
-        match &self.state{
-            State::Init => { println!("客户端 <{}> 尚未发起连接，请先尝试连接", self.name); return; },
-            State::TCPConnected => { println!("客户端 <{}> 尚未完成登录，请先尝试登录", self.name); return; },
-            State::Authorized => {
-                let retr_result = self._retr(order_number);
-                if let Ok(response) = retr_result{
-                    self.print_debug(&response);
-                    if let Response::Ok(res) = response{
-                        let mail = parse_raw_mail(res);
-                        if let Ok(pop3m) = mail{
-                            println!("--- [{}] 号邮件详细内容---", order_number);
-                            println!("发件人: {}", pop3m.from.unwrap_or_else(||{ (String::from("No Info"), String::from("No Info"))}).0);
-                            println!("收件人: {}", pop3m.to.unwrap_or_else(||{ (String::from("No Info"), String::from("No Info"))}).0);
-                            println!("邮件主题: {}", pop3m.subject.unwrap_or_else(||{ String::from("No Info")}));
-                            println!("时间: {}", pop3m.time.unwrap_or_else(|| {String::from("No Info")}));
-                            println!("邮件内容---");
-                            println!("{}", pop3m.plain.unwrap_or_else(||{ String::from("No Info")}));
-
-                        }else if let Err(error) = mail{
-                            println!("客户端 <{}> 获取邮件详细内容失败，错误信息: {:?}", self.name, error);
-                        }
-                    }
-                }else if let Err(error) = retr_result{
-                    println!("客户端 <{}> 请求邮件内容出现问题，错误内容: [{:?}]", self.name, error);
-                }
-            }
-            _ => {
-                println!("客户端 <{}> 登录时出现意外状态，请使用 -reset 进行重置", self.name);
+    fn select_login(&self, capabilities: &Capabilities) -> Result<LoginMethod, MailError>{
+        //用户指定的验证方式优先；否则依次选择 CRAM-MD5、APOP、服务器声明的其他 SASL 方式，最后使用 USER/PASS
+        if let Some(forced) = &self.auth_mechanism{
+            return sasl::select(&capabilities.sasl, Some(forced)).map(LoginMethod::Sasl);
+        }
+        if capabilities.sasl.iter().any(|mechanism| mechanism == "CRAM-MD5"){
+            return Ok(LoginMethod::Sasl(String::from("CRAM-MD5")));
+        }
+        if let Some(timestamp) = self.greeting.as_deref().and_then(apop_timestamp){
+            return Ok(LoginMethod::Apop(timestamp));
+        }
+        if !capabilities.sasl.is_empty(){
+            if let Ok(mechanism) = sasl::select(&capabilities.sasl, None){
+                return Ok(LoginMethod::Sasl(mechanism));
             }
         }
+        Ok(LoginMethod::UserPass)
     }
 
-    pub fn mail_list(&mut self){
-        match &self.state{
-            State::Init => { println!("客户端 <{}> 尚未发起连接，请先尝试连接", self.name); return; },
-            State::TCPConnected => { println!("客户端 <{}> 尚未完成登录，请先尝试登录", self.name); return; },
-            State::Authorized => {
-                let stat = self._stat();
-                let mut total: usize = 0;
-                if let Ok(response) = stat{
-                    self.print_debug(&response);
-                    if let Response::Ok(ok_response) = response{
-                        let parse_result = parse_status_response(ok_response);
-                        if let Ok(ok_parse) = parse_result{
-                            total = ok_parse.0;
-                            println!("---------客户端 <{}> 的邮件列表: ---------", self.name);
-                            println!("-> 邮件数: [{}]", total);
-                        }else if let Err(err_parse) = parse_result{
-                            println!("客户端 <{}> 请求邮件信息出现问题，错误内容: [{:?}]", self.name, err_parse);
-                        }
-                    }else if let Response::Err(err_response) = response{
-                        println!("客户端 <{}> 请求邮件信息出现问题，错误内容: [{}]", self.name, err_response);
-                    }
-                }else if let Err(error) = stat{
-                    println!("客户端 <{}> 请求邮件信息出现问题，错误信息: [{:?}]", self.name, error);
-                }
-
-                let mut counter: usize = 1;
-                while counter <= total{
-                    let mail_retr = self._retr(counter);
-                    if let Ok(response) = mail_retr{
-                        self.print_debug(&response);
-                        if let Response::Ok(ok_response) = response{
-                            let parse_result = parse_raw_mail(ok_response);
-                            if let Ok(mail) = parse_result{
-                                println!("({}) -> [{}] From [{}] to [{}]", counter, mail.subject.unwrap(), mail.from.unwrap().1, mail.to.unwrap().1);
-                            }else if let Err(error) = parse_result{
-                                println!("客户端 <{}> 对邮件进行解析时出现错误，错误内容: [{:?}]", self.name, error);
-                            }
-                        }else if let Response::Err(err_response) = response{
-                            println!("客户端 <{}> 请求邮件信息出现问题，错误内容: [{:?}]", self.name, err_response);
-                        }
-                    }else if let Err(error) = mail_retr{
-                        println!("客户端 <{}> 请求邮件列表出现问题，错误信息: [{:?}]({})", self.name, error, counter);
-                    }
-                    counter += 1;
-                }
-                println!();
+    fn authenticate(&mut self, mechanism_name: String, account: String, password: String) -> Result<(), MailError>{
+        let mut mechanism = match sasl::create(&mechanism_name, account, password){
+            Some(mechanism) => mechanism,
+            None => { return Err(AuthError::MechanismNotSupported(mechanism_name).into()); }
+        };
+        //auth <mechanism>，之后逐个回应服务器的 "+ " 质询直至返回 +OK
+        let initial_response = mechanism.initial_response();
+        let mut auth_res = self._auth(mechanism_name, initial_response);
+        let mut counter: usize = 0;
+        loop{
+            let line = auth_res?.trim_end().to_string();
+            self.client.log_debug(format!("Response: {:?}", line));
+            if line.starts_with("+OK"){
+                return Ok(());
             }
-            _ => {
-                println!("客户端 <{}> 登录时出现意外状态，请使用 -reset 进行重置", self.name);
+            if !line.starts_with('+') || counter > 8{
+                return Err(AuthError::Rejected(ReplyError::pop3(line.trim_start_matches("-ERR"))).into());
             }
+            let challenge = line[1..].trim().to_string();
+            let reply = match base64::decode(&challenge){
+                Ok(decoded) => mechanism.respond(&decoded),
+                Err(_) => Err(AuthError::ChallengeDecode(challenge).into())
+            };
+            auth_res = match reply{
+                Ok(reply) => self._send_base64(reply),
+                Err(error) => {
+                    //以 * 取消验证过程
+                    let _ = self._send(String::from("*"));
+                    let _ = self._receive();
+                    Err(error)
+                }
+            };
+            counter += 1;
         }
     }
 
-    pub fn quit(&mut self){
-        let quit_result = self._quit();
-        if let Ok(response) = quit_result{
-            self.print_debug(&response);
-            if let Response::Ok(ok_res) = response{
-                println!("客户端 <{}> 已退出登录", self.name);
-                self.client.shutdown();
-                self.state = State::Init;
-                if self.dele_list.len() > 0{
-                    println!("以下序号的邮件将被删除:");
-                    println!("-> {:?}", self.dele_list);
-                }
-                self.dele_list.clear();
-            }else if let Response::Err(err_res) = response{
-                println!("客户端 <{}> 退出失败，错误信息: <{}>", self.name, err_res);
+    fn apop(&mut self, account: String, timestamp: &str, password: &str) -> Result<(), MailError>{
+        let response = self._apop(account, apop_digest(timestamp, password))?;
+        self.log_debug(&response);
+        expect_login_ok(response)
+    }
+
+    fn user_pass(&mut self, account: String, password: String) -> Result<(), MailError>{
+        //USER 失败时不再发送 PASS
+        let response = self._user(account)?;
+        self.log_debug(&response);
+        expect_login_ok(response)?;
+        let response = self._pass(password)?;
+        self.log_debug(&response);
+        expect_login_ok(response)
+    }
+
+    pub fn status(&mut self) -> Result<(usize, usize), MailError>{
+        //(邮件总数, 总字节数)
+        self.require_authorized()?;
+        let response = self._stat()?;
+        self.log_debug(&response);
+        parse_status_response(expect_ok(response)?)
+    }
+
+    pub fn mail_size(&mut self, order_number: usize) -> Result<usize, MailError>{
+        self.require_authorized()?;
+        let response = self._list(order_number)?;
+        self.log_debug(&response);
+        Ok(parse_status_response(expect_ok(response)?)?.1)
+    }
+
+    pub fn retrieve(&mut self, order_number: usize) -> Result<Vec<u8>, MailError>{
+        //返回原始邮件内容，可使用 parse_raw_mail 解析
+        self.require_authorized()?;
+        self._retr(order_number)?.map_err(|info| ReplyError::pop3(&info).into())
+    }
+
+    pub fn open_store(&self) -> Result<Store, MailError>{
+        //每个账号使用存储目录下的一个 Maildir
+        let account = self.account.clone().unwrap_or_default();
+        Store::open(&std::path::Path::new(&self.sync_config.store_dir).join(sanitize_filename(&account)))
+    }
+
+    pub fn keep_local(&mut self, order_number: usize, raw: &[u8]) -> Result<bool, MailError>{
+        //将查看过的邮件保存到本地 Maildir 并标记为已读；服务器不支持 UIDL 时无法去重，不保存并返回 false
+        let uid = match self._uidl_one(order_number)?{
+            Response::Ok(response) => match parse_uidl_response(response){
+                Ok(entries) if entries.len() == 1 => entries[0].1.clone(),
+                _ => { return Ok(false); }
+            },
+            Response::Err(_) => { return Ok(false); }
+        };
+        let mut store = self.open_store()?;
+        store.insert(&uid, raw)?;
+        let key = store.get(&uid).map(|message| message.key.clone()).unwrap_or_default();
+        store.mark_seen(&key)?;
+        Ok(true)
+    }
+
+    pub fn export_local(&self, path: &str) -> Result<usize, MailError>{
+        //将本地存储中的全部邮件追加到 mbox 文件，不需要连接服务器，返回导出的邮件数
+        let store = self.open_store()?;
+        let mut exported: Vec<MboxMessage> = Vec::new();
+        for message in store.local_messages()?{
+            match std::fs::read(&message.path){
+                Ok(raw) => { exported.push(MboxMessage::new(&raw)); }
+                Err(error) => { return Err(MailError::StoreFile{ path: message.path.display().to_string(), source: error }); }
             }
-        }else if let Err(error) = quit_result{
-            println!("客户端 <{}> 退出失败，错误信息: <{:?}>", self.name, error)
         }
+        mbox::append(std::path::Path::new(path), &exported)?;
+        Ok(exported.len())
     }
 
-    pub fn delete(&mut self, order_number: usize){
-        if self.dele_list.contains(&order_number){
-            println!("客户端 <{}>的 [{}]号 邮件添加到删除列表中失败，错误信息: 邮件已在删除列表中", self.name, order_number);
-            return;
+    pub fn fetch_new(&mut self) -> Result<SyncReport, MailError>{
+        self.require_authorized()?;
+        let entries = parse_uidl_response(expect_ok(self._uidl()?)?)?;
+        let mut store = self.open_store()?;
+
+        //只下载本地存储中没有的邮件，单封邮件被服务器拒绝时继续下载其他邮件
+        let mut report = SyncReport{ server: entries.len(), ..SyncReport::default() };
+        for (order, uid) in &entries{
+            if store.contains(uid){
+                continue;
+            }
+            match self._retr(*order)?{
+                Ok(raw) => {
+                    store.insert(uid, &raw)?;
+                    report.fetched.push(summarize_mail(*order, &raw, false));
+                }
+                Err(_) => { report.failed.push(*order); }
+            }
         }
-        let dele_result = self._dele(order_number);
-        if let Ok(response) = dele_result{
-            self.print_debug(&response);
-            if let Response::Ok(ok_res) = response{
-                println!("客户端 <{}> 已将 [{}]号 邮件添加到删除列表中，将会在退出时执行删除", self.name, order_number);
-                self.dele_list.push(order_number);
-            }else if let Response::Err(err_res) = response{
-                println!("客户端 <{}>的 [{}]号 邮件添加到删除列表中失败，错误信息: <{}>", self.name, order_number, err_res)
+        report.local = store.messages().len();
+
+        //按保留策略将已下载且到期的邮件加入删除列表，退出时执行删除
+        let now = Utc::now().timestamp();
+        for (order, uid) in &entries{
+            let fetched_at = match store.get(uid){
+                Some(message) => message.fetched_at,
+                None => { continue; }
+            };
+            if self.dele_list.contains(order) || !self.sync_config.retention.should_delete(fetched_at, now){
+                continue;
+            }
+            if let Response::Ok(_) = self._dele(*order)?{
+                self.dele_list.push(*order);
+                report.expired.push(*order);
             }
-        }else if let Err(error) = dele_result{
-            println!("客户端 <{}> 的 [{}]号 邮件添加到删除列表中失败，错误信息: <{:?}>", self.name, order_number, error);
         }
+        Ok(report)
     }
 
-    pub fn reset_delete(&mut self){
-        let reset_result = self._rset();
-        if let Ok(response) = reset_result{
-            self.print_debug(&response);
-            if let Response::Ok(ok_res) = response{
-                println!("客户端 <{}> 的删除列表已重置", self.name);
-                self.dele_list.clear();
-            }else if let Response::Err(error) = response{
-                println!("客户端 <{}> 删除列表重置失败，错误信息: {}", self.name, error)
-            }
-        }else if let Err(error) = reset_result{
-            println!("客户端 <{}> 删除列表重置失败，错误信息: {:?}", self.name, error);
+    pub fn summaries(&mut self) -> Result<Vec<MailSummary>, MailError>{
+        self.require_authorized()?;
+        let list = self._list_all()?;
+        self.log_debug(&list);
+        let entries = parse_list_response(expect_ok(list)?)?;
+
+        //只获取邮件头部，服务器不支持 TOP 时下载整封邮件
+        let headers_only = self.supports("TOP")?;
+        let mut summaries: Vec<MailSummary> = Vec::new();
+        for (order, size) in entries{
+            let response = if headers_only { self._top(order, 0)? } else { self._retr(order)? };
+            let raw = response.map_err(|info| ReplyError::pop3(&info))?;
+            let mut summary = summarize_mail(order, &raw, headers_only);
+            summary.size = size;
+            summaries.push(summary);
         }
+        Ok(summaries)
     }
 
-    pub fn display_delete_list(&mut self){
-        if self.dele_list.len() > 0{
-            println!("客户端 <{}> 的删除列表如下: ", self.name);
-            println!("-> {:?}", self.dele_list);
-        }else{
-            println!("客户端 <{}> 的删除列表中无内容", self.name);
+    pub fn close(&mut self) -> Result<Vec<usize>, MailError>{
+        //服务器在 QUIT 后执行删除，返回被删除的邮件序号
+        let response = self._quit()?;
+        self.log_debug(&response);
+        expect_ok(response)?;
+        self.client.shutdown();
+        self.state = State::Init;
+        Ok(std::mem::take(&mut self.dele_list))
+    }
+
+    pub fn delete(&mut self, order_number: usize) -> Result<(), MailError>{
+        //已在删除列表中的邮件不再重复发送 DELE
+        self.require_authorized()?;
+        if self.dele_list.contains(&order_number){
+            return Ok(());
         }
-        println!();
+        let response = self._dele(order_number)?;
+        self.log_debug(&response);
+        expect_ok(response)?;
+        self.dele_list.push(order_number);
+        Ok(())
+    }
+
+    pub fn reset_delete(&mut self) -> Result<(), MailError>{
+        self.require_authorized()?;
+        let response = self._rset()?;
+        self.log_debug(&response);
+        expect_ok(response)?;
+        self.dele_list.clear();
+        Ok(())
     }
 
     pub fn reset_client(&mut self){
         self.client.shutdown();
         self.state = State::Init;
-        self.debug = false;
+        self.client.set_debug(false);
         self.dele_list = Vec::new();
+        self.capabilities = None;
+        self.greeting = None;
     }
 
 }
 
-struct POP3Mail{
-    time: Option<String>,
-    from: Option<(String, String)>,
-    to: Option<(String, String)>,
-    subject: Option<String>,
-    plain: Option<String>,
-    html: Option<String>
+pub struct POP3Mail{
+    pub time: Option<String>,
+    pub from: Option<(String, String)>,
+    pub to: Option<(String, String)>,
+    pub subject: Option<String>,
+    pub plain: Option<String>,
+    pub html: Option<String>,
+    //正文实际使用的字符集，优先取纯文本部分
+    pub charset: Option<String>,
+    pub attachments: Vec<ReceivedAttachment>,
+    pub mime: Option<MimePart>
+}
+
+#[derive(Debug, Clone)]
+pub struct ReceivedAttachment{
+    pub filename: String,
+    pub mime_type: String,
+    //解码后的字节数
+    pub size: usize,
+    pub content_id: Option<String>,
+    pub data: Vec<u8>
 }
 
 impl POP3Mail{
@@ -530,57 +745,67 @@ impl POP3Mail{
             to: None,
             subject: None,
             plain: None,
-            html: None
+            html: None,
+            charset: None,
+            attachments: Vec::new(),
+            mime: None
         }
     }
 
-    fn check_integrity(&self) -> usize{
-        if let Some(s) = &self.time{
-            if let Some(s) = &self.from{
-                if let Some(s) = &self.to{
-                    if let Some(s) = &self.plain{
-                        if let Some(s) = &self.html{
-                            return 0;
-                        }
-                        return 5;
-                    }
-                    return 4;
-                }
-                return 3;
-            }
-            return 2;
+    fn validate(&self) -> Result<(), MessageError>{
+        //密送、邮件列表等邮件常缺少 To 或 Date，只有附件的邮件没有正文，显示时均以 No info 代替；只要求有发件人
+        if self.from.is_none(){
+            return Err(MessageError::MissingHeader("From"));
         }
-        return 1;
+        Ok(())
     }
 
-    fn display(&self){
-        println!("time: {:?}", self.time);
-        println!("from: {:?}", self.from);
-        println!("to: {:?}", self.to);
-        println!("subject: {:?}", self.subject);
-        println!("plain: {:?}", self.plain);
-        println!("html: {:?}", self.html);
+    pub fn to_draft(&self) -> SMTPMail{
+        //转换为可重新发送的草稿，收件人取完整的 To/Cc 列表；附件只存在于内存中，无法作为草稿附件
+        let mut draft = SMTPMail::new();
+        let addresses = |name: &str| -> Vec<String>{
+            self.mime.as_ref().and_then(|root| root.header(name)).map(|value|{
+                mime::split_unquoted(value, ',').into_iter().map(|address| address.trim().to_string()).filter(|address| !address.is_empty())
+                    .map(|address|{
+                        let address = Address::parse(&address);
+                        match address.name{
+                            Some(name) => format!("{} <{}>", codec::decode_header(&name), address.email),
+                            None => address.email
+                        }
+                    }).collect()
+            }).unwrap_or_default()
+        };
+        draft.from = addresses("From").into_iter().next();
+        draft.to = addresses("To");
+        draft.cc = addresses("Cc");
+        draft.subject = self.subject.clone();
+        draft.content = self.plain.as_ref().map(|plain| plain.lines().map(|line| line.to_string()).collect());
+        draft.html = self.html.clone();
+        draft
     }
+}
 
-    fn check_head_integrity(&self) -> usize{
-        if let Some(_) = &self.time{
-            if let Some(_) = &self.from{
-                if let Some(_) = &self.to{
-                    return 0;
-                }
-                return 3;
-            }
-            return 2;
-        }
-        return 1;
+
+fn expect_ok(response: Response) -> Result<String, MailError>{
+    match response{
+        Response::Ok(info) => Ok(info),
+        Response::Err(info) => Err(ReplyError::pop3(&info).into())
     }
 }
 
+fn expect_login_ok(response: Response) -> Result<(), MailError>{
+    //登录阶段的 -ERR 表示账号或授权码被拒绝
+    match response{
+        Response::Ok(_) => Ok(()),
+        Response::Err(info) => Err(AuthError::Rejected(ReplyError::pop3(&info)).into())
+    }
+}
 
 fn parse_response(_response: String) -> Result<Response, MailError>{
     let response = _response.trim_end().to_string();
-    let regex_ok = Regex::new(r"^\+OK (.*)$").unwrap();
-    let regex_err = Regex::new(r"^-ERR (.*)$").unwrap();
+    //状态行后的说明文字是可选的
+    let regex_ok = Regex::new(r"^\+OK ?(.*)$").unwrap();
+    let regex_err = Regex::new(r"^-ERR ?(.*)$").unwrap();
 
     return if regex_ok.is_match(&response) {
         let mut ok_cap = regex_ok.captures(&response);
@@ -591,7 +816,7 @@ fn parse_response(_response: String) -> Result<Response, MailError>{
         let body = err_cap.unwrap();
         Ok(Response::Err(body[1].to_string().clone()))
     } else {
-        Err(POP3ResponseParseError(response))
+        Err(ParseError::Response(Protocol::POP3, response).into())
     }
 }
 
@@ -608,158 +833,262 @@ fn parse_status_response(_response: String) -> Result<(usize, usize), MailError>
                 return Ok((p1, p2));
             }
         }
-        Err(MailError::POP3StatusParseError(format!("邮箱状态解析失败: [{}]", response)))
+        Err(ParseError::Listing(response).into())
     } else {
-        Err(MailError::POP3StatusParseError(format!("邮箱状态解析失败: [{}]", response)))
+        Err(ParseError::Listing(response).into())
     }
 }
 
-fn parse_retr_octets_count(_response: String) -> Result<usize, MailError>{
-    let response = _response.trim_end().to_string();
-    let octets_count_regex = Regex::new(r"^(\d+) octets$").unwrap();
-    if octets_count_regex.is_match(&response){
-        let mut cap = octets_count_regex.captures(&response);
-        let _counter = cap.unwrap();
-        let counter = _counter[1].to_string().trim().parse::<usize>().unwrap();
-        return Ok(counter);
-    }else{
-        return Err(POP3ResponseParseError(response));
+fn parse_uidl_response(response: String) -> Result<Vec<(usize, String)>, MailError>{
+    //将 uidl 命令的多行响应解析为 (邮件序号, 唯一标识) 列表
+    let mut entries: Vec<(usize, String)> = Vec::new();
+    for line in response.lines(){
+        let mut fields = line.split_whitespace();
+        match (fields.next().map(|order| order.parse::<usize>()), fields.next()){
+            (Some(Ok(order)), Some(uid)) => { entries.push((order, uid.to_string())); }
+            _ => { return Err(ParseError::Listing(line.to_string()).into()); }
+        }
     }
-    return Ok(0);
+    Ok(entries)
 }
 
-fn parse_raw_mail(raw: String) -> Result<POP3Mail, MailError>{
-    let mut mail = POP3Mail::new();
-    let lines = raw.lines();
-    let time_regex = Regex::new(r"^Date: (.+)$").unwrap();
-    let from_regex = Regex::new("^From: \"(.*)\" <(.+)>").unwrap();
-    let to_regex = Regex::new("^To: \"(.*)\" <(.+)>").unwrap();
-    let to1_regex = Regex::new("^To: (.*)").unwrap();
-    let subject_regex = Regex::new("^Subject: (.*)").unwrap();
-    let boundary_regex = Regex::new("^\tboundary=\"(.*)\"").unwrap();
-    let mut boundary = String::from("");
-    let mut lines1 = lines.clone();
-    for line in lines{
-        if time_regex.is_match(line){
-            let mut cap = time_regex.captures(line).unwrap();
-            mail.time.replace(cap[1].to_string());
-        }else if from_regex.is_match(line){
-            let mut cap = from_regex.captures(line).unwrap();
-            mail.from.replace((cap[1].to_string(), cap[2].to_string()));
-        }else if to_regex.is_match(line){
-            let mut cap = to_regex.captures(line).unwrap();
-            mail.to.replace((cap[1].to_string(), cap[2].to_string()));
-        }else if subject_regex.is_match(line){
-            let mut cap = subject_regex.captures(line).unwrap();
-            mail.subject.replace(cap[1].to_string());
-        }else if boundary_regex.is_match(line){
-            let mut cap = boundary_regex.captures(line).unwrap();
-            boundary = cap[1].to_string();
-            break;
-        }else if to1_regex.is_match(line){
-            let mut cap = to1_regex.captures(line).unwrap();
-            mail.to.replace(("".to_string(), cap[1].to_string()));
+fn apop_timestamp(greeting: &str) -> Option<String>{
+    //RFC 1939 7: 问候语中形如 <进程号.时钟@主机名> 的时间戳，包括尖括号
+    let start = greeting.find('<')?;
+    let end = start + greeting[start..].find('>')?;
+    let timestamp = &greeting[start..=end];
+    if timestamp.contains('@') { Some(timestamp.to_string()) } else { None }
+}
+
+fn apop_digest(timestamp: &str, password: &str) -> String{
+    md5::compute(format!("{}{}", timestamp, password)).0.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn parse_list_response(response: String) -> Result<Vec<(usize, usize)>, MailError>{
+    //将不带参数的 list 命令的多行响应解析为 (邮件序号, 字节数) 列表
+    response.lines().map(|line| parse_status_response(line.to_string())).collect()
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MailSummary{
+    pub order: usize,
+    pub size: usize,
+    pub date: String,
+    pub from: String,
+    pub to: String,
+    pub subject: String,
+    pub has_attachments: bool
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SyncReport{
+    //服务器上的邮件数与同步后本地存储中的邮件数
+    pub server: usize,
+    pub local: usize,
+    pub fetched: Vec<MailSummary>,
+    //服务器拒绝下载的邮件序号与按保留策略加入删除列表的邮件序号
+    pub failed: Vec<usize>,
+    pub expired: Vec<usize>
+}
+
+fn summarize_mail(order: usize, raw: &[u8], headers_only: bool) -> MailSummary{
+    //只有头部时无法确定各部分的内容，以 multipart/mixed 或整封邮件为附件作为判断依据
+    let root = MimePart::parse(raw);
+    let has_attachments = if headers_only{
+        root.mime_type == "multipart/mixed" || root.is_attachment()
+    }else{
+        parse_raw_mail(raw).map(|mail| !mail.attachments.is_empty()).unwrap_or(false)
+    };
+    let date = root.header("Date").map(|date|{
+        match DateTime::parse_from_rfc2822(date.trim()){
+            Ok(date) => date.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string(),
+            Err(_) => date.trim().to_string()
         }
+    });
+    MailSummary{
+        order,
+        size: raw.len(),
+        date: date.unwrap_or_else(|| String::from("No Info")),
+        from: root.header("From").and_then(parse_first_address).map(|from| from.1).unwrap_or_else(|| String::from("No Info")),
+        to: root.header("To").and_then(parse_first_address).map(|to| to.1).unwrap_or_else(|| String::from("No Info")),
+        subject: root.header("Subject").map(codec::decode_header).unwrap_or_else(|| String::from("* Mail No Subject *")),
+        has_attachments
     }
+}
 
-    if &boundary == ""{
-        return Err(MailError::POP3MailIntegrityFailedError(7))// error code 7: No boundary sign found
-    }
-    let boundary_regex = Regex::new(&format!("(.*){}(.*)", boundary)).unwrap();
+struct MultilineReader{
+    data: Vec<u8>,
+    finished: bool
+}
 
+impl MultilineReader{
+    //RFC 1939 3: 多行响应以单独的 "." 行结束，以 "." 开头的内容行在发送时多加了一个 "."
+    fn new() -> MultilineReader{
+        MultilineReader{ data: Vec::new(), finished: false }
+    }
 
-    let mut parsing_plain_text: bool = false;
-    let mut parsing_html: bool = false;
-    let mut plain_text_result = String::new();
-    let mut html_result = String::new();
-    let content_type_regex = Regex::new("Content-Type: (.+); charset=(.+)").unwrap();
-    let content_transfer_encoding_regex = Regex::new("Content-Transfer-Encoding: (.+)").unwrap();
-    let mut _7bit_mode = false;
-    for line in lines1{
-        if content_type_regex.is_match(line){
-            let cap = content_type_regex.captures(line).unwrap();
-            if &cap[2] == "GBK"{
-                match & cap[1]{
-                    "text/plain" => { parsing_plain_text = true; continue; }
-                    "text/html" => { parsing_html = true; continue; }
-                    _ => { return Err(MailError::POP3MailFormatNotSupportError(line.to_string())); }
-                }
-            }else { return Err(MailError::POP3MailFormatNotSupportError(line.to_string())); }
+    fn feed(&mut self, line: &[u8]){
+        //每次传入包含换行符的完整一行
+        if self.finished{
+            return;
         }
-        if content_transfer_encoding_regex.is_match(line){
-            let cap = content_transfer_encoding_regex.captures(line).unwrap();
-            match &cap[1]{
-                "base64" => {
+        if line == b".\r\n" || line == b".\n"{
+            self.finished = true;
+            return;
+        }
+        self.data.extend_from_slice(line.strip_prefix(b".").unwrap_or(line));
+    }
 
-                }
-                "7bit" => {
-                    _7bit_mode = true;
-                }
-                _ => {
-                    return Err(MailError::POP3MailFormatNotSupportError(line.to_string()));
-                }
-            }
+    fn is_finished(&self) -> bool{
+        self.finished
+    }
+}
+
+pub fn parse_raw_mail(raw: &[u8]) -> Result<POP3Mail, MailError>{
+    let root = MimePart::parse(raw);
+    let mut mail = POP3Mail::new();
+    if let Some(date) = root.header("Date"){
+        mail.time.replace(date.to_string());
+    }
+    if let Some(from) = root.header("From"){
+        mail.from = parse_first_address(from);
+    }
+    if let Some(to) = root.header("To"){
+        mail.to = parse_first_address(to);
+    }
+    if let Some(subject) = root.header("Subject"){
+        mail.subject.replace(codec::decode_header(subject));
+    }
+    let plain_part = root.find_body("text/plain");
+    let html_part = root.find_body("text/html");
+    //正文无法按传输编码解码时与附件一样保留原始内容，不影响整封邮件的解析
+    if let Some(part) = plain_part{
+        let (text, charset) = part.decode_text_lossy();
+        mail.plain.replace(text);
+        mail.charset.replace(charset);
+    }
+    if let Some(part) = html_part{
+        let (text, charset) = part.decode_text_lossy();
+        mail.html.replace(text);
+        if mail.charset.is_none(){
+            mail.charset.replace(charset);
+        }
+    }
+    if mail.plain.is_none(){
+        //只有 HTML 正文时生成纯文本内容用于显示
+        if let Some(html) = &mail.html{
+            mail.plain.replace(message::html_to_text(html).join("\n"));
+        }
+    }
+    for part in root.walk(){
+        let is_body = [plain_part, html_part].iter().any(|body| body.is_some_and(|body| std::ptr::eq(body, part)));
+        if part.is_multipart() || is_body{
+            continue;
+        }
+        //未标记为附件且没有文件名的文本部分视为正文的一部分
+        let content_id = part.header("Content-ID").map(|id| id.trim().trim_start_matches('<').trim_end_matches('>').to_string());
+        if !part.is_attachment() && part.filename().is_none() && content_id.is_none() && part.mime_type.starts_with("text/"){
             continue;
         }
+        //无法解码的附件保留原始内容，不影响整封邮件的解析
+        let data = part.decoded_body().unwrap_or_else(|_| part.body.clone());
+        let filename = part.filename().unwrap_or_else(||{
+            let extension = mime_guess::get_mime_extensions_str(&part.mime_type).and_then(|extensions| extensions.first()).map(|extension| format!(".{}", extension)).unwrap_or_default();
+            format!("attachment-{}{}", mail.attachments.len() + 1, extension)
+        });
+        mail.attachments.push(ReceivedAttachment{
+            filename,
+            mime_type: part.mime_type.clone(),
+            size: data.len(),
+            content_id,
+            data
+        });
+    }
+    mail.mime.replace(root);
 
-        if boundary_regex.is_match(line){
+    mail.validate()?;
 
-            if parsing_html && !_7bit_mode{
-                let error_copy = html_result.clone();
-                let decode_result = base64::decode(error_copy.clone());
-                if let Ok(ok_result) = decode_result{
-                    let from_utf8_result = GBK.decode(&ok_result, DecoderTrap::Strict);
-                    if let Ok(fu_result) = from_utf8_result{
-                        mail.html.replace(fu_result);
-                        parsing_plain_text = false;
-                    }else if let Err(error) = from_utf8_result{
-                        return Err(MailError::POP3MailDecodeError(error_copy.clone()));
-                    }
-                }else if let Err(error) = decode_result{
-                    return Err(MailError::POP3MailDecodeError(error_copy.clone()));
-                }
-                continue;
-            }else{
-                mail.html.replace(html_result.clone());
-                parsing_html = false;
-            }
-            if parsing_plain_text && !_7bit_mode{
-                let error_copy = plain_text_result.clone();
-                let decode_result = base64::decode(error_copy.clone());
-                if let Ok(ok_result) = decode_result{
-                    let from_utf8_result = GBK.decode(&ok_result, DecoderTrap::Strict);
-                    if let Ok(fu_result) = from_utf8_result{
-                        mail.plain.replace(fu_result);
-                        parsing_plain_text = false;
-                    }else if let Err(error) = from_utf8_result{
-                        return Err(MailError::POP3MailDecodeError(error_copy.clone()));
-                    }
-                }else if let Err(error) = decode_result{
-                    return Err(MailError::POP3MailDecodeError(error_copy.clone()));
-                }
-                continue;
-            }else{
-                mail.plain.replace(plain_text_result.clone());
-                parsing_plain_text = false;
-            }
-        }
+    return Ok(mail);
+}
 
-        if parsing_html{
-            html_result = html_result + line;
-            continue;
+pub struct MboxImport{
+    pub drafts: Vec<SMTPMail>,
+    //无法解析而跳过的邮件序号(从 1 开始)与原因
+    pub skipped: Vec<(usize, MailError)>
+}
+
+pub fn import_mbox(path: &str) -> Result<MboxImport, MailError>{
+    //附件不会导入
+    let mut import = MboxImport{ drafts: Vec::new(), skipped: Vec::new() };
+    for (index, message) in mbox::read(std::path::Path::new(path))?.iter().enumerate(){
+        match parse_raw_mail(&message.raw){
+            Ok(mail) => { import.drafts.push(mail.to_draft()); }
+            Err(error) => { import.skipped.push((index + 1, error)); }
         }
+    }
+    Ok(import)
+}
 
-        if parsing_plain_text{
-            plain_text_result = plain_text_result + line;
-            continue;
+pub fn sanitize_filename(filename: &str) -> String{
+    //去除路径分隔符、控制字符与 Windows 保留字符，避免写出保存目录或生成非法文件名
+    let mut name: String = filename.chars().map(|c| if c.is_control() || "/\\<>:\"|?*".contains(c) { '_' } else { c }).collect();
+    name = name.trim().trim_matches('.').trim().to_string();
+    let stem = name.split('.').next().unwrap_or_default().to_uppercase();
+    let reserved = ["CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9"];
+    if reserved.contains(&stem.as_str()){
+        name.insert(0, '_');
+    }
+    if name.is_empty(){
+        name = String::from("attachment");
+    }
+    //多数文件系统限制文件名不超过 255 字节，截断时保留扩展名
+    while name.len() > 255{
+        let (stem, extension) = split_extension(&name);
+        let mut stem = stem.to_string();
+        stem.pop();
+        if stem.is_empty(){
+            //按字节位置截断，避免切在多字节字符中间
+            let end = name.char_indices().take_while(|(index, c)| index + c.len_utf8() <= 255).last().map(|(index, c)| index + c.len_utf8()).unwrap_or(0);
+            name.truncate(end);
+            break;
         }
+        name = format!("{}{}", stem, extension);
     }
+    name
+}
 
-    if mail.check_integrity() != 0{
-        return Err(MailError::POP3MailIntegrityFailedError(mail.check_integrity()));
+fn split_extension(filename: &str) -> (&str, &str){
+    match filename.rfind('.'){
+        Some(dot) if dot > 0 => (&filename[..dot], &filename[dot..]),
+        _ => (filename, "")
     }
+}
 
-    return Ok(mail);
+pub fn save_attachment(attachment: &ReceivedAttachment, directory: &str) -> std::io::Result<std::path::PathBuf>{
+    //同名文件已存在时依次尝试 "名称 (1).扩展名"、"名称 (2).扩展名"...，使用 create_new 避免覆盖
+    let filename = sanitize_filename(&attachment.filename);
+    let (stem, extension) = split_extension(&filename);
+    std::fs::create_dir_all(directory)?;
+    let mut counter: usize = 0;
+    loop{
+        let candidate = if counter == 0 { filename.clone() } else { format!("{} ({}){}", stem, counter, extension) };
+        let path = std::path::Path::new(directory).join(candidate);
+        match std::fs::OpenOptions::new().write(true).create_new(true).open(&path){
+            Ok(mut file) => {
+                file.write_all(&attachment.data)?;
+                return Ok(path);
+            }
+            Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => { counter += 1; }
+            Err(error) => { return Err(error); }
+        }
+    }
+}
+
+pub fn parse_first_address(value: &str) -> Option<(String, String)>{
+    //返回 (显示名, 地址)，多个地址时只取第一个
+    let first = mime::split_unquoted(value, ',').into_iter().map(|address| address.trim().to_string()).find(|address| !address.is_empty())?;
+    let address = Address::parse(&first);
+    Some((address.name.map(|name| codec::decode_header(&name)).unwrap_or_default(), address.email))
 }
 
 // fn parse_raw_mail_smtp(raw: String) -> Result<POP3Mail, MailError>{
@@ -774,98 +1103,373 @@ fn parse_raw_mail(raw: String) -> Result<POP3Mail, MailError>{
 
 
 
-pub fn run(name: String, account: String, password: String, pop3_host: String){
-    let mut client = crate::pop3::Client::new(pop3_host, name);
-    client.account.replace(account);
-    client.password.replace(password);
-    loop{
-        let c = ||{
-            return if let State::Authorized = client.state {
-                "已登录".to_string()
-            } else { "未登录".to_string() }
-        };
-        println!("正在运行: pop3客户端 <{}> ({})，使用 -help 获得帮助", client.name, c());
-        let _input = util::input();
-        let mut __input = _input.split_whitespace();
-        if let Some(head) = __input.next(){
-            match head{
-                "-help" => {
-                    println!("-login  自动发起连接并尝试登录");
-                    println!("-test  测试连接状况");
-                    println!("-delete [order number(int):邮件列表中序号]  将指定序号的邮件标记为删除");
-                    println!("-list <delete:删除列表>/<mail:邮件列表(default)>");
-                    println!("-detail [order number(int):邮件列表中序号]  显示指定序号邮件的详细内容");
-                    println!("-reset <delete:删除列表>/<connection:连接信息(default)>  对指定内容进行重置");
-                    println!("-debug  切换模式(普通/debug)");
-                    println!("-quit <connection:tcp连接(default)>/<client:客户端>  执行quit命令，此时会执行删除操作");
-                }
-                "-login" => {
-                    client.connect();
-                    client.login(client.account.clone().unwrap(), client.password.clone().unwrap());
-                }
-                "-test" => {
-                    client.connection_test();
-                }
-                "-delete" => {
-                    if let Some(param) = __input.next(){
-                        let _order_number = param.trim().parse::<usize>();
-                        if let Ok(on) = _order_number{
-                            client.delete(on);
-                        }else { print_error(); }
-                    }
-                }
-                "-list" => {
-                    let k = __input.next();
-                    if let Some("delete") = k{
-                        client.display_delete_list();
-                    }else if let Some("mail") = k{
-                        client.mail_list();
-                    }else if None == k{
-                        client.mail_list();
-                    }else{
-                        print_error();
-                    }
-                }
-                "-detail" => {
-                    if let Some(_on) = __input.next(){
-                        if let Ok(on) = _on.trim().parse::<usize>(){
-                            client.get_mail_by_order(on);
-                        }else{ print_error(); }
-                    }else{
-                        print_error();
-                    }
-                }
-                "-reset" => {
-                    let k = __input.next();
-                    if let Some("delete") = k{
-                        client.reset_delete();
-                    }else if let Some("client") = k{
-                        client.reset_client();
-                    }else{
-                        print_error();
-                    }
-                }
-                "-debug" => {
-                    client.debug();
-                }
-                "-quit" => {
-                    let k = __input.next();
-                    if let Some("connection") = k{
-                        client.quit();
-                    }else if let Some("client") = k{
-                        client.quit();
-                        break;
-                    }else if None == k{
-                        client.quit();
-                    }else{
-                        print_error();
-                    }
-                }
-                _ => {
-                    print_error();
-                }
+pub fn default_port(security: &Security) -> u16{
+    match security{
+        Security::Tls => 995,
+        _ => 110
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::thread;
+    use crate::tcp::TlsConfig;
+    use crate::tcp::test_support::{CERT_PATH, converse, listen, stand_in_server};
+    use crate::error::IoOperation;
+
+    fn test_client(port: u16, security: Security) -> Client{
+        let mut client = Client::new(String::from("localhost"), String::from("tester"));
+        let mut tls_config = TlsConfig::new(security);
+        tls_config.ca_roots.push(String::from(CERT_PATH));
+        client.client.set_port(port);
+        client.client.set_tls_config(tls_config);
+        client
+    }
+
+    #[test]
+    fn login_upgrades_with_stls_and_uses_sasl(){
+        let (port, server) = stand_in_server(Security::StartTls, "+OK ready\r\n",
+            vec!["+OK\r\nSTLS\r\nSASL PLAIN\r\n.\r\n", "+OK Begin TLS\r\n"],
+            vec!["+OK\r\nSASL PLAIN\r\nUSER\r\n.\r\n", "+OK logged in\r\n"]);
+        let mut client = test_client(port, Security::StartTls);
+        client.open().unwrap();
+        client.authorize(String::from("user@localhost"), String::from("secret")).unwrap();
+        assert!(matches!(client.state, State::Authorized));
+        assert!(client.client.is_secure());
+        client.reset_client();
+        assert_eq!(server.join().unwrap(), vec![
+            String::from("capa"), String::from("stls"), String::from("<tls>"), String::from("capa"),
+            format!("auth plain {}", base64::encode("\0user@localhost\0secret"))
+        ]);
+    }
+
+    #[test]
+    fn lists_summaries_through_result_api(){
+        let (port, server) = stand_in_server(Security::Plain, "+OK ready\r\n", vec![
+            "+OK\r\nTOP\r\nUSER\r\n.\r\n", "+OK\r\n", "+OK\r\n", "+OK\r\n1 120\r\n.\r\n",
+            "+OK\r\nTOP\r\n.\r\n", "+OK\r\nSubject: hi\r\nFrom: alice@example.com\r\n\r\n.\r\n", "+OK bye\r\n"
+        ], Vec::new());
+        let mut client = test_client(port, Security::Plain);
+        client.set_allow_plaintext_auth(true);
+        client.open().unwrap();
+        client.authorize(String::from("user@localhost"), String::from("secret")).unwrap();
+        let summaries = client.summaries().unwrap();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!((summaries[0].order, summaries[0].size), (1, 120));
+        assert_eq!(summaries[0].subject, "hi");
+        assert_eq!(summaries[0].from, "alice@example.com");
+        assert!(client.close().unwrap().is_empty());
+        assert_eq!(server.join().unwrap(), vec![
+            String::from("capa"), String::from("user user@localhost"), String::from("pass secret"),
+            String::from("list"), String::from("capa"), String::from("top 1 0"), String::from("quit")
+        ]);
+    }
+
+    #[test]
+    fn login_refuses_plaintext_without_stls(){
+        let (port, server) = stand_in_server(Security::Plain, "+OK ready\r\n", vec!["+OK\r\nUSER\r\n.\r\n"], Vec::new());
+        let mut client = test_client(port, Security::StartTls);
+        client.open().unwrap();
+        assert!(matches!(client.authorize(String::from("user@localhost"), String::from("secret")), Err(MailError::Auth(AuthError::TlsRequired(_)))));
+        assert!(matches!(client.state, State::TCPConnected));
+        client.reset_client();
+        assert_eq!(server.join().unwrap(), vec![String::from("capa")]);
+    }
+
+    #[test]
+    fn missing_stls_is_not_downgraded(){
+        //服务器不支持 STLS 时，即便提供 APOP 与 CRAM-MD5 且允许明文验证也不降级
+        let (port, server) = stand_in_server(Security::Plain, "+OK ready <1.2@localhost>\r\n", vec!["+OK\r\nSASL CRAM-MD5\r\n.\r\n"], Vec::new());
+        let mut client = test_client(port, Security::StartTls);
+        client.set_allow_plaintext_auth(true);
+        client.open().unwrap();
+        match client.authorize(String::from("tim"), String::from("tanstaaftanstaaf")){
+            Err(MailError::Auth(AuthError::TlsRequired(extension))) => { assert_eq!(extension, "STLS"); }
+            _ => panic!("missing STLS not refused")
+        }
+        assert!(matches!(client.state, State::TCPConnected));
+        client.reset_client();
+        assert_eq!(server.join().unwrap(), vec![String::from("capa")]);
+    }
+
+    #[test]
+    fn plain_connection_refuses_apop_and_cram_md5(){
+        let (port, server) = stand_in_server(Security::Plain, "+OK ready <1.2@localhost>\r\n", vec!["-ERR unknown command\r\n"], Vec::new());
+        let mut client = test_client(port, Security::Plain);
+        client.open().unwrap();
+        match client.authorize(String::from("mrose"), String::from("tanstaaf")){
+            Err(MailError::Auth(AuthError::TlsRequired(method))) => { assert_eq!(method, "APOP"); }
+            _ => panic!("APOP not refused")
+        }
+        client.reset_client();
+        assert_eq!(server.join().unwrap(), vec![String::from("capa")]);
+
+        let (port, server) = stand_in_server(Security::Plain, "+OK ready\r\n", vec!["+OK\r\nSASL CRAM-MD5\r\n.\r\n"], Vec::new());
+        let mut client = test_client(port, Security::Plain);
+        client.open().unwrap();
+        match client.authorize(String::from("tim"), String::from("tanstaaftanstaaf")){
+            Err(MailError::Auth(AuthError::TlsRequired(method))) => { assert_eq!(method, "CRAM-MD5"); }
+            _ => panic!("CRAM-MD5 not refused")
+        }
+        client.reset_client();
+        assert_eq!(server.join().unwrap(), vec![String::from("capa")]);
+    }
+
+    #[test]
+    fn plain_connection_refuses_user_pass(){
+        let (port, server) = stand_in_server(Security::Plain, "+OK ready\r\n", vec!["+OK\r\nUSER\r\nSASL PLAIN\r\n.\r\n"], Vec::new());
+        let mut client = test_client(port, Security::Plain);
+        client.open().unwrap();
+        match client.authorize(String::from("user@localhost"), String::from("secret")){
+            Err(MailError::Auth(AuthError::TlsRequired(method))) => { assert_eq!(method, "PLAIN"); }
+            _ => panic!("plaintext auth not refused")
+        }
+        assert!(matches!(client.state, State::TCPConnected));
+        client.reset_client();
+        assert_eq!(server.join().unwrap(), vec![String::from("capa")]);
+    }
+
+    #[test]
+    fn login_with_apop_digest(){
+        //RFC 1939 7 中的示例
+        let (port, server) = stand_in_server(Security::Plain, "+OK POP3 server ready <1896.697170952@dbc.mtview.ca.us>\r\n",
+            vec!["-ERR unknown command\r\n", "+OK maildrop has 1 message\r\n"], Vec::new());
+        let mut client = test_client(port, Security::Plain);
+        client.set_allow_plaintext_auth(true);
+        client.open().unwrap();
+        client.authorize(String::from("mrose"), String::from("tanstaaf")).unwrap();
+        assert!(matches!(client.state, State::Authorized));
+        client.reset_client();
+        assert_eq!(server.join().unwrap(), vec![String::from("capa"), String::from("apop mrose c4c9334bac560ecc979e58001b3e22fb")]);
+    }
+
+    #[test]
+    fn login_with_cram_md5_challenge(){
+        //RFC 2195 中的示例
+        let (port, server) = stand_in_server(Security::Plain, "+OK ready <1.2@localhost>\r\n",
+            vec!["+OK\r\nSASL PLAIN CRAM-MD5\r\n.\r\n", "+ PDE4OTYuNjk3MTcwOTUyQHBvc3RvZmZpY2UucmVzdG9uLm1jaS5uZXQ+\r\n", "+OK\r\n"], Vec::new());
+        let mut client = test_client(port, Security::Plain);
+        client.set_allow_plaintext_auth(true);
+        client.open().unwrap();
+        client.authorize(String::from("tim"), String::from("tanstaaftanstaaf")).unwrap();
+        assert!(matches!(client.state, State::Authorized));
+        client.reset_client();
+        assert_eq!(server.join().unwrap(), vec![
+            String::from("capa"), String::from("auth cram-md5"), String::from("dGltIGI5MTNhNjAyYzdlZGE3YTQ5NWI0ZTZlNzMzNGQzODkw")
+        ]);
+    }
+
+    #[test]
+    fn login_stops_after_rejected_user(){
+        let (port, server) = stand_in_server(Security::Plain, "+OK ready\r\n", vec!["-ERR\r\n", "-ERR no such user\r\n"], Vec::new());
+        let mut client = test_client(port, Security::Plain);
+        client.set_allow_plaintext_auth(true);
+        client.open().unwrap();
+        assert!(matches!(client.authorize(String::from("nobody"), String::from("secret")), Err(MailError::Auth(AuthError::Rejected(_)))));
+        assert!(matches!(client.state, State::TCPConnected));
+        client.reset_client();
+        assert_eq!(server.join().unwrap(), vec![String::from("capa"), String::from("user nobody")]);
+    }
+
+    #[test]
+    fn returns_to_init_when_server_closes_connection(){
+        //服务器读取 USER 命令后不回复，直接关闭连接
+        let (listener, port) = listen();
+        let server = thread::spawn(move ||{
+            let (stream, _) = listener.accept().unwrap();
+            let mut stream = BufReader::new(stream);
+            stream.get_mut().write_all(b"+OK ready\r\n").unwrap();
+            let mut received: Vec<String> = Vec::new();
+            converse(&mut stream, &mut received, "+OK\r\nUSER\r\n.\r\n");
+            let mut line = String::new();
+            stream.read_line(&mut line).unwrap();
+            received.push(line.trim_end().to_string());
+            received
+        });
+        let mut client = test_client(port, Security::Plain);
+        client.set_allow_plaintext_auth(true);
+        client.open().unwrap();
+        assert!(matches!(client.authorize(String::from("user@localhost"), String::from("secret")), Err(MailError::Transport(TransportError::Closed))));
+        assert!(matches!(client.state, State::Init));
+        assert!(!client.client.is_connected());
+        assert_eq!(server.join().unwrap(), vec![String::from("capa"), String::from("user user@localhost")]);
+    }
+
+    #[test]
+    fn times_out_silent_server_and_sends_keepalive(){
+        //登录后第一条 NOOP 返回 -ERR，第二条正常回复，之后不再回复，直到客户端断开连接
+        let (listener, port) = listen();
+        let server = thread::spawn(move ||{
+            let (stream, _) = listener.accept().unwrap();
+            let mut stream = BufReader::new(stream);
+            stream.get_mut().write_all(b"+OK ready\r\n").unwrap();
+            let mut received: Vec<String> = Vec::new();
+            for reply in ["-ERR\r\n", "+OK\r\n", "+OK\r\n", "-ERR busy\r\n", "+OK\r\n"].iter(){
+                converse(&mut stream, &mut received, reply);
             }
-        }else { print_error(); }
+            let mut line = String::new();
+            while stream.read_line(&mut line).unwrap_or(0) > 0{}
+            received.push(line.trim_end().to_string());
+            received
+        });
+        let mut client = test_client(port, Security::Plain);
+        client.set_allow_plaintext_auth(true);
+        client.set_timeouts(Timeouts{ read: Duration::from_millis(200), keepalive: Some(Duration::from_millis(0)), ..Timeouts::default() });
+        client.open().unwrap();
+        //登录前不发送 NOOP
+        assert!(!client.keepalive().unwrap());
+        assert!(matches!(client.noop(), Err(MailError::ClientState(_))));
+        client.authorize(String::from("user@localhost"), String::from("secret")).unwrap();
+        assert!(matches!(client.keepalive(), Err(MailError::Reply(_))));
+        assert!(matches!(client.state, State::Authorized));
+        assert!(client.keepalive().unwrap());
+        assert!(matches!(client.noop(), Err(MailError::Transport(TransportError::Timeout(IoOperation::Read)))));
+        assert!(matches!(client.state, State::Init));
+        assert!(!client.keepalive().unwrap());
+        assert_eq!(server.join().unwrap(), vec![
+            String::from("capa"), String::from("user user@localhost"), String::from("pass secret"),
+            String::from("noop"), String::from("noop"), String::from("noop")
+        ]);
+    }
+
+    #[test]
+    fn extracts_attachments(){
+        let raw = concat!(
+            "Date: Fri, 01 May 2020 12:00:00 +0800\r\n",
+            "From: Alice <alice@example.com>\r\n",
+            "To: bob@example.com\r\n",
+            "Subject: =?utf-8?B?5L2g5aW9?=\r\n",
+            "Content-Type: multipart/mixed; boundary=b\r\n",
+            "\r\n",
+            "--b\r\n",
+            "Content-Type: text/plain; charset=utf-8\r\n",
+            "\r\n",
+            "body\r\n",
+            "--b\r\n",
+            "Content-Type: text/plain; name=\"notes.txt\"\r\n",
+            "Content-Disposition: attachment; filename=\"../notes.txt\"\r\n",
+            "Content-Transfer-Encoding: base64\r\n",
+            "\r\n",
+            "SGVsbG8=\r\n",
+            "--b\r\n",
+            "Content-Type: image/png\r\n",
+            "Content-ID: <logo@example.com>\r\n",
+            "\r\n",
+            "png\r\n",
+            "--b--\r\n"
+        );
+        let mail = parse_raw_mail(raw.as_bytes()).unwrap();
+        assert_eq!(mail.subject, Some(String::from("你好")));
+        assert_eq!(mail.plain, Some(String::from("body")));
+        assert_eq!(mail.attachments.len(), 2);
+        assert_eq!(mail.attachments[0].filename, "../notes.txt");
+        assert_eq!(mail.attachments[0].data, b"Hello");
+        assert_eq!(mail.attachments[0].size, 5);
+        assert_eq!(mail.attachments[1].filename, "attachment-2.png");
+        assert_eq!(mail.attachments[1].content_id, Some(String::from("logo@example.com")));
+    }
+
+    #[test]
+    fn accepts_mail_without_date_recipients_or_body(){
+        //密送邮件没有 To，只有附件的邮件没有正文
+        let raw = concat!(
+            "From: alice@example.com\r\n",
+            "Content-Type: application/pdf; name=\"a.pdf\"\r\n",
+            "Content-Transfer-Encoding: base64\r\n",
+            "\r\n",
+            "JVBERg==\r\n"
+        );
+        let mail = parse_raw_mail(raw.as_bytes()).unwrap();
+        assert!(mail.time.is_none() && mail.to.is_none() && mail.plain.is_none());
+        assert_eq!(mail.attachments[0].data, b"%PDF");
+
+        //缺少发件人的邮件被跳过并报告序号
+        let path = std::env::temp_dir().join(format!("mail_client_import_{}.mbox", std::process::id()));
+        let mbox = format!("From alice@example.com Sat Oct 18 05:08:23 2026\n{}\nFrom MAILER-DAEMON Sat Oct 18 05:08:23 2026\nSubject: no sender\n\nbody\n", raw.replace("\r\n", "\n"));
+        std::fs::write(&path, mbox).unwrap();
+        let import = import_mbox(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(import.drafts.len(), 1);
+        assert!(matches!(import.skipped.as_slice(), [(2, MailError::Message(MessageError::MissingHeader("From")))]));
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn keeps_body_with_broken_transfer_encoding(){
+        let raw = concat!(
+            "From: alice@example.com\r\n",
+            "Subject: broken\r\n",
+            "Content-Type: text/plain; charset=utf-8\r\n",
+            "Content-Transfer-Encoding: base64\r\n",
+            "\r\n",
+            "not*base64!\r\n"
+        );
+        let mail = parse_raw_mail(raw.as_bytes()).unwrap();
+        assert_eq!(mail.subject.as_deref(), Some("broken"));
+        assert_eq!(mail.plain.as_deref().map(str::trim_end), Some("not*base64!"));
+    }
+
+    #[test]
+    fn reads_multiline_responses(){
+        let mut reader = MultilineReader::new();
+        for line in [&b"Subject: a\r\n"[..], b"\r\n", b"..hidden\r\n", b".\r\n", b"+OK next\r\n"].iter(){
+            reader.feed(line);
+        }
+        assert!(reader.is_finished());
+        assert_eq!(reader.data, b"Subject: a\r\n\r\n.hidden\r\n".to_vec());
+        let mut reader = MultilineReader::new();
+        reader.feed(b"\xb2\xe2\xca\xd4\r\n");
+        reader.feed(b"..\r\n");
+        assert!(!reader.is_finished());
+        reader.feed(b".\n");
+        assert!(reader.is_finished());
+        assert_eq!(reader.data, b"\xb2\xe2\xca\xd4\r\n.\r\n".to_vec());
+    }
+
+    #[test]
+    fn summarizes_headers_for_listing(){
+        assert_eq!(parse_list_response(String::from("1 120\r\n2 30000\r\n")).unwrap(), vec![(1, 120), (2, 30000)]);
+        assert!(parse_list_response(String::from("1\r\n")).is_err());
+        let capabilities = Capabilities::parse("TOP\r\nsasl PLAIN cram-md5\r\nUIDL\r\n");
+        assert_eq!(capabilities.names, vec!["TOP", "SASL", "UIDL"]);
+        assert_eq!(capabilities.sasl, vec!["PLAIN", "CRAM-MD5"]);
+        assert!(capabilities.has("top") && !capabilities.has("STLS"));
+        let headers = concat!(
+            "Date: Fri, 01 May 2020 12:00:00 +0800\r\n",
+            "From: Alice <alice@example.com>\r\n",
+            "To: bob@example.com\r\n",
+            "Subject: report\r\n",
+            "Content-Type: multipart/mixed; boundary=b\r\n",
+            "\r\n"
+        );
+        let summary = summarize_mail(1, headers.as_bytes(), true);
+        assert_eq!((summary.from.as_str(), summary.to.as_str(), summary.subject.as_str()), ("alice@example.com", "bob@example.com", "report"));
+        assert!(summary.has_attachments);
+        assert!(!summarize_mail(1, b"Subject: a\r\nContent-Type: text/plain\r\n\r\n", true).has_attachments);
+    }
+
+    #[test]
+    fn saves_attachments_with_safe_unique_names(){
+        assert_eq!(sanitize_filename("../../etc/passwd"), "_.._etc_passwd");
+        assert_eq!(sanitize_filename("CON.txt"), "_CON.txt");
+        assert_eq!(sanitize_filename(" .. "), "attachment");
+        assert_eq!(sanitize_filename(&("长".repeat(100) + ".txt")).len(), 253);
+        assert_eq!(sanitize_filename(&("a.".to_string() + &"长".repeat(100))), "a.".to_string() + &"长".repeat(84));
+        let directory = std::env::temp_dir().join(format!("mail_client_save_{}", std::process::id()));
+        let directory = directory.to_string_lossy().to_string();
+        let attachment = ReceivedAttachment{
+            filename: String::from("a/b.txt"),
+            mime_type: String::from("text/plain"),
+            size: 2,
+            content_id: None,
+            data: b"hi".to_vec()
+        };
+        let first = save_attachment(&attachment, &directory).unwrap();
+        let second = save_attachment(&attachment, &directory).unwrap();
+        assert_eq!(first.file_name().unwrap(), "a_b.txt");
+        assert_eq!(second.file_name().unwrap(), "a_b (1).txt");
+        assert_eq!(std::fs::read(&second).unwrap(), b"hi");
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,183 @@
+use crate::error::{MailError, AuthError};
+
+//自动选择时的优先级，XOAUTH2 需要 bearer token 而非授权码，只在用户指定时使用
+const PREFERENCE: [&str; 3] = ["CRAM-MD5", "PLAIN", "LOGIN"];
+
+pub const SUPPORTED: [&str; 4] = ["XOAUTH2", "CRAM-MD5", "PLAIN", "LOGIN"];
+
+pub trait Mechanism{
+    fn name(&self) -> &'static str;
+
+    //随 AUTH 命令一同发送的初始响应，返回 None 时等待服务器的第一个质询
+    fn initial_response(&mut self) -> Option<Vec<u8>>{
+        None
+    }
+
+    //根据服务器质询(已 base64 解码)生成下一步响应
+    fn respond(&mut self, challenge: &[u8]) -> Result<Vec<u8>, MailError>;
+}
+
+pub struct Login{
+    account: String,
+    password: String,
+    step: usize
+}
+
+impl Mechanism for Login{
+    fn name(&self) -> &'static str{ "LOGIN" }
+
+    fn respond(&mut self, _challenge: &[u8]) -> Result<Vec<u8>, MailError>{
+        self.step += 1;
+        match self.step{
+            1 => Ok(self.account.clone().into_bytes()),
+            2 => Ok(self.password.clone().into_bytes()),
+            _ => Err(AuthError::UnexpectedChallenge(String::from(self.name())).into())
+        }
+    }
+}
+
+pub struct Plain{
+    account: String,
+    password: String
+}
+
+impl Mechanism for Plain{
+    fn name(&self) -> &'static str{ "PLAIN" }
+
+    fn initial_response(&mut self) -> Option<Vec<u8>>{
+        Some(format!("\0{}\0{}", self.account, self.password).into_bytes())
+    }
+
+    fn respond(&mut self, challenge: &[u8]) -> Result<Vec<u8>, MailError>{
+        //服务器不支持初始响应时会先发送空质询
+        if challenge.is_empty(){
+            if let Some(response) = self.initial_response(){
+                return Ok(response);
+            }
+        }
+        Err(AuthError::UnexpectedChallenge(String::from(self.name())).into())
+    }
+}
+
+pub struct CramMd5{
+    account: String,
+    password: String
+}
+
+impl Mechanism for CramMd5{
+    fn name(&self) -> &'static str{ "CRAM-MD5" }
+
+    fn respond(&mut self, challenge: &[u8]) -> Result<Vec<u8>, MailError>{
+        let digest: String = hmac_md5(self.password.as_bytes(), challenge).iter().map(|byte| format!("{:02x}", byte)).collect();
+        Ok(format!("{} {}", self.account, digest).into_bytes())
+    }
+}
+
+pub struct XOAuth2{
+    account: String,
+    token: String
+}
+
+impl Mechanism for XOAuth2{
+    fn name(&self) -> &'static str{ "XOAUTH2" }
+
+    fn initial_response(&mut self) -> Option<Vec<u8>>{
+        Some(format!("user={}\x01auth=Bearer {}\x01\x01", self.account, self.token).into_bytes())
+    }
+
+    fn respond(&mut self, _challenge: &[u8]) -> Result<Vec<u8>, MailError>{
+        //验证失败时服务器以质询形式返回 JSON 错误信息，回复空行后服务器给出最终错误码
+        Ok(Vec::new())
+    }
+}
+
+pub fn create(name: &str, account: String, password: String) -> Option<Box<dyn Mechanism>>{
+    match name.to_uppercase().as_str(){
+        "LOGIN" => Some(Box::new(Login{ account, password, step: 0 })),
+        "PLAIN" => Some(Box::new(Plain{ account, password })),
+        "CRAM-MD5" => Some(Box::new(CramMd5{ account, password })),
+        "XOAUTH2" => Some(Box::new(XOAuth2{ account, token: password })),
+        _ => None
+    }
+}
+
+pub fn is_supported(name: &str) -> bool{
+    SUPPORTED.contains(&name.to_uppercase().as_str())
+}
+
+pub fn select(advertised: &[String], forced: Option<&str>) -> Result<String, MailError>{
+    //用户指定的验证方式优先，否则选择服务器支持的最强方式；只使用服务器声明过的方式
+    if let Some(forced) = forced{
+        let forced = forced.to_uppercase();
+        if !is_supported(&forced) || !advertised.iter().any(|m| m.eq_ignore_ascii_case(&forced)){
+            return Err(AuthError::MechanismNotSupported(forced).into());
+        }
+        return Ok(forced);
+    }
+    if advertised.is_empty(){
+        return Err(AuthError::MechanismNotSupported(String::from("服务器未声明 AUTH 扩展")).into());
+    }
+    for name in PREFERENCE.iter(){
+        if advertised.iter().any(|m| m.eq_ignore_ascii_case(name)){
+            return Ok(name.to_string());
+        }
+    }
+    Err(AuthError::MechanismNotSupported(advertised.join(" ")).into())
+}
+
+pub fn hmac_md5(key: &[u8], message: &[u8]) -> [u8; 16]{
+    //RFC 2104，MD5 分组长度为 64 字节
+    let mut block = [0u8; 64];
+    if key.len() > 64{
+        block[..16].copy_from_slice(&md5::compute(key).0);
+    }else{
+        block[..key.len()].copy_from_slice(key);
+    }
+    let mut inner: Vec<u8> = block.iter().map(|byte| byte ^ 0x36).collect();
+    inner.extend_from_slice(message);
+    let mut outer: Vec<u8> = block.iter().map(|byte| byte ^ 0x5c).collect();
+    outer.extend_from_slice(&md5::compute(&inner).0);
+    md5::compute(&outer).0
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn names(list: &[&str]) -> Vec<String>{
+        list.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn computes_cram_md5_digest(){
+        //RFC 2195 中的示例
+        let challenge = b"<1896.697170952@postoffice.reston.mci.net>";
+        let digest: String = hmac_md5(b"tanstaaftanstaaf", challenge).iter().map(|byte| format!("{:02x}", byte)).collect();
+        assert_eq!(digest, "b913a602c7eda7a495b4e6e7334d3890");
+        let mut mechanism = create("cram-md5", String::from("tim"), String::from("tanstaaftanstaaf")).unwrap();
+        assert!(mechanism.initial_response().is_none());
+        assert_eq!(mechanism.respond(challenge).unwrap(), b"tim b913a602c7eda7a495b4e6e7334d3890".to_vec());
+    }
+
+    #[test]
+    fn selects_strongest_advertised_mechanism(){
+        assert_eq!(select(&names(&["LOGIN", "PLAIN", "CRAM-MD5"]), None).unwrap(), "CRAM-MD5");
+        assert_eq!(select(&names(&["login", "plain"]), None).unwrap(), "PLAIN");
+        assert_eq!(select(&names(&["XOAUTH2", "LOGIN"]), None).unwrap(), "LOGIN");
+        //XOAUTH2 只在用户指定时使用
+        assert!(matches!(select(&names(&["XOAUTH2", "GSSAPI"]), None), Err(MailError::Auth(AuthError::MechanismNotSupported(_)))));
+        assert!(matches!(select(&[], None), Err(MailError::Auth(AuthError::MechanismNotSupported(_)))));
+    }
+
+    #[test]
+    fn forced_mechanism_must_be_advertised(){
+        assert_eq!(select(&names(&["PLAIN", "XOAUTH2"]), Some("xoauth2")).unwrap(), "XOAUTH2");
+        match select(&names(&["PLAIN", "LOGIN"]), Some("cram-md5")){
+            Err(MailError::Auth(AuthError::MechanismNotSupported(name))) => { assert_eq!(name, "CRAM-MD5"); }
+            _ => panic!("unadvertised mechanism selected")
+        }
+        assert!(matches!(select(&[], Some("LOGIN")), Err(MailError::Auth(AuthError::MechanismNotSupported(_)))));
+        assert!(matches!(select(&names(&["GSSAPI"]), Some("GSSAPI")), Err(MailError::Auth(AuthError::MechanismNotSupported(_)))));
+    }
+}
@@ -1,7 +1,13 @@
-use crate::{tcp, util};
+use crate::{tcp, sasl, mime};
+use crate::message::{self, Address, MessageBuilder};
+use crate::tcp::{Security, TlsConfig, Timeouts};
 use crate::smtp;
-use crate::util::{MailError, print_error};
+use crate::config::Profile;
+use crate::error::{MailError, ReplyError, ParseError, AuthError, MessageError, Protocol};
 use regex::Regex;
+use serde::Serialize;
+use std::net::IpAddr;
+use std::time::Duration;
 
 
 
@@ -11,51 +17,160 @@ pub struct Response{
     group: Vec<String>
 }
 
-impl Response{
-    fn new(code: usize) -> Response{
-        Response{
-            code,
-            group: Vec::new()
+pub enum State{
+    Init, TCPConnected, Authorized
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Capabilities{
+    //SIZE 扩展声明的最大邮件字节数，0 表示服务器未声明固定上限
+    pub size: Option<usize>,
+    pub auth: Vec<String>,
+    pub pipelining: bool,
+    pub eight_bit_mime: bool,
+    pub smtp_utf8: bool,
+    pub dsn: bool,
+    pub chunking: bool,
+    pub starttls: bool,
+    pub enhanced_status_codes: bool,
+    //未识别的其他扩展关键字
+    pub others: Vec<String>
+}
+
+impl Capabilities{
+    pub fn parse(response: &Response) -> Capabilities{
+        //ehlo 回复的首行为服务器域名，其余每行为一个扩展关键字及其参数
+        let mut capabilities = Capabilities::default();
+        for line in response.group.iter().skip(1){
+            let line = line.trim().to_uppercase();
+            //部分旧服务器使用 AUTH=LOGIN PLAIN 的形式
+            let mut params = line.split([' ', '=']).filter(|param| !param.is_empty());
+            let keyword = match params.next(){
+                Some(keyword) => keyword,
+                None => { continue; }
+            };
+            match keyword{
+                "SIZE" => {
+                    capabilities.size = Some(params.next().and_then(|size| size.parse::<usize>().ok()).unwrap_or(0));
+                }
+                "AUTH" => {
+                    for mechanism in params{
+                        if !capabilities.auth.iter().any(|m| m == mechanism){
+                            capabilities.auth.push(mechanism.to_string());
+                        }
+                    }
+                }
+                "PIPELINING" => { capabilities.pipelining = true; }
+                "8BITMIME" => { capabilities.eight_bit_mime = true; }
+                "SMTPUTF8" => { capabilities.smtp_utf8 = true; }
+                "DSN" => { capabilities.dsn = true; }
+                "CHUNKING" => { capabilities.chunking = true; }
+                "STARTTLS" => { capabilities.starttls = true; }
+                "ENHANCEDSTATUSCODES" => { capabilities.enhanced_status_codes = true; }
+                _ => { capabilities.others.push(line.clone()); }
+            }
         }
+        capabilities
     }
+}
 
+#[derive(Debug, Clone)]
+pub struct Attachment{
+    pub path: String,
+    pub filename: String,
+    pub mime_type: String,
+    //内嵌资源(如 HTML 正文中以 cid: 引用的图片)的 Content-ID，不含尖括号；普通附件为 None
+    pub content_id: Option<String>
 }
 
-pub enum State{
-    Init, TCPConnected, Authorized
+impl Attachment{
+    pub fn from_path(path: &str) -> Result<Attachment, MailError>{
+        //文件内容在发送时才读取，这里只检查文件是否存在并根据扩展名推断 MIME 类型
+        let file_path = std::path::Path::new(path);
+        if !file_path.is_file(){
+            return Err(MessageError::File{ path: path.to_string(), source: std::io::Error::new(std::io::ErrorKind::NotFound, "not a file") }.into());
+        }
+        let filename = match file_path.file_name(){
+            Some(name) => name.to_string_lossy().to_string(),
+            None => { return Err(MessageError::File{ path: path.to_string(), source: std::io::Error::new(std::io::ErrorKind::InvalidInput, "no file name") }.into()); }
+        };
+        Ok(Attachment{
+            path: path.to_string(),
+            filename,
+            mime_type: mime_guess::from_path(file_path).first_or_octet_stream().to_string(),
+            content_id: None
+        })
+    }
+
+    pub fn inline(path: &str) -> Result<Attachment, MailError>{
+        //以文件名作为 Content-ID，HTML 正文中使用 cid:文件名 引用
+        let mut attachment = Attachment::from_path(path)?;
+        attachment.content_id.replace(attachment.filename.clone());
+        Ok(attachment)
+    }
 }
 
 pub struct SMTPMail{
     pub from: Option<String>,
-    pub to: Option<String>,
+    pub to: Vec<String>,
+    pub cc: Vec<String>,
+    pub bcc: Vec<String>,
     pub subject: Option<String>,
-    pub content: Option<Vec<String>>
+    pub content: Option<Vec<String>>,
+    //HTML 正文，设置后以 multipart/alternative 发送，content 为空时自动生成纯文本备选内容
+    pub html: Option<String>,
+    pub attachments: Vec<Attachment>,
+    pub inline_images: Vec<Attachment>
+}
+
+impl Default for SMTPMail{
+    fn default() -> SMTPMail{
+        SMTPMail::new()
+    }
 }
 
 impl SMTPMail{
     pub fn new() -> SMTPMail{
         SMTPMail{
             from: None,
-            to: None,
+            to: Vec::new(),
+            cc: Vec::new(),
+            bcc: Vec::new(),
             subject: None,
-            content: None
+            content: None,
+            html: None,
+            attachments: Vec::new(),
+            inline_images: Vec::new()
         }
     }
 
-    pub fn check_integrity(&self) -> usize{
-        if let Some(from) = &self.from{
-            if let Some(to) = &self.to{
-                if let Some(subject) = &self.subject{
-                    if let Some(content) = &self.content{
-                        return 0;
-                    }
-                    return 4;
-                }
-                return 3;
+    pub fn validate(&self) -> Result<(), MessageError>{
+        //发送前检查邮件是否完整
+        if self.from.is_none(){
+            return Err(MessageError::MissingHeader("From"));
+        }
+        if self.recipients().is_empty(){
+            return Err(MessageError::MissingRecipients);
+        }
+        if self.subject.is_none(){
+            return Err(MessageError::MissingHeader("Subject"));
+        }
+        if self.content.is_none() && self.html.is_none(){
+            return Err(MessageError::MissingBody);
+        }
+        Ok(())
+    }
+
+    pub fn recipients(&self) -> Vec<String>{
+        //所有需要发送 rcpt to 的地址(包括密送)，去除重复
+        let mut recipients: Vec<String> = Vec::new();
+        for address in self.to.iter().chain(self.cc.iter()).chain(self.bcc.iter()){
+            let email = Address::parse(address).email;
+            if !recipients.iter().any(|r| r.eq_ignore_ascii_case(&email)){
+                recipients.push(email);
             }
-            return 2;
         }
-        return 1;
+        recipients
     }
 
 }
@@ -64,60 +179,149 @@ impl std::clone::Clone for smtp::SMTPMail{
         SMTPMail{
             from: self.from.clone(),
             to: self.to.clone(),
+            cc: self.cc.clone(),
+            bcc: self.bcc.clone(),
             subject: self.subject.clone(),
-            content: self.content.clone()
+            content: self.content.clone(),
+            html: self.html.clone(),
+            attachments: self.attachments.clone(),
+            inline_images: self.inline_images.clone()
         }
     }
 }
 
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Delivery{
+    //服务器接受与拒绝的接收方，至少有一个接收方被接受时才会发送邮件
+    pub accepted: Vec<String>,
+    pub rejected: Vec<String>
+}
+
+pub fn parse_address_list(input: &str) -> Vec<String>{
+    //地址之间以逗号或分号分隔，引号内的显示名与尖括号内的分隔符不拆分
+    mime::split_unquoted(input, ',').iter()
+        .flat_map(|part| mime::split_unquoted(part, ';'))
+        .map(|address| address.trim().to_string()).filter(|address| !address.is_empty()).collect()
+}
+
 
 pub struct Client{
     client: tcp::Client,
     name: String,
     account: Option<String>,
     password: Option<String>,
-    mail_group: Vec<SMTPMail>,
     state: State,
-    debug: bool
+    capabilities: Option<Capabilities>,
+    auth_mechanism: Option<String>,
+    allow_plaintext_auth: bool
 }
 
 impl Client{
 
-    pub fn print_debug(&mut self, response: &Response){
-        if self.debug{
-            println!("{:?}", response);
+    fn log_debug(&mut self, response: &Response){
+        if self.client.is_debug(){
+            self.client.log_debug(format!("{:?}", response));
         }
     }
 
-    pub fn debug(&mut self){
-        self.debug = !self.debug;
-        if self.debug{
-            println!("已切换至 debug 模式");
-        }else{
-            println!("已切换至 普通 模式");
-        }
+    pub fn name(&self) -> &str{
+        &self.name
+    }
+
+    pub fn state(&self) -> &State{
+        &self.state
+    }
+
+    pub fn account(&self) -> Option<&str>{
+        self.account.as_deref()
+    }
+
+    pub fn security(&self) -> Security{
+        self.client.security()
+    }
+
+    pub fn is_secure(&self) -> bool{
+        self.client.is_secure()
+    }
+
+    pub fn capabilities(&self) -> Option<&Capabilities>{
+        //ehlo 之后才能获取
+        self.capabilities.as_ref()
+    }
+
+    pub fn allows_plaintext_auth(&self) -> bool{
+        self.allow_plaintext_auth
+    }
+
+    pub fn set_allow_plaintext_auth(&mut self, allow: bool){
+        self.allow_plaintext_auth = allow;
+    }
+
+    pub fn set_timeouts(&mut self, timeouts: Timeouts){
+        self.client.set_timeouts(timeouts);
+    }
+
+    pub fn keepalive_interval(&self) -> Option<Duration>{
+        self.client.keepalive_interval()
+    }
+
+    pub fn is_debug(&self) -> bool{
+        self.client.is_debug()
+    }
+
+    pub fn set_debug(&mut self, debug: bool){
+        self.client.set_debug(debug);
+    }
+
+    pub fn take_debug_log(&mut self) -> Vec<String>{
+        self.client.take_debug_log()
     }
 
     pub fn new(host_name: String, name: String) -> smtp::Client{
         let mut c = Client{
-            client: tcp::Client::new(host_name, 25),
+            client: tcp::Client::new(host_name, 587),
             name,
             account: None,
             password: None,
-            mail_group: Vec::new(),
             state: State::Init,
-            debug: false
+            capabilities: None,
+            auth_mechanism: None,
+            allow_plaintext_auth: false
         };
         c.client.set_end_pointer(String::from("\r\n"));
+        c.client.set_tls_config(TlsConfig::new(Security::StartTls));
         return c;
     }
 
+    pub fn from_profile(profile: &Profile, password: String) -> Result<smtp::Client, MailError>{
+        let server = match &profile.smtp{
+            Some(server) => server,
+            None => { return Err(MailError::Config(String::from("未配置 smtp 服务器"))); }
+        };
+        let tls_config = profile.tls_config(server, Security::StartTls);
+        let mut client = Client::new(server.host.clone(), profile.hostname.clone().unwrap_or_default());
+        client.auth_mechanism = server.auth.as_ref().map(|auth| auth.to_uppercase());
+        client.allow_plaintext_auth = server.allow_plaintext_auth.unwrap_or(false);
+        client.client.set_port(server.port.unwrap_or_else(|| default_port(&tls_config.security)));
+        client.client.set_tls_config(tls_config);
+        client.client.set_timeouts(profile.timeouts());
+        client.account = profile.account.clone();
+        client.password.replace(password);
+        Ok(client)
+    }
+
     fn _receive_to_end(&mut self) -> Result<Response, MailError>{
         let response_regex = Regex::new(r"^(\d+)(.)(.+)").unwrap();
         let mut response_group: Vec<String> = Vec::new();
         let mut counter: usize = 0;
         let code = loop{
-            let response_line = self.client.receive()?;
+            let response_line = match self.client.receive(){
+                Ok(line) => line,
+                Err(error) => {
+                    self.check_connection();
+                    return Err(error);
+                }
+            };
             let _cap = response_regex.captures(&response_line);
             if let Some(cap) = _cap{
                 response_group.push(cap[3].to_string());
@@ -125,376 +329,657 @@ impl Client{
                     break cap[1].trim().parse::<usize>().unwrap()
                 }
             }else{
-                return Err(MailError::SMTPResponseParseError(response_line));
+                return Err(ParseError::Response(Protocol::SMTP, response_line.trim_end().to_string()).into());
             }
             counter += 1;
             if counter > 1000{
-                return Err(MailError::SMTPResponseParseError(String::from("Receive overtime.")));
+                return Err(ParseError::Response(Protocol::SMTP, String::from("回复超过 1000 行")).into());
             }
         };
+        if code == 421{
+            //服务器即将关闭连接(RFC 5321 3.8)，之后不会再有回复
+            let response = Response{ code, group: response_group };
+            self.log_debug(&response);
+            self.client.shutdown();
+            self.check_connection();
+            return Err(reply_error(&response));
+        }
         Ok(Response{
             code,
             group: response_group
         })
     }
 
+    fn _send(&mut self, content: String) -> Result<(), MailError>{
+        let result = self.client.send(content);
+        self.check_connection();
+        result
+    }
+
+    fn check_connection(&mut self){
+        //连接已断开(超时、服务器关闭连接或 421)时回到初始状态，需重新连接并登录
+        if !self.client.is_connected(){
+            self.state = State::Init;
+            self.capabilities = None;
+        }
+    }
+
     fn _connect(&mut self) -> Result<Response, MailError>{
         self.client.connect()?;
         self._receive_to_end()
     }
 
     fn _ehlo(&mut self, domain: String) -> Result<Response, MailError>{
-        self.client.send(format!("ehlo {}", domain))?;
+        self._send(format!("ehlo {}", domain))?;
         self._receive_to_end()
     }
 
-    fn _auth(&mut self, auth_para: Option<String>) -> Result<Response, MailError>{
-        if let Some(para) = auth_para{
-            self.client.send(format!("auth {}", para))?;
+    fn _starttls(&mut self) -> Result<Response, MailError>{
+        self._send(String::from("starttls"))?;
+        self._receive_to_end()
+    }
+
+    fn _auth(&mut self, mechanism: String, initial_response: Option<Vec<u8>>) -> Result<Response, MailError>{
+        if let Some(initial) = initial_response{
+            //RFC 4954: 空的初始响应以 = 表示
+            let encoded = if initial.is_empty() { String::from("=") } else { base64::encode(initial) };
+            self._send(format!("auth {} {}", mechanism.to_lowercase(), encoded))?;
             self._receive_to_end()
         }else{
-            self.client.send("auth".to_string())?;
+            self._send(format!("auth {}", mechanism.to_lowercase()))?;
             self._receive_to_end()
         }
     }
 
     fn _data(&mut self) -> Result<Response, MailError>{
-        self.client.send(String::from("data"))?;
+        self._send(String::from("data"))?;
         self._receive_to_end()
     }
 
-    fn _mail_from(&mut self, sender: String) -> Result<Response, MailError>{
-        self.client.send(format!("mail from:<{}>", sender))?;
+    fn _mail_from(&mut self, sender: String, parameters: Vec<String>) -> Result<Response, MailError>{
+        let mut command = format!("mail from:<{}>", sender);
+        for parameter in parameters{
+            command.push(' ');
+            command.push_str(&parameter);
+        }
+        self._send(command)?;
         self._receive_to_end()
     }
 
     fn _rcpt_to(&mut self, receiver: String) -> Result<Response, MailError>{
-        self.client.send(format!("rcpt to:<{}>", receiver))?;
+        self._send(format!("rcpt to:<{}>", receiver))?;
         self._receive_to_end()
     }
 
-    fn _send_base64(&mut self, string: String) -> Result<Response, MailError>{
-        self.client.send(base64::encode(string))?;
+    fn _send_base64(&mut self, bytes: Vec<u8>) -> Result<Response, MailError>{
+        self._send(base64::encode(bytes))?;
         self._receive_to_end()
     }
 
-    fn _send_lines(&mut self, lines: Vec<String>) -> Result<Response, MailError>{
-        for line in lines{
-            self.client.send(line)?;
+    fn _send_data(&mut self, lines: Vec<String>) -> Result<Response, MailError>{
+        for line in message::dot_stuff(lines){
+            self._send(line)?;
         }
+        self._send(String::from("."))?;
+        self._receive_to_end()
+    }
+
+    fn _noop(&mut self) -> Result<Response, MailError>{
+        self._send(String::from("noop"))?;
+        self._receive_to_end()
+    }
+
+    fn _rset(&mut self) -> Result<Response, MailError>{
+        self._send(String::from("rset"))?;
         self._receive_to_end()
     }
 
     fn _quit(&mut self) -> Result<Response, MailError>{
-        self.client.send(String::from("quit"))?;
+        self._send(String::from("quit"))?;
         self._receive_to_end()
     }
 
     //-------------以下为业务函数--------------------
 
-    pub fn connect(&mut self){
-        if let State::Init = self.state{
-            if let Ok(response) = self._connect(){
-                self.print_debug(&response);
-                self.state = State::TCPConnected;
-                println!("客户端 <{}> 连接成功", self.name);
-            }else{
-                println!("客户端 <{}> 发起连接失败，请使用 -reset 进行重置", self.name);
-            }
-        }else{
-            println!("客户端 <{}> 处于已连接状态，若要重置连接，请使用 -reset 命令", self.name);
+    pub fn open(&mut self) -> Result<(), MailError>{
+        let response = self._connect()?;
+        self.log_debug(&response);
+        if response.code != 220{
+            return Err(reply_error(&response));
         }
+        self.state = State::TCPConnected;
+        Ok(())
     }
 
-    pub fn login(&mut self){
+    pub fn authorize(&mut self) -> Result<(), MailError>{
         match self.state{
-            State::Init => {
-                println!("客户端 <{}> 尚未发起连接，请先尝试连接", self.name); return;
-            }
-            State::TCPConnected => {
-
-            }
-            State::Authorized => {
-                if let Some(account) = &self.account{
-                    println!("客户端 <{}> 已登录为 <{}> ，若要重新登录请使用 -reset 进行重置", self.name, account); return;
-                }else{
-                    println!("未找到客户端 <{}> 的邮箱账号，请尝试使用 -reset 进行重置", self.name); return;
-                }
-            }
+            State::Init => { return Err(MailError::ClientState(String::from("尚未发起连接"))); }
+            State::TCPConnected => {}
+            State::Authorized => { return Err(MailError::ClientState(String::from("已登录"))); }
         }
+        let account = self.account.clone().unwrap_or_default();
+        let password = self.password.clone().unwrap_or_default();
 
-        let account = self.account.clone().unwrap();
-        let password = self.password.clone().unwrap();
+        self.hello()?;
 
-        let ehlo_res = self._ehlo(self.name.clone());
-        if let Ok(ehlo_response) = ehlo_res{
-            self.print_debug(&ehlo_response);
-            if ehlo_response.code != 250{
-                println!("客户端 <{}> 请求登录失败，错误信息: {:?}", self.name, ehlo_response);
+        //starttls 模式下通过 STARTTLS 升级连接，升级后需重新发送 ehlo；服务器不支持时不降级为明文
+        if self.client.security() == Security::StartTls && !self.client.is_secure(){
+            if !self.capabilities.as_ref().is_some_and(|caps| caps.starttls){
+                return Err(AuthError::TlsRequired(String::from("STARTTLS")).into());
             }
-        }else if let Err(error) = ehlo_res{
-            println!("客户端 <{}> 请求登录失败，错误信息: {:?}", self.name, error); return;
-        }
-
-        //进行auth
-        self.account = Some(account.clone());
-        self.password = Some(password.clone());
-        //auth login
-        let _res = self._auth(Some(String::from("login")));
-        if let Ok(response) = _res{
-            self.print_debug(&response);
-            if response.code != 334{
-                println!("客户端 <{}> 请求身份验证失败，错误信息: {:?}", self.name, response); return;
+            let response = self._starttls()?;
+            self.log_debug(&response);
+            if response.code != 220{
+                return Err(reply_error(&response));
             }
-        }else if let Err(error) = _res{
-            println!("客户端 <{}> 请求身份验证失败，错误信息: {:?}", self.name, error); return;
-        }
-        //auth account
-        let account_res = self._send_base64(account);
-        if let Ok(account_response) = account_res{
-            self.print_debug(&account_response);
-            if account_response.code != 334{
-                println!("客户端 <{}> 发送用户名失败，错误信息: {:?}", self.name, account_response); return;
+            if let Err(error) = self.client.starttls(){
+                self.state = State::Init;
+                return Err(error);
             }
-        }else if let Err(error) = account_res{
-            println!("客户端 <{}> 发送用户名失败，错误信息: {:?}", self.name, error); return;
-        }
-        //auth password
-        let pass_res = self._send_base64(password);
-        if let Ok(pass_response) = pass_res{
-            self.print_debug(&pass_response);
-            if pass_response.code != 235{
-                println!("客户端 <{}> 发送授权码失败, 错误信息: {:?}", self.name, pass_response); return;
+            self.hello()?;
+        }
+
+        //选择 SASL 验证方式；未加密连接上除非用户明确允许，否则拒绝任何验证方式
+        let advertised = self.capabilities.as_ref().map(|caps| caps.auth.clone()).unwrap_or_default();
+        let mechanism_name = sasl::select(&advertised, self.auth_mechanism.as_deref())?;
+        if !self.client.is_secure() && !self.allow_plaintext_auth{
+            return Err(AuthError::TlsRequired(mechanism_name).into());
+        }
+        let mut mechanism = match sasl::create(&mechanism_name, account, password){
+            Some(mechanism) => mechanism,
+            None => { return Err(AuthError::MechanismNotSupported(mechanism_name).into()); }
+        };
+
+        //auth <mechanism>，之后逐个回应服务器的 334 质询直至返回 235
+        let initial_response = mechanism.initial_response();
+        let mut auth_res = self._auth(mechanism_name, initial_response);
+        let mut counter: usize = 0;
+        loop{
+            let response = auth_res?;
+            self.log_debug(&response);
+            if response.code == 235{ break; }
+            if response.code != 334 || counter > 8{
+                return Err(AuthError::Rejected(ReplyError::smtp(response.code, &response.group)).into());
             }
-        }else if let Err(error) = pass_res{
-            println!("客户端 <{}> 发送授权码失败，错误信息: {:?}", self.name, error); return;
+            let challenge = response.group.first().map(|line| line.trim().to_string()).unwrap_or_default();
+            let reply = match base64::decode(&challenge){
+                Ok(decoded) => mechanism.respond(&decoded),
+                Err(_) => Err(AuthError::ChallengeDecode(challenge).into())
+            };
+            auth_res = match reply{
+                Ok(reply) => self._send_base64(reply),
+                Err(error) => {
+                    //以 * 取消验证过程
+                    let _ = self._send(String::from("*"));
+                    let _ = self._receive_to_end();
+                    Err(error)
+                }
+            };
+            counter += 1;
         }
 
         self.state = State::Authorized;
-        println!("客户端 <{}> 登录成功", self.name);
+        Ok(())
     }
 
-    pub fn save(&mut self, mail: SMTPMail){
-        println!("客户端 <{}> 已保存邮件 [{}]", self.name, mail.subject.clone().unwrap());
-        self.mail_group.push(mail);
+    fn domain(&self) -> String{
+        //RFC 5321 4.1.4: 未配置主机名时以本机地址字面量代替，用于 ehlo 与 Message-ID
+        if !self.name.is_empty(){
+            return self.name.clone();
+        }
+        match self.client.local_addr().map(|address| address.ip()){
+            Some(IpAddr::V6(ip)) => format!("[IPv6:{}]", ip),
+            Some(ip) => format!("[{}]", ip),
+            None => String::from("[127.0.0.1]")
+        }
     }
 
-    pub fn send(&mut self, order_number: usize){
-        match self.state{
-            State::Init => {
-                println!("客户端 <{}> 尚未发起连接，请先尝试连接", self.name); return;
-            }
-            State::TCPConnected => {
-                println!("客户端 <{}> 尚未登录，请先尝试登录", self.name); return;
-            }
-            State::Authorized => {
+    fn hello(&mut self) -> Result<(), MailError>{
+        //发送 ehlo 并记录服务器支持的扩展
+        let response = self._ehlo(self.domain())?;
+        self.log_debug(&response);
+        if response.code != 250{
+            return Err(reply_error(&response));
+        }
+        self.capabilities.replace(Capabilities::parse(&response));
+        Ok(())
+    }
 
+    pub fn deliver(&mut self, mail: &SMTPMail) -> Result<Delivery, MailError>{
+        if let State::Authorized = self.state{}else{
+            return Err(MailError::ClientState(String::from("尚未登录")));
+        }
+        mail.validate()?;
+
+        let eight_bit = self.capabilities.as_ref().is_some_and(|caps| caps.eight_bit_mime);
+        let lines = MessageBuilder::from_mail(mail).domain(self.domain()).eight_bit(eight_bit).build()?;
+        let size: usize = lines.iter().map(|line| line.len() + 2).sum();
+        let mut parameters: Vec<String> = Vec::new();
+        if let Some(limit) = self.capabilities.as_ref().and_then(|caps| caps.size){
+            if limit > 0 && size > limit{
+                return Err(MessageError::TooLarge{ size, limit }.into());
             }
+            parameters.push(format!("SIZE={}", size));
         }
-        if order_number > self.mail_group.len(){
-            println!("客户端 <{}> 未找到序号为 [{}] 的邮件，请重试", self.name, order_number); return;
-        }
-
-        let target = self.mail_group.remove(order_number - 1);
-        if target.check_integrity() != 0{
-            println!("客户端 <{}> 检查邮件完整性出错，错误码: {}", self.name, target.check_integrity());
-            self.save(target);
-            return;
+        //头部均已编码，非 ASCII 字节只会出现在 8bit 正文中
+        if eight_bit && lines.iter().any(|line| !line.is_ascii()){
+            parameters.push(String::from("BODY=8BITMIME"));
         }
 
         //发送 mail from:
-        let from_res = self._mail_from(target.from.clone().unwrap());
-        if let Ok(response) = from_res{
-            self.print_debug(&response);
-            if response.code != 250{
-                println!("客户端 <{}> 请求发送邮件发送方失败，错误信息: {:?}", self.name, response); return;
-            }
-        }else if let Err(error) = from_res{
-            println!("客户端 <{}> 请求发送邮件发送方失败，错误信息: {:?}", self.name, error); return;
+        let response = self._mail_from(Address::parse(mail.from.as_ref().unwrap()).email, parameters)?;
+        self.log_debug(&response);
+        if response.code != 250{
+            return Err(reply_error(&response));
         }
 
-        //发送 rcpt to:
-        let from_res = self._rcpt_to(target.to.clone().unwrap());
-        if let Ok(response) = from_res{
-            self.print_debug(&response);
-            if response.code != 250{
-                println!("客户端 <{}> 请求发送邮件接收方失败，错误信息: {:?}", self.name, response); return;
+        //逐个发送 rcpt to:，部分接收方被拒绝时仍继续发送
+        let mut delivery = Delivery::default();
+        for recipient in mail.recipients(){
+            let response = self._rcpt_to(recipient.clone())?;
+            self.log_debug(&response);
+            if response.code == 250 || response.code == 251{
+                delivery.accepted.push(recipient);
+            }else{
+                delivery.rejected.push(recipient);
             }
-        }else if let Err(error) = from_res{
-            println!("客户端 <{}> 请求发送邮件接收方失败，错误信息: {:?}", self.name, error); return;
         }
-
+        if delivery.accepted.is_empty(){
+            self.abort_transaction();
+            return Err(MessageError::RecipientsRejected(delivery.rejected).into());
+        }
 
         //发送data
-        let data_res = self._data();
-        if let Ok(response) = data_res{
-            self.print_debug(&response);
-            if response.code != 354{
-                self.save(target);
-                println!("客户端 <{}> 请求发送邮件内容失败，错误信息: {:?}", self.name, response); return;
-            }
-        }else if let Err(error) = data_res{
-            self.save(target);
-            println!("客户端 <{}> 请求发送邮件内容失败，错误信息: {:?}", self.name, error); return;
+        let response = self._data()?;
+        self.log_debug(&response);
+        if response.code != 354{
+            self.abort_transaction();
+            return Err(reply_error(&response));
         }
 
-
-        let subject = target.subject.clone().unwrap();
         //开始连续发送邮件主要内容
-        let send_lines_res = self._send_lines(serialize(target));
-        if let Ok(response) = send_lines_res{
-            self.print_debug(&response);
-            if response.code != 250{
-                println!("客户端 <{}> 发送邮件内容时出错，错误信息: {:?}", self.name, response); return;
-            }
-        }else if let Err(error) = send_lines_res{
-            println!("客户端 <{}> 发送邮件内容时出错，错误信息: {:?}", self.name, error); return;
+        let response = self._send_data(lines)?;
+        self.log_debug(&response);
+        if response.code != 250{
+            self.abort_transaction();
+            return Err(reply_error(&response));
         }
+        Ok(delivery)
+    }
 
-        println!("客户端 <{}> 已成功发送邮件 [{}]", self.name, subject);
-
+    fn abort_transaction(&mut self){
+        //以 rset 清除服务器上的发件状态，之后可以继续发送其他邮件
+        if let Ok(response) = self._rset(){
+            self.log_debug(&response);
+        }
     }
 
-    pub fn show_mail_group(&mut self){
-        if self.mail_group.len() == 0{
-            println!("客户端 <{}> 的已保存邮件列表中无内容", self.name); return;
+    pub fn noop(&mut self) -> Result<(), MailError>{
+        if let State::Init = self.state{
+            return Err(MailError::ClientState(String::from("尚未发起连接")));
         }
-        println!("客户端 <{}> 的已保存邮件列表: ", self.name);
-        let mut counter: usize = 0;
-        for mail in &self.mail_group{
-            counter += 1;
-            println!("[{}]  {}", counter, mail.subject.clone().unwrap());
+        let response = self._noop()?;
+        self.log_debug(&response);
+        if response.code != 250{
+            return Err(reply_error(&response));
         }
-        println!();
+        Ok(())
     }
 
-    pub fn quit(&mut self){
-        let quit_res = self._quit();
-        if let Ok(response) = quit_res{
-            self.print_debug(&response);
-            if response.code != 221{
-                println!("客户端 <{}> 退出失败，错误信息: {:?}", self.name, response); return;
-            }
-        }else if let Err(error) = quit_res{
-            println!("客户端 <{}> 退出失败，错误信息: {:?}", self.name, error); return;
+    pub fn keepalive(&mut self) -> Result<bool, MailError>{
+        if !self.client.needs_keepalive(){
+            return Ok(false);
+        }
+        self.noop()?;
+        Ok(true)
+    }
+
+    pub fn close(&mut self) -> Result<(), MailError>{
+        let response = self._quit()?;
+        self.log_debug(&response);
+        if response.code != 221{
+            return Err(reply_error(&response));
         }
         self.client.shutdown();
-        self.mail_group.clear();
         self.state = State::Init;
+        Ok(())
     }
 
     pub fn reset(&mut self){
         self.client.shutdown();
-        self.debug = false;
+        self.capabilities = None;
+        self.client.set_debug(false);
         self.state = State::Init;
     }
 
 }
 
-fn parse_response_group(response_group: Vec<String>) -> Result<Response, MailError>{
-    if response_group.len() == 0 { return Err(MailError::SMTPResponseParseError(String::from("No Response Group Found."))); }
-    let response_regex = Regex::new(r"(\d+).(.+)").unwrap();
-    let code = response_regex.captures(&response_group[0]).unwrap()[1].trim().parse::<usize>().unwrap();
-    let mut group: Vec<String> = Vec::new();
-    for line in response_group{
-        let cap = response_regex.captures(&line).unwrap();
-        group.push(cap[2].to_string());
-    }
-    return Ok(Response{
-        code,
-        group
-    })
+fn reply_error(response: &Response) -> MailError{
+    ReplyError::smtp(response.code, &response.group).into()
 }
 
-fn is_response_end(response: String) -> bool{
-    let end_regex = Regex::new(r"^(\d+).(.+)").unwrap();
-    return end_regex.is_match(&response);
+pub fn default_port(security: &Security) -> u16{
+    match security{
+        Security::Plain => 25,
+        Security::StartTls => 587,
+        Security::Tls => 465
+    }
 }
 
-fn serialize(mail: SMTPMail) -> Vec<String>{
-    let mut lines: Vec<String> = Vec::new();
-    lines.push(format!("from:<{}>", mail.from.clone().unwrap()));
-    lines.push(format!("to:<{}>",mail.to.clone().unwrap()));
-    lines.push(format!("subject:{}", mail.subject.clone().unwrap()));
-    lines.push(String::from("\r\n"));
-    lines.append(&mut mail.content.clone().unwrap());
-    lines.push(String::from("\r\n"));
-    lines.push(String::from("."));
-    lines
-}
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::thread;
+    use native_tls::Certificate;
+    use crate::tcp::test_support::{CERT, CERT_PATH, accept_tls, listen, stand_in_server};
+
+    const GREETING: &str = "220 localhost ESMTP stand-in\r\n";
+    const AUTH_REPLIES: [&str; 2] = ["250-localhost\r\n250 AUTH LOGIN PLAIN\r\n", "235 Authentication successful\r\n"];
+
+    fn smtp_server(security: Security, offer_starttls: bool) -> (u16, thread::JoinHandle<Vec<String>>){
+        match (security, offer_starttls){
+            (Security::Tls, _) => stand_in_server(Security::Tls, GREETING, AUTH_REPLIES.to_vec(), Vec::new()),
+            (_, true) => stand_in_server(Security::StartTls, GREETING,
+                vec!["250-localhost\r\n250-STARTTLS\r\n250 8BITMIME\r\n", "220 Ready to start TLS\r\n"], AUTH_REPLIES.to_vec()),
+            (_, false) => stand_in_server(Security::Plain, GREETING, vec!["250-localhost\r\n250 AUTH LOGIN\r\n", "334 VXNlcm5hbWU6\r\n"], Vec::new())
+        }
+    }
 
-pub fn run(name: String, account: String, password: String, host_name: String, mail_list: &mut Vec<SMTPMail>){
-    let mut client = smtp::Client::new(host_name, name);
-    client.mail_group.clone_from(mail_list);
-    client.account.replace(account);
-    client.password.replace(password);
-
-    loop{
-        let c = ||{
-            return if let State::Authorized = client.state {
-                "已登录".to_string()
-            } else { "未登录".to_string() }
-        };
-        println!("正在运行: smtp客户端 <{}> ({})，使用 -help 获得帮助", client.name, c());
-        let _input = util::input();
-        let mut __input = _input.split_whitespace();
-        if let Some(head) = __input.next(){
-            match head{
-                "-help" => {
-                    println!("-login  自动发起连接并尝试登录");
-                    println!("-list  显示邮件列表中内容");
-                    println!("-send [order number(int):邮件列表序号]  发送指定序号的邮件");
-                    println!("-reset  重置 smtp 客户端");
-                    println!("-debug  切换模式(普通/debug)");
-                    println!("-quit  退出 smtp 客户端");
-                }
-                "-login" => {
-                    client.connect();
-                    client.login();
-                }
-                "-list" => {
-                    if client.mail_group.len() == 0{
-                        println!("无已保存的邮件，可使用编辑器创建新的邮件"); continue;
-                    }
-                    println!("---已保存的邮件列表---");
-                    let mut counter: usize = 0;
-                    for mail in &client.mail_group{
-                        counter += 1;
-                        if let Some(sub) = &mail.subject{
-                            println!("[{}] {}", counter, sub);
-                        }else{
-                            println!("[{}] * Mail No Subject *", counter);
-                        }
-                    }
-                    println!();
-                }
-                "-send" => {
-                    if let Some(param1) = __input.next(){
-                        if let Ok(on) = param1.trim().parse::<usize>(){
-                            client.send(on);
-                        }else { print_error(); }
-                    }else { print_error(); }
-                }
-                "-reset" => {
-                    client.reset();
-                }
-                "-debug" => {
-                    client.debug();
-                }
-                "-quit" => {
-                    println!("将会退出 smtp 客户端，是否确认？(yes/no)");
-                    let s = util::input();
-                    if &s == "yes"{
-                        break;
-                    }else if &s == "no"{
-                        continue;
-                    }else { print_error(); }
-                }
-                _ => {
-                    print_error();
+    fn test_client(port: u16, tls_config: TlsConfig) -> Client{
+        let mut client = Client::new(String::from("localhost"), String::from("tester"));
+        client.client.set_port(port);
+        client.client.set_tls_config(tls_config);
+        client.account.replace(String::from("user@localhost"));
+        client.password.replace(String::from("secret"));
+        client
+    }
+
+    fn trusted(security: Security) -> TlsConfig{
+        let mut tls_config = TlsConfig::new(security);
+        tls_config.ca_roots.push(String::from(CERT_PATH));
+        tls_config
+    }
+
+    fn delivery_server(rejected: &'static str, final_reply: &'static str) -> (u16, thread::JoinHandle<Vec<String>>){
+        //按命令回复，rcpt to 的地址包含 rejected 时返回 550；邮件内容只记录为 <data>
+        let (listener, port) = listen();
+        let handle = thread::spawn(move ||{
+            let (stream, _) = listener.accept().unwrap();
+            let mut stream = BufReader::new(stream);
+            stream.get_mut().write_all(GREETING.as_bytes()).unwrap();
+            let mut received: Vec<String> = Vec::new();
+            let mut line = String::new();
+            while { line.clear(); stream.read_line(&mut line).unwrap_or(0) > 0 }{
+                let command = line.trim_end().to_string();
+                let reply = match command.split(' ').next().unwrap_or_default(){
+                    "ehlo" => "250-localhost\r\n250 AUTH PLAIN\r\n",
+                    "auth" => "235 Authentication successful\r\n",
+                    "rcpt" if command.contains(rejected) => "550 5.1.1 User unknown\r\n",
+                    "data" => "354 End data with <CR><LF>.<CR><LF>\r\n",
+                    _ => "250 OK\r\n"
+                };
+                received.push(command.clone());
+                stream.get_mut().write_all(reply.as_bytes()).unwrap();
+                if command == "data"{
+                    while { line.clear(); stream.read_line(&mut line).unwrap_or(0) > 0 } && line.trim_end() != "."{}
+                    received.push(String::from("<data>"));
+                    stream.get_mut().write_all(final_reply.as_bytes()).unwrap();
                 }
             }
-        }else{ print_error();}
+            received
+        });
+        (port, handle)
+    }
+
+    fn test_mail() -> SMTPMail{
+        let mut mail = SMTPMail::new();
+        mail.from = Some(String::from("user@localhost"));
+        mail.to = parse_address_list("\"Smith, Alice\" <alice@example.com>; nobody@example.com");
+        mail.subject = Some(String::from("hi"));
+        mail.content = Some(vec![String::from("hello")]);
+        mail
+    }
+
+    fn auth_commands() -> Vec<String>{
+        vec![
+            String::from("ehlo tester"),
+            format!("auth plain {}", base64::encode("\0user@localhost\0secret")),
+        ]
+    }
+
+    #[test]
+    fn parses_ehlo_capabilities(){
+        let lines = ["localhost greets tester", "SIZE 35882577", "AUTH=LOGIN PLAIN", "auth login xoauth2", "PIPELINING", "8BITMIME",
+            "SMTPUTF8", "DSN", "CHUNKING", "STARTTLS", "ENHANCEDSTATUSCODES", "X-CUSTOM value", "VRFY"];
+        let response = Response{ code: 250, group: lines.iter().map(|line| line.to_string()).collect() };
+        let caps = Capabilities::parse(&response);
+        assert_eq!(caps.size, Some(35882577));
+        assert_eq!(caps.auth, vec![String::from("LOGIN"), String::from("PLAIN"), String::from("XOAUTH2")]);
+        assert!(caps.pipelining && caps.eight_bit_mime && caps.smtp_utf8 && caps.dsn && caps.chunking && caps.starttls && caps.enhanced_status_codes);
+        assert_eq!(caps.others, vec![String::from("X-CUSTOM VALUE"), String::from("VRFY")]);
+
+        //SIZE 不带参数表示没有固定上限，首行域名不作为扩展
+        let response = Response{ code: 250, group: vec![String::from("SIZE"), String::from("SIZE")] };
+        let caps = Capabilities::parse(&response);
+        assert_eq!(caps.size, Some(0));
+        assert!(caps.auth.is_empty() && caps.others.is_empty() && !caps.starttls);
+        let response = Response{ code: 250, group: vec![String::from("localhost")] };
+        assert_eq!(Capabilities::parse(&response).size, None);
+    }
+
+    #[test]
+    fn splits_address_list_outside_quotes(){
+        assert_eq!(parse_address_list("\"Smith, Alice\" <alice@example.com>, bob@example.com;\"a;b\" <c@example.com>,"), vec![
+            String::from("\"Smith, Alice\" <alice@example.com>"), String::from("bob@example.com"), String::from("\"a;b\" <c@example.com>")
+        ]);
+    }
+
+    #[test]
+    fn delivers_to_accepted_recipients_only(){
+        let (port, server) = delivery_server("nobody", "250 OK queued\r\n");
+        let mut client = test_client(port, TlsConfig::new(Security::Plain));
+        client.set_allow_plaintext_auth(true);
+        client.open().unwrap();
+        client.authorize().unwrap();
+        let delivery = client.deliver(&test_mail()).unwrap();
+        assert_eq!(delivery.accepted, vec![String::from("alice@example.com")]);
+        assert_eq!(delivery.rejected, vec![String::from("nobody@example.com")]);
+        client.reset();
+        let mut expected = auth_commands();
+        expected.extend(vec![
+            String::from("mail from:<user@localhost>"), String::from("rcpt to:<alice@example.com>"), String::from("rcpt to:<nobody@example.com>"),
+            String::from("data"), String::from("<data>")
+        ]);
+        assert_eq!(server.join().unwrap(), expected);
+    }
+
+    #[test]
+    fn resets_transaction_after_rejected_data(){
+        let (port, server) = delivery_server("nobody", "554 5.7.1 Message rejected\r\n");
+        let mut client = test_client(port, TlsConfig::new(Security::Plain));
+        client.set_allow_plaintext_auth(true);
+        client.open().unwrap();
+        client.authorize().unwrap();
+        match client.deliver(&test_mail()){
+            Err(MailError::Reply(reply)) => { assert_eq!(reply.code, Some(554)); }
+            _ => panic!("rejected message reported as sent")
+        }
+        assert!(matches!(client.state, State::Authorized));
+        client.reset();
+        let received = server.join().unwrap();
+        assert_eq!(&received[received.len() - 2..], &[String::from("<data>"), String::from("rset")]);
     }
-    mail_list.clone_from(&client.mail_group);
-}
\ No newline at end of file
+
+    #[test]
+    fn login_upgrades_with_starttls(){
+        let (port, server) = smtp_server(Security::StartTls, true);
+        let mut client = test_client(port, trusted(Security::StartTls));
+        client.open().unwrap();
+        client.authorize().unwrap();
+        assert!(matches!(client.state, State::Authorized));
+        assert!(client.client.is_secure());
+        assert_eq!(client.capabilities.as_ref().unwrap().auth, vec![String::from("LOGIN"), String::from("PLAIN")]);
+        client.reset();
+
+        let mut expected = vec![String::from("ehlo tester"), String::from("starttls"), String::from("<tls>")];
+        expected.append(&mut auth_commands());
+        assert_eq!(server.join().unwrap(), expected);
+    }
+
+    #[test]
+    fn login_refuses_plaintext_auth(){
+        let (port, server) = smtp_server(Security::StartTls, false);
+        let mut client = test_client(port, trusted(Security::StartTls));
+        client.open().unwrap();
+        assert!(matches!(client.authorize(), Err(MailError::Auth(AuthError::TlsRequired(_)))));
+        assert!(matches!(client.state, State::TCPConnected));
+        assert!(!client.client.is_secure());
+        client.reset();
+
+        assert_eq!(server.join().unwrap(), vec![String::from("ehlo tester")]);
+    }
+
+    #[test]
+    fn greets_with_address_literal_without_hostname(){
+        let (port, server) = stand_in_server(Security::Plain, GREETING, vec!["250 localhost\r\n"], Vec::new());
+        let mut client = test_client(port, TlsConfig::new(Security::Plain));
+        client.name.clear();
+        client.open().unwrap();
+        assert_eq!(client.domain(), "[127.0.0.1]");
+        client.hello().unwrap();
+        client.reset();
+        assert_eq!(server.join().unwrap(), vec![String::from("ehlo [127.0.0.1]")]);
+    }
+
+    #[test]
+    fn starttls_missing_is_not_downgraded(){
+        //服务器不支持 STARTTLS 且只提供 CRAM-MD5 时，即便允许明文验证也不降级
+        let (port, server) = stand_in_server(Security::Plain, GREETING, vec!["250-localhost\r\n250 AUTH CRAM-MD5\r\n"], Vec::new());
+        let mut client = test_client(port, trusted(Security::StartTls));
+        client.set_allow_plaintext_auth(true);
+        client.open().unwrap();
+        match client.authorize(){
+            Err(MailError::Auth(AuthError::TlsRequired(extension))) => { assert_eq!(extension, "STARTTLS"); }
+            _ => panic!("missing STARTTLS not refused")
+        }
+        assert!(matches!(client.state, State::TCPConnected));
+        client.reset();
+        assert_eq!(server.join().unwrap(), vec![String::from("ehlo tester")]);
+    }
+
+    #[test]
+    fn answers_auth_challenges(){
+        //RFC 2195 中的 CRAM-MD5 示例
+        let (port, server) = stand_in_server(Security::Tls, GREETING, vec![
+            "250-localhost\r\n250 AUTH CRAM-MD5 PLAIN\r\n",
+            "334 PDE4OTYuNjk3MTcwOTUyQHBvc3RvZmZpY2UucmVzdG9uLm1jaS5uZXQ+\r\n",
+            "235 Authentication successful\r\n"
+        ], Vec::new());
+        let mut client = test_client(port, trusted(Security::Tls));
+        client.account.replace(String::from("tim"));
+        client.password.replace(String::from("tanstaaftanstaaf"));
+        client.open().unwrap();
+        client.authorize().unwrap();
+        assert!(matches!(client.state, State::Authorized));
+        client.reset();
+        assert_eq!(server.join().unwrap(), vec![
+            String::from("ehlo tester"), String::from("auth cram-md5"), String::from("dGltIGI5MTNhNjAyYzdlZGE3YTQ5NWI0ZTZlNzMzNGQzODkw")
+        ]);
+
+        //PLAIN 收到空质询时重新发送身份信息，收到其他质询时以 * 取消
+        let (port, server) = stand_in_server(Security::Tls, GREETING, vec![
+            "250-localhost\r\n250 AUTH PLAIN\r\n", "334 \r\n", "235 Authentication successful\r\n"
+        ], Vec::new());
+        let mut client = test_client(port, trusted(Security::Tls));
+        client.open().unwrap();
+        client.authorize().unwrap();
+        client.reset();
+        let mut expected = auth_commands();
+        expected.push(base64::encode("\0user@localhost\0secret"));
+        assert_eq!(server.join().unwrap(), expected);
+
+        let (port, server) = stand_in_server(Security::Tls, GREETING, vec![
+            "250-localhost\r\n250 AUTH PLAIN\r\n", "334 VXNlcm5hbWU6\r\n", "501 5.7.0 Authentication cancelled\r\n"
+        ], Vec::new());
+        let mut client = test_client(port, trusted(Security::Tls));
+        client.open().unwrap();
+        assert!(matches!(client.authorize(), Err(MailError::Auth(AuthError::UnexpectedChallenge(_)))));
+        assert!(matches!(client.state, State::TCPConnected));
+        client.reset();
+        let mut expected = auth_commands();
+        expected.push(String::from("*"));
+        assert_eq!(server.join().unwrap(), expected);
+    }
+
+    #[test]
+    fn plain_connection_requires_explicit_opt_in(){
+        let (port, server) = smtp_server(Security::Plain, false);
+        let mut client = test_client(port, TlsConfig::new(Security::Plain));
+        client.open().unwrap();
+        match client.authorize(){
+            Err(MailError::Auth(AuthError::TlsRequired(mechanism))) => { assert_eq!(mechanism, "LOGIN"); }
+            _ => panic!("plaintext auth not refused")
+        }
+        assert!(matches!(client.state, State::TCPConnected));
+        client.reset();
+        assert_eq!(server.join().unwrap(), vec![String::from("ehlo tester")]);
+
+        let (port, server) = stand_in_server(Security::Plain, GREETING, AUTH_REPLIES.to_vec(), Vec::new());
+        let mut client = test_client(port, TlsConfig::new(Security::Plain));
+        client.set_allow_plaintext_auth(true);
+        client.open().unwrap();
+        client.authorize().unwrap();
+        assert!(matches!(client.state, State::Authorized));
+        client.reset();
+        assert_eq!(server.join().unwrap(), auth_commands());
+    }
+
+    #[test]
+    fn login_over_implicit_tls_with_pinned_fingerprint(){
+        let (port, server) = smtp_server(Security::Tls, false);
+        let mut tls_config = TlsConfig::new(Security::Tls);
+        let der = Certificate::from_pem(CERT).unwrap().to_der().unwrap();
+        tls_config.pinned_fingerprint.replace(tcp::fingerprint(&der).to_lowercase().replace(":", ""));
+        let mut client = test_client(port, tls_config);
+        client.open().unwrap();
+        client.authorize().unwrap();
+        assert!(matches!(client.state, State::Authorized));
+        assert!(client.client.is_secure());
+        client.reset();
+
+        assert_eq!(server.join().unwrap(), auth_commands());
+    }
+
+    #[test]
+    fn service_closing_reply_returns_to_init(){
+        let (port, server) = stand_in_server(Security::Plain, GREETING, vec!["421 4.3.2 Service shutting down\r\n"], Vec::new());
+        let mut client = test_client(port, TlsConfig::new(Security::Plain));
+        client.open().unwrap();
+        match client.authorize(){
+            Err(MailError::Reply(reply)) => { assert_eq!((reply.code, reply.text.as_str()), (Some(421), "Service shutting down")); }
+            _ => panic!("421 not reported")
+        }
+        assert!(matches!(client.state, State::Init));
+        assert!(client.capabilities.is_none() && !client.client.is_connected());
+        assert_eq!(server.join().unwrap(), vec![String::from("ehlo tester")]);
+    }
+
+    #[test]
+    fn implicit_tls_rejects_mismatched_fingerprint(){
+        let (listener, port) = listen();
+        let server = thread::spawn(move ||{
+            let (stream, _) = listener.accept().unwrap();
+            let mut tls_stream = accept_tls(stream);
+            let mut buf = [0u8; 1];
+            tls_stream.read(&mut buf).unwrap_or(0)
+        });
+        let mut tls_config = TlsConfig::new(Security::Tls);
+        tls_config.pinned_fingerprint.replace(String::from("00:11:22"));
+        let mut client = test_client(port, tls_config);
+        assert!(client.open().is_err());
+        assert!(matches!(client.state, State::Init));
+        assert!(!client.client.is_secure());
+        assert_eq!(server.join().unwrap(), 0);
+    }
+}
@@ -0,0 +1,216 @@
+use crate::error::MailError;
+use crate::maildir::{Maildir, MaildirMessage};
+use crate::util;
+use chrono::prelude::*;
+use std::path::{Path, PathBuf};
+
+//唯一标识索引保存在 Maildir 根目录中，其他 Maildir 客户端会忽略 tmp/new/cur 之外的文件
+const INDEX_FILE: &str = "pop3_uidl";
+
+//默认存储目录位于 XDG 数据目录(通常为 ~/.local/share)下的 mail_client/mail_store
+const DATA_DIR: &str = "mail_client";
+const STORE_DIR: &str = "mail_store";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Retention{
+    //邮件始终保留在服务器上
+    LeaveOnServer,
+    //下载 N 天后从服务器删除，0 表示下载后立即删除
+    DeleteAfterDays(u32)
+}
+
+impl Retention{
+    pub fn parse(s: &str) -> Option<Retention>{
+        let s = s.trim().to_lowercase();
+        if s == "keep"{
+            return Some(Retention::LeaveOnServer);
+        }
+        s.parse::<u32>().ok().map(Retention::DeleteAfterDays)
+    }
+
+    pub fn name(&self) -> String{
+        match self{
+            Retention::LeaveOnServer => String::from("keep"),
+            Retention::DeleteAfterDays(days) => format!("delete after {} days", days)
+        }
+    }
+
+    //写入配置文件的形式，可由 parse 读回
+    pub fn setting(&self) -> String{
+        match self{
+            Retention::LeaveOnServer => String::from("keep"),
+            Retention::DeleteAfterDays(days) => days.to_string()
+        }
+    }
+
+    pub fn should_delete(&self, fetched_at: i64, now: i64) -> bool{
+        match self{
+            Retention::LeaveOnServer => false,
+            Retention::DeleteAfterDays(days) => now - fetched_at >= *days as i64 * 24 * 3600
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SyncConfig{
+    //本地邮件存储的根目录，每个账号使用其中的一个子目录
+    pub store_dir: String,
+    pub retention: Retention
+}
+
+impl Default for SyncConfig{
+    fn default() -> SyncConfig{
+        SyncConfig{
+            store_dir: default_store_dir().to_string_lossy().to_string(),
+            retention: Retention::LeaveOnServer
+        }
+    }
+}
+
+impl SyncConfig{
+    pub fn new() -> SyncConfig{
+        SyncConfig::default()
+    }
+}
+
+pub fn default_store_dir() -> PathBuf{
+    //与配置文件一样不依赖启动时的工作目录，无法确定数据目录时才使用当前目录
+    dirs::data_dir().unwrap_or_else(|| PathBuf::from(".")).join(DATA_DIR).join(STORE_DIR)
+}
+
+#[derive(Debug, Clone)]
+pub struct StoredMessage{
+    //服务器 UIDL 返回的唯一标识
+    pub uid: String,
+    //下载时间(Unix 时间戳，秒)
+    pub fetched_at: i64,
+    pub size: usize,
+    //邮件在 Maildir 中的唯一文件名(不含标志部分)
+    pub key: String
+}
+
+pub struct Store{
+    root: PathBuf,
+    maildir: Maildir,
+    messages: Vec<StoredMessage>
+}
+
+impl Store{
+    pub fn open(root: &Path) -> Result<Store, MailError>{
+        //索引文件每行为 "uid\t下载时间\t大小\tMaildir 文件名"，RFC 1939 规定 uid 只包含 0x21-0x7E 字符，因此不会含有制表符
+        let mut store = Store{
+            root: root.to_path_buf(),
+            maildir: Maildir::open(root)?,
+            messages: Vec::new()
+        };
+        let index = match std::fs::read_to_string(root.join(INDEX_FILE)){
+            Ok(index) => index,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(error) => { return Err(MailError::StoreFile{ path: root.join(INDEX_FILE).display().to_string(), source: error }); }
+        };
+        for line in index.lines(){
+            let fields: Vec<&str> = line.split('\t').collect();
+            if let [uid, fetched_at, size, key] = fields[..]{
+                if let (Ok(fetched_at), Ok(size)) = (fetched_at.parse::<i64>(), size.parse::<usize>()){
+                    store.messages.push(StoredMessage{ uid: uid.to_string(), fetched_at, size, key: key.to_string() });
+                    continue;
+                }
+            }
+            return Err(MailError::Store(format!("索引文件格式错误: {}", line)));
+        }
+        Ok(store)
+    }
+
+    pub fn contains(&self, uid: &str) -> bool{
+        self.messages.iter().any(|message| message.uid == uid)
+    }
+
+    pub fn get(&self, uid: &str) -> Option<&StoredMessage>{
+        self.messages.iter().find(|message| message.uid == uid)
+    }
+
+    pub fn messages(&self) -> &[StoredMessage]{
+        &self.messages
+    }
+
+    pub fn insert(&mut self, uid: &str, raw: &[u8]) -> Result<(), MailError>{
+        //先投递到 Maildir 再更新索引，中断时最多留下一封未索引的邮件，下次同步会重新下载
+        if self.contains(uid){
+            return Ok(());
+        }
+        let key = self.maildir.deliver(raw)?;
+        self.messages.push(StoredMessage{
+            uid: uid.to_string(),
+            fetched_at: Utc::now().timestamp(),
+            size: raw.len(),
+            key
+        });
+        self.save_index()
+    }
+
+    pub fn read(&self, uid: &str) -> Result<Vec<u8>, MailError>{
+        let message = match self.get(uid){
+            Some(message) => message,
+            None => { return Err(MailError::Store(format!("邮件不存在: {}", uid))); }
+        };
+        self.maildir.read(&message.key)
+    }
+
+    pub fn mark_seen(&self, key: &str) -> Result<(), MailError>{
+        self.maildir.add_flag(key, 'S')
+    }
+
+    //Maildir 中的全部邮件，包括其他客户端投递的邮件
+    pub fn local_messages(&self) -> Result<Vec<MaildirMessage>, MailError>{
+        self.maildir.list()
+    }
+
+    fn save_index(&self) -> Result<(), MailError>{
+        let mut content = String::new();
+        for message in &self.messages{
+            content.push_str(&format!("{}\t{}\t{}\t{}\n", message.uid, message.fetched_at, message.size, message.key));
+        }
+        let path = self.root.join(INDEX_FILE);
+        if let Err(error) = util::atomic_write(&path, content.as_bytes()){
+            return Err(MailError::StoreFile{ path: path.display().to_string(), source: error });
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn persists_messages_by_uid(){
+        let root = std::env::temp_dir().join(format!("mail_client_store_{}", std::process::id()));
+        let mut store = Store::open(&root).unwrap();
+        store.insert("uid/1", b"Subject: a\r\n\r\nbody\r\n").unwrap();
+        store.insert("uid/1", b"ignored").unwrap();
+        store.insert("uid-2", b"second").unwrap();
+        let reopened = Store::open(&root).unwrap();
+        assert_eq!(reopened.messages().len(), 2);
+        assert!(reopened.contains("uid/1") && reopened.contains("uid-2") && !reopened.contains("uid-3"));
+        assert_eq!(reopened.read("uid/1").unwrap(), b"Subject: a\r\n\r\nbody\r\n");
+        assert_eq!(reopened.get("uid-2").unwrap().size, 6);
+        reopened.mark_seen(&reopened.get("uid-2").unwrap().key).unwrap();
+        let local = reopened.local_messages().unwrap();
+        assert_eq!(local.len(), 2);
+        assert_eq!(local.iter().filter(|message| message.flags == "S" && !message.is_new).count(), 1);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn applies_retention_policy(){
+        let day = 24 * 3600;
+        assert!(!Retention::LeaveOnServer.should_delete(0, 365 * day));
+        assert!(Retention::DeleteAfterDays(0).should_delete(100, 100));
+        assert!(!Retention::DeleteAfterDays(7).should_delete(0, 7 * day - 1));
+        assert!(Retention::DeleteAfterDays(7).should_delete(0, 7 * day));
+        assert_eq!(Retention::parse("KEEP"), Some(Retention::LeaveOnServer));
+        assert_eq!(Retention::parse("30"), Some(Retention::DeleteAfterDays(30)));
+        assert_eq!(Retention::parse("soon"), None);
+    }
+}
@@ -1,15 +1,132 @@
-use std::io::{BufReader, Write, BufRead};
-use std::net::TcpStream;
+use std::io::{BufReader, Write, BufRead, Read};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
 use std::ops::Add;
-use crate::util::MailError;
+use std::time::{Duration, Instant};
+use crate::error::{MailError, TransportError, IoOperation};
 use std::net::Shutdown::Both;
+use native_tls::{TlsConnector, TlsStream, Certificate};
+use sha2::{Sha256, Digest};
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Security{
+    Plain, StartTls, Tls
+}
+
+impl Security{
+    pub fn parse(s: &str) -> Option<Security>{
+        match s.trim().to_lowercase().as_str(){
+            "plain" => Some(Security::Plain),
+            "starttls" => Some(Security::StartTls),
+            "tls" | "ssl" => Some(Security::Tls),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str{
+        match self{
+            Security::Plain => "plain",
+            Security::StartTls => "starttls",
+            Security::Tls => "tls"
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TlsConfig{
+    pub security: Security,
+    //PEM 格式的根证书文件路径，为空时使用系统证书
+    pub ca_roots: Vec<String>,
+    //服务器证书的 SHA-256 指纹(十六进制，可含冒号)，设置后跳过证书链校验，只校验指纹
+    pub pinned_fingerprint: Option<String>
+}
+
+impl TlsConfig{
+    pub fn new(security: Security) -> TlsConfig{
+        TlsConfig{
+            security,
+            ca_roots: Vec::new(),
+            pinned_fingerprint: None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timeouts{
+    pub connect: Duration,
+    //读取或发送单行内容的最长等待时间，超时后连接被断开
+    pub read: Duration,
+    pub write: Duration,
+    //连接空闲超过该时间后由 pop3/smtp 客户端发送 NOOP，None 表示不保活
+    pub keepalive: Option<Duration>
+}
+
+impl Default for Timeouts{
+    fn default() -> Timeouts{
+        Timeouts{
+            connect: Duration::from_secs(30),
+            read: Duration::from_secs(60),
+            write: Duration::from_secs(60),
+            keepalive: Some(Duration::from_secs(120))
+        }
+    }
+}
+
+
+pub enum Stream{
+    Plain(TcpStream),
+    Tls(TlsStream<TcpStream>)
+}
+
+impl Read for Stream{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self{
+            Stream::Plain(stream) => { stream.read(buf) }
+            Stream::Tls(stream) => { stream.read(buf) }
+        }
+    }
+}
+
+impl Write for Stream{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self{
+            Stream::Plain(stream) => { stream.write(buf) }
+            Stream::Tls(stream) => { stream.write(buf) }
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self{
+            Stream::Plain(stream) => { stream.flush() }
+            Stream::Tls(stream) => { stream.flush() }
+        }
+    }
+}
+
+impl Stream{
+    fn shutdown(self){
+        match self{
+            Stream::Plain(stream) => { let _ = stream.shutdown(Both); }
+            Stream::Tls(mut stream) => {
+                let _ = stream.shutdown();
+                let _ = stream.get_ref().shutdown(Both);
+            }
+        }
+    }
+}
 
 
 pub struct Client {
     host_name: String,
     port: u16,
-    connection: Option<BufReader<TcpStream>>,
-    end_pointer: String
+    connection: Option<BufReader<Stream>>,
+    tls_config: TlsConfig,
+    timeouts: Timeouts,
+    //最近一次发送或接收数据的时间，用于判断是否需要保活
+    last_activity: Instant,
+    end_pointer: String,
+    debug: bool,
+    debug_log: Vec<String>
 }
 
 impl Client{
@@ -19,7 +136,12 @@ impl Client{
             host_name,
             port,
             connection: None,
-            end_pointer: String::from("")
+            tls_config: TlsConfig::new(Security::Plain),
+            timeouts: Timeouts::default(),
+            last_activity: Instant::now(),
+            end_pointer: String::from(""),
+            debug: false,
+            debug_log: Vec::new()
         }
     }
 
@@ -31,58 +153,264 @@ impl Client{
         self.end_pointer = end_pointer;
     }
 
+    pub fn set_tls_config(&mut self, tls_config: TlsConfig){
+        self.tls_config = tls_config;
+    }
+
+    pub fn set_timeouts(&mut self, timeouts: Timeouts){
+        //对之后建立的连接生效
+        self.timeouts = timeouts;
+    }
+
+    pub fn timeouts(&self) -> Timeouts{
+        self.timeouts
+    }
+
+    pub fn is_connected(&self) -> bool{
+        self.connection.is_some()
+    }
+
+    pub fn idle_time(&self) -> Duration{
+        self.last_activity.elapsed()
+    }
+
+    pub fn keepalive_interval(&self) -> Option<Duration>{
+        self.timeouts.keepalive
+    }
+
+    pub fn needs_keepalive(&self) -> bool{
+        //已连接且空闲超过保活间隔时，由 pop3/smtp 客户端发送 NOOP；NOOP 失败时连接随之断开
+        match (&self.connection, self.timeouts.keepalive){
+            (Some(_), Some(interval)) => self.idle_time() >= interval,
+            _ => false
+        }
+    }
+
+    pub fn is_debug(&self) -> bool{
+        self.debug
+    }
+
+    pub fn set_debug(&mut self, debug: bool){
+        self.debug = debug;
+        self.debug_log.clear();
+    }
+
+    pub fn log_debug(&mut self, entry: String){
+        if self.debug{
+            self.debug_log.push(entry);
+        }
+    }
+
+    pub fn take_debug_log(&mut self) -> Vec<String>{
+        //debug 模式下记录的服务器响应，由调用方决定如何输出
+        std::mem::take(&mut self.debug_log)
+    }
+
+    pub fn security(&self) -> Security{
+        self.tls_config.security.clone()
+    }
+
+    pub fn is_secure(&self) -> bool{
+        if let Some(connection) = &self.connection{
+            if let Stream::Tls(_) = connection.get_ref(){
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr>{
+        let stream = match self.connection.as_ref()?.get_ref(){
+            Stream::Plain(stream) => stream,
+            Stream::Tls(stream) => stream.get_ref()
+        };
+        stream.local_addr().ok()
+    }
+
     pub fn connect(&mut self) -> Result<(), MailError>{
-        let copy = self.host_name.clone();
-        return if let Ok(client) = TcpStream::connect((copy.as_str(), self.port)) {
-            self.connection.replace(BufReader::new(client));
+        self.shutdown();
+        let client = self.open_stream()?;
+        self.connection.replace(BufReader::new(Stream::Plain(client)));
+        self.last_activity = Instant::now();
+        if let Security::Tls = self.tls_config.security{
+            //隐式 TLS(smtps/pop3s)在收到问候语之前即完成握手
+            self.starttls()
+        }else{
             Ok(())
-        } else {
-            self.connection.take();
-            Err(MailError::TCPConnectFailError)
+        }
+    }
+
+    fn open_stream(&self) -> Result<TcpStream, MailError>{
+        //依次尝试解析得到的每个地址，读写超时同样作用于之后升级的 TLS 连接
+        let address = format!("{}:{}", self.host_name, self.port);
+        let candidates = match (self.host_name.as_str(), self.port).to_socket_addrs(){
+            Ok(candidates) => candidates,
+            Err(error) => { return Err(TransportError::Connect{ address, source: error }.into()); }
+        };
+        let mut last_error = std::io::Error::new(std::io::ErrorKind::NotFound, "no address resolved");
+        for candidate in candidates{
+            match TcpStream::connect_timeout(&candidate, self.timeouts.connect){
+                Ok(stream) => {
+                    let result = stream.set_read_timeout(Some(self.timeouts.read))
+                        .and_then(|_| stream.set_write_timeout(Some(self.timeouts.write)));
+                    return match result{
+                        Ok(()) => Ok(stream),
+                        Err(error) => Err(TransportError::Connect{ address, source: error }.into())
+                    };
+                }
+                Err(error) => { last_error = error; }
+            }
+        }
+        if last_error.kind() == std::io::ErrorKind::TimedOut{
+            return Err(TransportError::Timeout(IoOperation::Connect).into());
+        }
+        Err(TransportError::Connect{ address, source: last_error }.into())
+    }
+
+    fn build_connector(&self) -> Result<TlsConnector, MailError>{
+        let mut builder = TlsConnector::builder();
+        for path in &self.tls_config.ca_roots{
+            let pem = match std::fs::read(path){
+                Ok(pem) => pem,
+                Err(error) => { return Err(TransportError::CaFile{ path: path.clone(), source: error }.into()); }
+            };
+            match Certificate::from_pem(&pem){
+                Ok(certificate) => { builder.add_root_certificate(certificate); }
+                Err(error) => { return Err(TransportError::Certificate{ path: path.clone(), source: error }.into()); }
+            }
+        }
+        if self.tls_config.pinned_fingerprint.is_some(){
+            builder.danger_accept_invalid_certs(true);
+        }
+        match builder.build(){
+            Ok(connector) => Ok(connector),
+            Err(error) => Err(TransportError::Tls(error).into())
+        }
+    }
+
+    fn verify_fingerprint(&self, stream: &TlsStream<TcpStream>) -> Result<(), MailError>{
+        let pinned = match &self.tls_config.pinned_fingerprint{
+            Some(pinned) => normalize_fingerprint(pinned),
+            None => { return Ok(()); }
+        };
+        let certificate = match stream.peer_certificate(){
+            Ok(Some(certificate)) => certificate,
+            Ok(None) => { return Err(TransportError::FingerprintMismatch(String::from("无服务器证书")).into()); }
+            Err(error) => { return Err(TransportError::Tls(error).into()); }
+        };
+        let der = match certificate.to_der(){
+            Ok(der) => der,
+            Err(error) => { return Err(TransportError::Tls(error).into()); }
+        };
+        let actual = fingerprint(&der);
+        if actual == pinned{
+            Ok(())
+        }else{
+            Err(TransportError::FingerprintMismatch(actual).into())
+        }
+    }
+
+    pub fn starttls(&mut self) -> Result<(), MailError>{
+        //将已建立的明文连接升级为 TLS 连接，需在服务器同意升级(如 smtp 的 220 回复)后调用
+        let connection = match self.connection.take(){
+            Some(connection) => connection,
+            None => { return Err(TransportError::NotConnected.into()); }
+        };
+        if !connection.buffer().is_empty(){
+            //升级前服务器不应再发送任何数据，否则可能是明文注入
+            connection.into_inner().shutdown();
+            return Err(TransportError::UnexpectedData.into());
+        }
+        match connection.into_inner(){
+            Stream::Plain(stream) => {
+                let connector = self.build_connector()?;
+                match connector.connect(&self.host_name, stream){
+                    Ok(tls_stream) => {
+                        if let Err(error) = self.verify_fingerprint(&tls_stream){
+                            Stream::Tls(tls_stream).shutdown();
+                            return Err(error);
+                        }
+                        self.connection.replace(BufReader::new(Stream::Tls(tls_stream)));
+                        Ok(())
+                    }
+                    Err(native_tls::HandshakeError::Failure(error)) => {
+                        Err(TransportError::Tls(error).into())
+                    }
+                    Err(native_tls::HandshakeError::WouldBlock(_)) => {
+                        //握手期间等待服务器数据超时
+                        Err(TransportError::Timeout(IoOperation::Read).into())
+                    }
+                }
+            }
+            Stream::Tls(stream) => {
+                self.connection.replace(BufReader::new(Stream::Tls(stream)));
+                Ok(())
+            }
         }
     }
 
     pub fn send(&mut self, content: String) -> Result<(), MailError>{
         return if let Some(connection) = &mut self.connection {
             let content = content.add(&self.end_pointer);
-            return if let Ok(k) = connection.get_mut().write(content.as_bytes()) {
-                Ok(())
-            } else {
-                Err(MailError::TCPWriteError)
+            let result = connection.get_mut().write_all(content.as_bytes());
+            self.last_activity = Instant::now();
+            return match result {
+                Ok(()) => Ok(()),
+                Err(error) => {
+                    //写入失败后连接状态未知，直接断开
+                    self.shutdown();
+                    Err(transport_error(IoOperation::Write, error).into())
+                }
             }
         } else {
-            Err(MailError::TCPNoConnectionError)
+            Err(TransportError::NotConnected.into())
         }
     }
 
     pub fn shutdown(&mut self){
         if let Some(connection) = self.connection.take(){
-            connection.into_inner().shutdown(Both);
+            connection.into_inner().shutdown();
         }
     }
 
     pub fn receive(&mut self) -> Result<String, MailError>{
-        if let Some(mut connection) = self.connection.take(){
-            let mut buf: Vec<u8> = Vec::new();
-            connection.read_until(b'\n', &mut buf);
-            self.connection.replace(connection);
-            return if let Ok(s) = String::from_utf8(buf) {
-                Ok(s)
-            } else {
-                Err(MailError::TCPFromUTF8Error)
+        let buf = self.receive_bytes()?;
+        return match String::from_utf8(buf) {
+            Ok(s) => Ok(s),
+            Err(error) => Err(TransportError::InvalidUtf8(error).into())
+        }
+    }
+
+    pub fn receive_bytes(&mut self) -> Result<Vec<u8>, MailError>{
+        //读取一行原始字节(含换行符)，不要求为 UTF-8；超时、服务器关闭连接或读取出错时断开连接
+        let connection = match &mut self.connection{
+            Some(connection) => connection,
+            None => { return Err(TransportError::NotConnected.into()); }
+        };
+        let mut buf: Vec<u8> = Vec::new();
+        let result = connection.read_until(b'\n', &mut buf);
+        self.last_activity = Instant::now();
+        match result{
+            Ok(0) => {
+                self.shutdown();
+                Err(TransportError::Closed.into())
+            }
+            Ok(_) => Ok(buf),
+            Err(error) => {
+                self.shutdown();
+                Err(transport_error(IoOperation::Read, error).into())
             }
         }
-        return Err(MailError::TCPNoConnectionError);
     }
 
     pub fn error_handler(&mut self, error: MailError) -> Result<(), MailError>{
         match error{
-            MailError::TCPNoConnectionError => {
+            MailError::Transport(TransportError::NotConnected) => {
                 if let Err(error) = self.connect(){
                     return Err(error);
                 }
             },
-            MailError::TCPConnectFailError => {
+            MailError::Transport(TransportError::Connect{ .. }) => {
                 if let Err(error) = self.connect(){
                     return Err(error);
                 }
@@ -92,4 +420,90 @@ impl Client{
         Ok(())
     }
 
-}
\ No newline at end of file
+}
+
+fn transport_error(operation: IoOperation, error: std::io::Error) -> TransportError{
+    //设置了超时的套接字在超时后返回 WouldBlock(Unix) 或 TimedOut(Windows)
+    match error.kind(){
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => TransportError::Timeout(operation),
+        _ => match operation{
+            IoOperation::Write => TransportError::Write(error),
+            _ => TransportError::Read(error)
+        }
+    }
+}
+
+pub fn fingerprint(der: &[u8]) -> String{
+    //以冒号分隔的大写十六进制 SHA-256 指纹
+    Sha256::digest(der).iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<String>>().join(":")
+}
+
+fn normalize_fingerprint(fingerprint: &str) -> String{
+    let hex: Vec<char> = fingerprint.chars().filter(|c| c.is_ascii_hexdigit()).map(|c| c.to_ascii_uppercase()).collect();
+    hex.chunks(2).map(|pair| pair.iter().collect::<String>()).collect::<Vec<String>>().join(":")
+}
+
+
+#[cfg(test)]
+pub(crate) mod test_support{
+    //pop3 与 smtp 测试共用的本地替身服务器
+    use super::Security;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use native_tls::{Identity, TlsAcceptor, TlsStream};
+
+    pub const CERT: &[u8] = include_bytes!("../tests/fixtures/localhost.crt");
+    pub const KEY: &[u8] = include_bytes!("../tests/fixtures/localhost.key");
+    pub const CERT_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/localhost.crt");
+
+    pub fn listen() -> (TcpListener, u16){
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        (listener, port)
+    }
+
+    pub fn accept_tls(stream: TcpStream) -> TlsStream<TcpStream>{
+        let identity = Identity::from_pkcs8(CERT, KEY).unwrap();
+        TlsAcceptor::new(identity).unwrap().accept(stream).unwrap()
+    }
+
+    pub fn converse<S: Read + Write>(stream: &mut BufReader<S>, received: &mut Vec<String>, reply: &str) -> bool{
+        //读取客户端的一行命令并回复
+        let mut line = String::new();
+        if stream.read_line(&mut line).unwrap_or(0) == 0{ return false; }
+        received.push(line.trim_end().to_string());
+        stream.get_mut().write_all(reply.as_bytes()).unwrap();
+        true
+    }
+
+    fn reply_all<S: Read + Write>(stream: &mut BufReader<S>, received: &mut Vec<String>, replies: &[&str]) -> bool{
+        replies.iter().all(|reply| converse(stream, received, reply))
+    }
+
+    pub fn stand_in_server(security: Security, greeting: &'static str, replies: Vec<&'static str>, replies_after_tls: Vec<&'static str>) -> (u16, thread::JoinHandle<Vec<String>>){
+        //发送问候语后按顺序回复客户端的每条命令，返回收到的命令
+        //Tls 在问候语之前完成握手；StartTls 在回复完 replies 后握手，再回复 replies_after_tls
+        let (listener, port) = listen();
+        let handle = thread::spawn(move ||{
+            let mut received: Vec<String> = Vec::new();
+            let (stream, _) = listener.accept().unwrap();
+            if let Security::Tls = security{
+                let mut secure = BufReader::new(accept_tls(stream));
+                secure.get_mut().write_all(greeting.as_bytes()).unwrap();
+                reply_all(&mut secure, &mut received, &replies);
+                return received;
+            }
+            let mut plain = BufReader::new(stream);
+            plain.get_mut().write_all(greeting.as_bytes()).unwrap();
+            if !reply_all(&mut plain, &mut received, &replies) || security != Security::StartTls{
+                return received;
+            }
+            let mut secure = BufReader::new(accept_tls(plain.into_inner()));
+            received.push(String::from("<tls>"));
+            reply_all(&mut secure, &mut received, &replies_after_tls);
+            received
+        });
+        (port, handle)
+    }
+}
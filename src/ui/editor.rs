@@ -0,0 +1,488 @@
+use mail_client::smtp::{SMTPMail, Attachment, parse_address_list};
+use super::{input, print_error, describe};
+
+pub struct MailEditor{
+    current_order: usize,
+    current_saved: bool,
+    lines: Vec<String>,
+    saved_mails: Vec<SMTPMail>,
+    current_from: Option<String>,
+    current_to: Vec<String>,
+    current_cc: Vec<String>,
+    current_bcc: Vec<String>,
+    current_subject: Option<String>,
+    current_content: Option<Vec<String>>,
+    current_html: Option<String>,
+    current_attachments: Vec<Attachment>,
+    current_inline_images: Vec<Attachment>
+}
+
+impl MailEditor {
+    pub fn run(mail_list: &mut Vec<SMTPMail>){
+        let mut editor = MailEditor{
+            current_order: 0,
+            current_saved: false,
+            current_from: None,
+            current_to: Vec::new(),
+            current_cc: Vec::new(),
+            current_bcc: Vec::new(),
+            current_subject: None,
+            current_content: None,
+            current_html: None,
+            current_attachments: Vec::new(),
+            current_inline_images: Vec::new(),
+            lines: vec![],
+            saved_mails: mail_list.clone(),
+        };
+        loop{
+            println!("正在使用: 邮件编辑器<{}号邮件>，使用 -help 获取帮助", editor.current_order);
+            let _input = input();
+            let mut __input = _input.split_whitespace();
+            if let Some(head) = __input.next(){
+                match head{
+                    "-help" => {
+                        println!("-edit <from:邮件发送者>/<to:邮件接收者>/<cc:抄送>/<bcc:密送>/<subject:邮件主题>/<content:邮件内容 <append:添加行>/<line:修改行>/<remove:删除行>/<all:重新编辑所有内容>>/<html:HTML 正文>  编辑邮件内容");
+                        println!("-check <format:邮件格式信息>/<integrity:邮件完整性>  对邮件进行正确性检测");
+                        println!("-display <all:所有内容>/<subject:邮件主题>/<from:邮件发送者>/<to:邮件接收者>/<cc:抄送>/<bcc:密送>/<content:邮件内容>/<html:HTML 正文>/<attachments:附件>  显示编辑中的邮件内容");
+                        println!("-html [path:文件路径]  从文件读取 HTML 正文，未编辑纯文本内容时发送时自动生成");
+                        println!("-attach [path:文件路径]  为当前邮件添加附件");
+                        println!("-inline [path:文件路径]  添加 HTML 正文中以 cid:文件名 引用的内嵌图片");
+                        println!("-detach [index(int):附件序号]/<inline [index(int):内嵌图片序号]>  移除当前邮件的指定附件");
+                        println!("-list  显示已保存邮件列表");
+                        println!("-save  保存当前邮件");
+                        println!("-take [order number(int):已保存邮件列表中序号]/<new:创建新邮件>  切换编辑器至指定邮件");
+                        println!("-delete [order number(int):已保存邮件列表中序号]/<current:删除当前邮件>  删除指定邮件");
+                        println!("-quit 退出邮件编辑器");
+                        println!();
+                    }
+                    "-edit" => {
+                        if let Some(param1) = __input.next(){
+                            if editor.current_order == 0{
+                                println!("未找到正在编辑的邮件，请使用 -take 创建或加载邮件"); continue;
+                            }
+                            match param1{
+                                "from" => {
+                                    println!("请输入 [{}] 号邮件的发送邮箱地址:", editor.current_order);
+                                    let new_from = input();
+                                    editor.current_from.replace(new_from);
+                                    editor.current_saved = false;
+                                    println!("[{}] 号邮件的发送邮箱地址已修改为: [{}]", editor.current_order, editor.current_from.clone().unwrap());
+                                }
+                                "to" => {
+                                    println!("请输入 [{}] 号邮件的接收邮箱地址(多个地址以逗号分隔):", editor.current_order);
+                                    editor.current_to = parse_address_list(&input());
+                                    editor.current_saved = false;
+                                    println!("[{}] 号邮件的接收邮箱地址已修改为: {:?}", editor.current_order, editor.current_to);
+                                }
+                                "cc" => {
+                                    println!("请输入 [{}] 号邮件的抄送邮箱地址(多个地址以逗号分隔，留空则清除):", editor.current_order);
+                                    editor.current_cc = parse_address_list(&input());
+                                    editor.current_saved = false;
+                                    println!("[{}] 号邮件的抄送邮箱地址已修改为: {:?}", editor.current_order, editor.current_cc);
+                                }
+                                "bcc" => {
+                                    println!("请输入 [{}] 号邮件的密送邮箱地址(多个地址以逗号分隔，留空则清除):", editor.current_order);
+                                    editor.current_bcc = parse_address_list(&input());
+                                    editor.current_saved = false;
+                                    println!("[{}] 号邮件的密送邮箱地址已修改为: {:?}", editor.current_order, editor.current_bcc);
+                                }
+                                "subject" => {
+                                    println!("请输入 [{}] 号邮件的主题:", editor.current_order);
+                                    let input = input();
+                                    editor.current_subject.replace(input);
+                                    editor.current_saved = false;
+                                    println!("[{}] 号邮件的主题已修改为: [{}]", editor.current_order, editor.current_subject.clone().unwrap());
+                                }
+                                "html" => {
+                                    println!("请按行输入 [{}] 号邮件的 HTML 正文，若要结束输入请键入 -exit，不输入任何内容则清除", editor.current_order);
+                                    let mut html: Vec<String> = Vec::new();
+                                    loop{
+                                        let line = input();
+                                        if line.as_str() == "-exit"{ break; }
+                                        html.push(line);
+                                    }
+                                    editor.current_html = if html.is_empty() { None } else { Some(html.join("\n")) };
+                                    editor.current_saved = false;
+                                    println!("HTML 正文已更新，可使用 -display html 进行查看");
+                                    println!();
+                                }
+                                "content" => {
+                                    if let Some(param2) = __input.next(){
+                                        match param2{
+                                            "append" => {
+                                                println!("请按行输入邮件内容，若要结束输入请键入 -exit");
+                                                loop{
+                                                    let line = input();
+                                                    if line.as_str() == "-exit"{ break; }
+                                                    editor.lines.push(line);
+                                                }
+                                                editor.current_content.replace(editor.lines.clone());
+                                                editor.current_saved = false;
+                                                println!("邮件内容已更新，可使用 -display 进行查看");
+                                                println!();
+                                            }
+                                            "line" => {
+                                                println!("请输入要修改的行序号:");
+                                                let _on = input();
+                                                let __on = _on.trim().parse::<usize>();
+                                                if let Ok(on) = __on{
+                                                    if on > editor.lines.len(){
+                                                        print_error(); continue;
+                                                    }else{
+                                                        println!("请输入修改后的行内容:");
+                                                        let new_line = input();
+                                                        editor.lines[on - 1] = new_line;
+                                                        editor.current_content.replace(editor.lines.clone());
+                                                        editor.current_saved = false;
+                                                        println!("邮件内容已更新，可使用 -display 进行查看");
+                                                        println!();
+                                                    }
+                                                }else { print_error(); }
+                                            }
+                                            "remove" => {
+                                                println!("请输入要删除的行序号:");
+                                                let _on = input();
+                                                let __on = _on.trim().parse::<usize>();
+                                                if let Ok(on) = __on{
+                                                    if on > editor.lines.len(){
+                                                        print_error(); continue;
+                                                    }else{
+                                                        editor.lines.remove(on - 1);
+                                                        editor.current_saved = false;
+                                                        editor.current_content.replace(editor.lines.clone());
+                                                        println!("邮件内容已更新，可使用 -display 进行查看");
+                                                        println!();
+                                                    }
+                                                }else { print_error(); }
+                                            }
+                                            "all" => {
+                                                editor.lines.clear();
+                                                println!("请按行输入邮件内容，若要结束输入请键入 -exit");
+                                                loop{
+                                                    let line = input();
+                                                    if line.as_str() == "-exit"{ break; }
+                                                    editor.lines.push(line);
+                                                }
+                                                editor.current_content.replace(editor.lines.clone());
+                                                editor.current_saved = false;
+                                                println!("邮件内容已更新，可使用 -display 进行查看");
+                                                println!();
+                                            }
+                                            _ => { print_error(); }
+                                        }
+                                    }else { print_error(); }
+                                }
+                                _ => { print_error(); }
+                            }
+                        }else{ print_error(); }
+                    }
+                    "-attach" => {
+                        if editor.current_order == 0{
+                            println!("未找到正在编辑的邮件，请使用 -take 创建或加载邮件"); continue;
+                        }
+                        //路径中可能包含空格，取命令之后的全部内容
+                        let path = _input.trim_start()["-attach".len()..].trim();
+                        if path.is_empty(){ print_error(); continue; }
+                        match Attachment::from_path(path){
+                            Ok(attachment) => {
+                                println!("[{}] 号邮件已添加附件 [{}] ({})", editor.current_order, attachment.filename, attachment.mime_type);
+                                editor.current_attachments.push(attachment);
+                                editor.current_saved = false;
+                            }
+                            Err(error) => {
+                                println!("添加附件失败，错误信息: {}", describe(&error));
+                            }
+                        }
+                    }
+                    "-html" => {
+                        if editor.current_order == 0{
+                            println!("未找到正在编辑的邮件，请使用 -take 创建或加载邮件"); continue;
+                        }
+                        let path = _input.trim_start()["-html".len()..].trim();
+                        if path.is_empty(){ print_error(); continue; }
+                        match std::fs::read_to_string(path){
+                            Ok(html) => {
+                                editor.current_html.replace(html);
+                                editor.current_saved = false;
+                                println!("[{}] 号邮件已读取 HTML 正文 [{}]", editor.current_order, path);
+                            }
+                            Err(error) => {
+                                println!("读取 HTML 正文失败，错误信息: {}", error);
+                            }
+                        }
+                    }
+                    "-inline" => {
+                        if editor.current_order == 0{
+                            println!("未找到正在编辑的邮件，请使用 -take 创建或加载邮件"); continue;
+                        }
+                        let path = _input.trim_start()["-inline".len()..].trim();
+                        if path.is_empty(){ print_error(); continue; }
+                        match Attachment::inline(path){
+                            Ok(image) => {
+                                println!("[{}] 号邮件已添加内嵌图片 [{}]，请在 HTML 正文中使用 cid:{} 引用", editor.current_order, image.filename, image.content_id.clone().unwrap_or_default());
+                                editor.current_inline_images.push(image);
+                                editor.current_saved = false;
+                            }
+                            Err(error) => {
+                                println!("添加内嵌图片失败，错误信息: {}", describe(&error));
+                            }
+                        }
+                    }
+                    "-detach" => {
+                        if let Some(mut param1) = __input.next(){
+                            let inline = param1 == "inline";
+                            if inline{
+                                if let Some(param2) = __input.next(){ param1 = param2; } else { print_error(); continue; }
+                            }
+                            if let Ok(index) = param1.trim().parse::<usize>(){
+                                let list = if inline { &mut editor.current_inline_images } else { &mut editor.current_attachments };
+                                if index == 0 || index > list.len(){
+                                    println!("未找到序号为 [{}] 的附件", index); continue;
+                                }
+                                let attachment = list.remove(index - 1);
+                                editor.current_saved = false;
+                                println!("[{}] 号邮件已移除附件 [{}]", editor.current_order, attachment.filename);
+                            }else { print_error(); }
+                        }else { print_error(); }
+                    }
+                    "-check" => {
+                        print_error();
+                    }
+                    "-display" => {
+                        if let Some(param1) = __input.next(){
+                            match param1{
+                                "from" => {
+                                    if let Some(from) = &editor.current_from{
+                                        println!("[{}] 号邮件发送邮箱地址: [{}]", editor.current_order, from);
+                                    }else{
+                                        println!("[{}] 号邮件无已保存的发送邮箱地址", editor.current_order);
+                                    }
+                                }
+                                "to" => {
+                                    editor.display_addresses("接收", &editor.current_to);
+                                }
+                                "cc" => {
+                                    editor.display_addresses("抄送", &editor.current_cc);
+                                }
+                                "bcc" => {
+                                    editor.display_addresses("密送", &editor.current_bcc);
+                                }
+                                "subject" => {
+                                    if let Some(subject) = &editor.current_subject{
+                                        println!("[{}] 号邮件主题: [{}]", editor.current_order, subject);
+                                    }else{
+                                        println!("[{}] 号邮件无已保存的邮件主题", editor.current_order);
+                                    }
+                                }
+                                "html" => {
+                                    editor.display_html();
+                                }
+                                "attachments" => {
+                                    editor.display_attachments();
+                                }
+                                "content" => {
+                                    if let Some(content) = &editor.current_content{
+                                        println!("---[{}] 号邮件内容---", editor.current_order);
+                                        let mut counter: usize = 0;
+                                        for line in content{
+                                            counter += 1;
+                                            println!("[{}] {}", counter, line);
+                                        }
+                                        println!();
+                                    }else{
+                                        println!("[{}] 号邮件无已保存的邮件内容", editor.current_order);
+                                    }
+                                }
+                                "all" => {
+                                    if let Some(from) = &editor.current_from{
+                                        println!("[{}] 号邮件发送邮箱地址: [{}]", editor.current_order, from);
+                                    }else{
+                                        println!("[{}] 号邮件无已保存的发送邮箱地址", editor.current_order);
+                                    }
+                                    editor.display_addresses("接收", &editor.current_to);
+                                    editor.display_addresses("抄送", &editor.current_cc);
+                                    editor.display_addresses("密送", &editor.current_bcc);
+                                    if let Some(subject) = &editor.current_subject{
+                                        println!("[{}] 号邮件主题: [{}]", editor.current_order, subject);
+                                    }else{
+                                        println!("[{}] 号邮件无已保存的邮件主题", editor.current_order);
+                                    }
+                                    if let Some(content) = &editor.current_content{
+                                        println!("---[{}] 号邮件内容---", editor.current_order);
+                                        let mut counter: usize = 0;
+                                        for line in content{
+                                            counter += 1;
+                                            println!("[{}] {}", counter, line);
+                                        }
+                                        println!();
+                                    }else{
+                                        println!("[{}] 号邮件无已保存的邮件内容", editor.current_order);
+                                    }
+                                    editor.display_html();
+                                    editor.display_attachments();
+                                }
+                                _ => { print_error(); }
+                            }
+                        }else { print_error(); }
+                    }
+                    "-list" => {
+                        if editor.saved_mails.len() == 0{
+                           println!("编辑器中未发现已保存的邮件");
+                        }else{
+                            println!("---编辑器中已保存的邮件列表---");
+                            let mut counter: usize = 0;
+                            for mail in &editor.saved_mails{
+                                if let Some(sub) = &mail.subject{
+                                    println!("[{}]  {}", counter + 1, sub);
+                                }else{
+                                    println!("[{}]  * Mail No Subject *", counter + 1);
+                                }
+                                counter += 1;
+                            }
+                            println!();
+                        }
+                    }
+                    "-save" => {
+                        let pointer = editor.saved_mails.get_mut(editor.current_order - 1).unwrap();
+                        pointer.subject = editor.current_subject.clone();
+                        pointer.from = editor.current_from.clone();
+                        pointer.to = editor.current_to.clone();
+                        pointer.cc = editor.current_cc.clone();
+                        pointer.bcc = editor.current_bcc.clone();
+                        pointer.content = editor.current_content.clone();
+                        pointer.html = editor.current_html.clone();
+                        pointer.attachments = editor.current_attachments.clone();
+                        pointer.inline_images = editor.current_inline_images.clone();
+                        editor.current_saved = true;
+                        println!("[{}] 号邮件已保存", editor.current_order);
+                    }
+                    "-take" => {
+                        if let Some(param1) = __input.next(){
+                            if !editor.current_saved && (editor.current_order != 0){
+                                println!("当前邮件尚未保存，请保存后重试或使用 -delete current 删除当前邮件"); continue;
+                            }
+                            if param1 == "new"{
+                                editor.saved_mails.push(SMTPMail::new());
+                                editor.current_order = editor.saved_mails.len();
+                                editor.current_saved = false;
+                                editor.current_from = None;
+                                editor.current_to = Vec::new();
+                                editor.current_cc = Vec::new();
+                                editor.current_bcc = Vec::new();
+                                editor.current_subject = None;
+                                editor.current_content = None;
+                                editor.current_html = None;
+                                editor.current_attachments = Vec::new();
+                                editor.current_inline_images = Vec::new();
+                                println!("已创建新邮件，序号为 [{}]", editor.current_order); continue;
+                            }
+                            if let Ok(order_number) = param1.trim().parse::<usize>(){
+                                if order_number > editor.saved_mails.len(){
+                                    println!("未找到序号为 [{}] 的邮件", order_number);
+                                }else{
+                                    editor.current_order = order_number;
+                                    let copy = editor.saved_mails.get(order_number).unwrap();
+                                    editor.current_content = copy.content.clone();
+                                    editor.current_html = copy.html.clone();
+                                    editor.current_attachments = copy.attachments.clone();
+                                    editor.current_inline_images = copy.inline_images.clone();
+                                    editor.current_subject = copy.subject.clone();
+                                    editor.current_from = copy.from.clone();
+                                    editor.current_to = copy.to.clone();
+                                    editor.current_cc = copy.cc.clone();
+                                    editor.current_bcc = copy.bcc.clone();
+                                    editor.current_saved = false;
+                                    println!("已读取序号为 [{}] 的邮件", order_number);
+                                }
+                            }else { print_error(); }
+                        }else { print_error(); }
+                    }
+                    "-delete" => {
+                        if let Some(param1) = __input.next(){
+                            if editor.current_order == 1 { println!("当前无可删除邮件"); continue; }
+                            if param1 == "current"{
+                                editor.clear_current();
+                                editor.saved_mails.remove(editor.current_order - 1);
+                                println!("已删除当前邮件"); continue;
+                            }else if let Ok(order_number) = param1.trim().parse::<usize>(){
+                                editor.saved_mails.remove(order_number - 1);
+                                if editor.current_order == order_number{
+                                    editor.clear_current();
+                                    println!("已删除当前邮件"); continue;
+                                }else if editor.current_order > order_number{
+                                    editor.current_order -= 1;
+                                    println!("已删除 [{}] 号邮件", order_number);
+                                }else if editor.current_order < order_number{
+                                    editor.current_order += 1;
+                                    println!("已删除 [{}] 号邮件", order_number);
+                                }
+                            }else { print_error(); }
+                        }else { print_error(); }
+                    }
+                    "-quit" => {
+                        if !editor.current_saved{
+                            println!("当前邮件尚未保存，请保存后再尝试退出"); continue;
+                        }
+                        break;
+                    }
+                    _ => { print_error(); }
+                }
+            }else{ print_error(); }
+        }
+        mail_list.clone_from(&editor.saved_mails);
+        println!("已退出邮件编辑器");
+    }
+
+    fn display_addresses(&self, kind: &str, addresses: &[String]){
+        if addresses.is_empty(){
+            println!("[{}] 号邮件无已保存的{}邮箱地址", self.current_order, kind);
+        }else{
+            println!("[{}] 号邮件{}邮箱地址: {:?}", self.current_order, kind, addresses);
+        }
+    }
+
+    fn display_html(&self){
+        if let Some(html) = &self.current_html{
+            println!("---[{}] 号邮件 HTML 正文---", self.current_order);
+            println!("{}", html);
+            println!();
+        }else{
+            println!("[{}] 号邮件无 HTML 正文", self.current_order);
+        }
+    }
+
+    fn display_attachments(&self){
+        if self.current_attachments.is_empty() && self.current_inline_images.is_empty(){
+            println!("[{}] 号邮件无附件", self.current_order);
+            return;
+        }
+        if !self.current_attachments.is_empty(){
+            println!("---[{}] 号邮件附件---", self.current_order);
+            for (index, attachment) in self.current_attachments.iter().enumerate(){
+                println!("[{}] {} ({}) <- {}", index + 1, attachment.filename, attachment.mime_type, attachment.path);
+            }
+        }
+        if !self.current_inline_images.is_empty(){
+            println!("---[{}] 号邮件内嵌图片---", self.current_order);
+            for (index, image) in self.current_inline_images.iter().enumerate(){
+                println!("[{}] cid:{} ({}) <- {}", index + 1, image.content_id.clone().unwrap_or_default(), image.mime_type, image.path);
+            }
+        }
+        println!();
+    }
+
+    pub fn clear_current(&mut self){
+        self.current_content = None;
+        self.current_saved = false;
+        self.current_order = 0;
+        self.current_subject = None;
+        self.current_to.clear();
+        self.current_cc.clear();
+        self.current_bcc.clear();
+        self.current_html = None;
+        self.current_attachments.clear();
+        self.current_inline_images.clear();
+        self.current_from = None;
+        self.lines.clear();
+    }
+}
\ No newline at end of file
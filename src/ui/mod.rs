@@ -0,0 +1,64 @@
+//交互式前端: 命令行菜单、输入与全部输出，协议与存储逻辑位于库中
+pub mod editor;
+pub mod pop3;
+pub mod smtp;
+
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+pub fn input() -> String{
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line);
+    line.trim_end().to_string()
+}
+
+pub fn input_idle(interval: Option<Duration>, mut on_idle: impl FnMut()) -> String{
+    //等待输入期间每隔 interval 调用一次 on_idle，用于在空闲时保持连接
+    let interval = match interval{
+        Some(interval) => interval,
+        None => { return input(); }
+    };
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move ||{
+        let _ = sender.send(input());
+    });
+    loop{
+        match receiver.recv_timeout(interval){
+            Ok(line) => { return line; }
+            Err(RecvTimeoutError::Timeout) => { on_idle(); }
+            Err(RecvTimeoutError::Disconnected) => { return String::new(); }
+        }
+    }
+}
+
+pub fn input_secret() -> String{
+    //从终端读取时不回显，标准输入不是终端时按普通输入读取
+    match rpassword::read_password(){
+        Ok(line) => line.trim_end().to_string(),
+        Err(_) => input()
+    }
+}
+
+pub fn print_error(){
+    println!("不支持的命令格式，请重新输入");
+}
+
+pub fn describe(error: &dyn std::error::Error) -> String{
+    //依次附上底层原因，如 "无法连接到 host:995: Connection refused (os error 111)"
+    let mut description = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source{
+        description.push_str(": ");
+        description.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    description
+}
+
+pub fn print_debug_log(lines: Vec<String>){
+    //debug 模式下客户端记录的服务器响应
+    for line in lines{
+        println!("{}", line);
+    }
+}
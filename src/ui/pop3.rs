@@ -0,0 +1,486 @@
+use mail_client::pop3::{Client, POP3Mail, ReceivedAttachment, State, parse_raw_mail, parse_first_address, save_attachment};
+use mail_client::config::Profile;
+use mail_client::tcp::Security;
+use mail_client::error::{MailError, AuthError};
+use mail_client::mime::MimePart;
+use mail_client::{codec, mbox};
+use super::{input_idle, print_error, print_debug_log, describe};
+
+pub fn display_detail(mail: &POP3Mail, title: &str){
+    let no_info = || String::from("No Info");
+    println!("--- {}详细内容---", title);
+    println!("发件人: {}", mail.from.as_ref().map(|from| from.0.clone()).unwrap_or_else(no_info));
+    println!("收件人: {}", mail.to.as_ref().map(|to| to.0.clone()).unwrap_or_else(no_info));
+    println!("邮件主题: {}", mail.subject.clone().unwrap_or_else(no_info));
+    println!("时间: {}", mail.time.clone().unwrap_or_else(no_info));
+    println!("字符集: {}", mail.charset.clone().unwrap_or_else(no_info));
+    println!("邮件内容---");
+    println!("{}", mail.plain.clone().unwrap_or_else(no_info));
+    if !mail.attachments.is_empty(){
+        println!("附件---");
+        for (index, attachment) in mail.attachments.iter().enumerate(){
+            println!("[{}] {} ({}, {} 字节)", index + 1, attachment.filename, attachment.mime_type, attachment.size);
+        }
+    }
+}
+
+pub fn browse_mbox(path: &str, index: Option<usize>){
+    //与服务器上的邮件使用相同的解析流程
+    let messages = match mbox::read(std::path::Path::new(path)){
+        Ok(messages) => messages,
+        Err(error) => { println!("读取 mbox 文件失败，错误信息: [{}]", describe(&error)); return; }
+    };
+    if messages.is_empty(){
+        println!("mbox 文件 {} 中没有邮件", path); return;
+    }
+    match index{
+        None => {
+            println!("---mbox 文件 {} 中的邮件列表---", path);
+            for (index, message) in messages.iter().enumerate(){
+                let part = MimePart::parse(&message.raw);
+                let subject = part.header("Subject").map(codec::decode_header).unwrap_or_else(|| String::from("* Mail No Subject *"));
+                println!("({}) [{}] -> [{}] From [{}]", index + 1, message.date, subject, message.sender);
+            }
+            println!();
+        }
+        Some(index) => {
+            if index == 0 || index > messages.len(){
+                println!("mbox 文件中未找到序号为 [{}] 的邮件", index); return;
+            }
+            match parse_raw_mail(&messages[index - 1].raw){
+                Ok(mail) => { display_detail(&mail, &format!("mbox [{}] 号邮件", index)); }
+                Err(error) => { println!("对邮件进行解析时出现错误，错误内容: [{}]", describe(&error)); }
+            }
+        }
+    }
+}
+
+fn require_login(client: &Client) -> bool{
+    match client.state(){
+        State::Init => { println!("客户端 <{}> 尚未发起连接，请先尝试连接", client.name()); false },
+        State::TCPConnected => { println!("客户端 <{}> 尚未完成登录，请先尝试登录", client.name()); false },
+        State::Authorized => true
+    }
+}
+
+fn connect(client: &mut Client){
+    if let State::Init = client.state(){
+        match client.open(){
+            Ok(()) => { println!("客户端 <{}> 连接成功", client.name()); }
+            Err(error) => { println!("客户端 <{}> 发起连接失败，错误信息: {}", client.name(), describe(&error)); }
+        }
+    }else{
+        println!("客户端 <{}> 处于已连接状态，若要重置连接，请使用 -reset 命令", client.name());
+    }
+}
+
+fn connection_test(client: &mut Client){
+    if let State::Init = client.state(){
+        println!("客户端 <{}> 尚未发起连接，请先尝试连接", client.name());
+        return;
+    }
+    if let State::TCPConnected = client.state(){
+        println!("客户端 <{}> 尚未登录，POP3 只能在登录后测试连接", client.name());
+        return;
+    }
+    println!("正在测试客户端 <{}> 的连接状态", client.name());
+    match client.noop(){
+        Ok(()) => { println!("客户端 <{}> 的连接正常", client.name()); }
+        Err(error) => {
+            if let State::Init = client.state(){
+                println!("客户端 <{}> 的连接已断开，请使用 -login 重新连接，错误信息: {}", client.name(), describe(&error));
+            }else{
+                println!("客户端 <{}> 的连接测试失败，错误信息: {}", client.name(), describe(&error));
+            }
+        }
+    }
+}
+
+fn login(client: &mut Client, account: String, password: String){
+    match client.state(){
+        State::Init => {
+            println!("客户端 <{}> 尚未发起连接，请先尝试连接", client.name()); return;
+        }
+        State::TCPConnected => {}
+        State::Authorized => {
+            if let Some(account) = client.account(){
+                println!("客户端 <{}> 已登录为 <{}> ，若要重新登录请使用 -reset 进行重置", client.name(), account);
+            }else{
+                println!("未找到客户端 <{}> 的邮箱账号，请尝试使用 -reset 进行重置", client.name());
+            }
+            return;
+        }
+    }
+    match client.authorize(account, password){
+        Ok(()) => {
+            if client.security() == Security::StartTls && client.is_secure(){
+                println!("客户端 <{}> 已升级为 TLS 加密连接", client.name());
+            }
+            println!("客户端 <{}> 已登录为 <{}>", client.name(), client.account().unwrap_or_default());
+        }
+        Err(MailError::Auth(AuthError::TlsRequired(method))) => {
+            if client.security() == Security::StartTls{
+                println!("客户端 <{}> 的服务器不支持 {}，已拒绝在未加密连接上登录，请检查服务器的加密方式配置", client.name(), method);
+            }else{
+                println!("客户端 <{}> 未使用加密连接，已拒绝以 {} 方式发送身份信息，若确需登录请使用 -insecure", client.name(), method);
+            }
+        }
+        Err(MailError::Auth(AuthError::MechanismNotSupported(error))) => {
+            println!("客户端 <{}> 无可用的身份验证方式，错误信息: {}", client.name(), error);
+        }
+        Err(MailError::Auth(AuthError::Rejected(reply))) => {
+            println!("客户端 <{}> 登录失败，服务器返回错误信息: <{}> ，请检查邮箱地址与授权码是否正确", client.name(), reply.text);
+        }
+        Err(error) => {
+            if let State::Init = client.state(){
+                println!("客户端 <{}> TLS 握手失败，错误信息: {}，请使用 -reset 进行重置", client.name(), describe(&error));
+            }else{
+                println!("客户端 <{}> 登录失败，错误类型: <{}>", client.name(), describe(&error));
+            }
+        }
+    }
+}
+
+fn status_query(client: &mut Client){
+    if !require_login(client){ return; }
+    match client.status(){
+        Ok(status) => { println!("客户端 <{}> 的邮箱状态: 邮件总数:[{}] 邮件总字节数: [{}]", client.name(), status.0, status.1); }
+        Err(error) => { println!("客户端 <{}> 邮箱状态获取失败，错误信息: {}", client.name(), describe(&error)); }
+    }
+}
+
+fn get_mail_by_order(client: &mut Client, order_number: usize){
+    if !require_login(client){ return; }
+    let raw = match client.retrieve(order_number){
+        Ok(raw) => raw,
+        Err(error) => { println!("客户端 <{}> 请求邮件内容出现问题，错误内容: [{}]", client.name(), describe(&error)); return; }
+    };
+    match parse_raw_mail(&raw){
+        Ok(mail) => {
+            display_detail(&mail, &format!("[{}] 号邮件", order_number));
+            if !mail.attachments.is_empty(){
+                println!("可使用 -save {} [附件序号] [保存目录] 保存附件", order_number);
+            }
+            //将查看过的邮件保存到本地 Maildir 并标记为已读
+            if let Err(error) = client.keep_local(order_number, &raw){
+                println!("客户端 <{}> 保存 [{}] 号邮件到本地存储失败，错误信息: [{}]", client.name(), order_number, describe(&error));
+            }
+        }
+        Err(error) => { println!("客户端 <{}> 获取邮件详细内容失败，错误信息: {}", client.name(), describe(&error)); }
+    }
+}
+
+fn local_mail(client: &mut Client, index: Option<usize>){
+    //读取本地 Maildir，不需要连接服务器
+    let store = match client.open_store(){
+        Ok(store) => store,
+        Err(error) => { println!("客户端 <{}> 打开本地邮件存储失败，错误信息: [{}]", client.name(), describe(&error)); return; }
+    };
+    let messages = match store.local_messages(){
+        Ok(messages) => messages,
+        Err(error) => { println!("客户端 <{}> 读取本地邮件失败，错误信息: [{}]", client.name(), describe(&error)); return; }
+    };
+    if messages.is_empty(){
+        println!("客户端 <{}> 的本地存储中没有邮件，可使用 -sync 进行同步", client.name()); return;
+    }
+    match index{
+        None => {
+            println!("---------客户端 <{}> 的本地邮件列表: ---------", client.name());
+            for (index, message) in messages.iter().enumerate(){
+                let raw = std::fs::read(&message.path).unwrap_or_default();
+                let part = MimePart::parse(&raw);
+                let subject = part.header("Subject").map(codec::decode_header).unwrap_or_else(|| String::from("* Mail No Subject *"));
+                let from = part.header("From").and_then(parse_first_address).map(|from| from.1).unwrap_or_default();
+                let state = if message.flags.contains('S') { "已读" } else { "未读" };
+                println!("({}) [{}] -> [{}] From [{}]", index + 1, state, subject, from);
+            }
+            println!();
+        }
+        Some(index) => {
+            if index == 0 || index > messages.len(){
+                println!("客户端 <{}> 的本地存储中未找到序号为 [{}] 的邮件", client.name(), index); return;
+            }
+            let message = &messages[index - 1];
+            let raw = match std::fs::read(&message.path){
+                Ok(raw) => raw,
+                Err(error) => { println!("客户端 <{}> 读取本地邮件失败，错误信息: {}", client.name(), error); return; }
+            };
+            match parse_raw_mail(&raw){
+                Ok(mail) => { display_detail(&mail, &format!("本地 [{}] 号邮件", index)); }
+                Err(error) => { println!("客户端 <{}> 对邮件进行解析时出现错误，错误内容: [{}]", client.name(), describe(&error)); }
+            }
+            if let Err(error) = store.mark_seen(&message.key){
+                println!("客户端 <{}> 标记邮件为已读失败，错误信息: [{}]", client.name(), describe(&error));
+            }
+        }
+    }
+}
+
+fn save_attachments(client: &mut Client, order_number: usize, index: Option<usize>, directory: String){
+    if !require_login(client){ return; }
+    let raw = match client.retrieve(order_number){
+        Ok(raw) => raw,
+        Err(error) => { println!("客户端 <{}> 请求邮件内容出现问题，错误内容: [{}]", client.name(), describe(&error)); return; }
+    };
+    let mail = match parse_raw_mail(&raw){
+        Ok(mail) => mail,
+        Err(error) => { println!("客户端 <{}> 对邮件进行解析时出现错误，错误内容: [{}]", client.name(), describe(&error)); return; }
+    };
+    if mail.attachments.is_empty(){
+        println!("客户端 <{}> 的 [{}] 号邮件没有附件", client.name(), order_number); return;
+    }
+    let selected: Vec<&ReceivedAttachment> = match index{
+        Some(index) => {
+            if index == 0 || index > mail.attachments.len(){
+                println!("客户端 <{}> 的 [{}] 号邮件中未找到序号为 [{}] 的附件", client.name(), order_number, index); return;
+            }
+            vec![&mail.attachments[index - 1]]
+        }
+        None => mail.attachments.iter().collect()
+    };
+    for attachment in selected{
+        match save_attachment(attachment, &directory){
+            Ok(path) => { println!("附件 [{}] 已保存至 {}", attachment.filename, path.display()); }
+            Err(error) => { println!("附件 [{}] 保存失败，错误信息: {}", attachment.filename, error); }
+        }
+    }
+}
+
+fn sync(client: &mut Client){
+    if !require_login(client){ return; }
+    let report = match client.fetch_new(){
+        Ok(report) => report,
+        Err(MailError::Reply(reply)) => {
+            println!("客户端 <{}> 的服务器不支持 UIDL，无法进行同步，错误信息: <{}>", client.name(), reply.text); return;
+        }
+        Err(error) => { println!("客户端 <{}> 同步失败，错误信息: [{}]", client.name(), describe(&error)); return; }
+    };
+    for order in &report.failed{
+        println!("客户端 <{}> 下载 [{}] 号邮件失败", client.name(), order);
+    }
+    println!("客户端 <{}> 同步完成: 服务器邮件 [{}] 封，新下载 [{}] 封，本地共 [{}] 封", client.name(), report.server, report.fetched.len(), report.local);
+    if !report.expired.is_empty(){
+        println!("客户端 <{}> 按保留策略 ({}) 已将 [{}] 封邮件添加到删除列表中，将会在退出时执行删除", client.name(), client.sync_config().retention.name(), report.expired.len());
+    }
+}
+
+fn mail_list(client: &mut Client){
+    if !require_login(client){ return; }
+    let summaries = match client.summaries(){
+        Ok(summaries) => summaries,
+        Err(error) => { println!("客户端 <{}> 请求邮件列表出现问题，错误信息: [{}]", client.name(), describe(&error)); return; }
+    };
+    println!("---------客户端 <{}> 的邮件列表: ---------", client.name());
+    println!("-> 邮件数: [{}]", summaries.len());
+    for summary in summaries{
+        println!("({}) [{}] [{} 字节]{} -> [{}] From [{}] to [{}]", summary.order, summary.date, summary.size,
+                 if summary.has_attachments { " [附件]" } else { "" }, summary.subject, summary.from, summary.to);
+    }
+    println!();
+}
+
+fn quit(client: &mut Client){
+    match client.close(){
+        Ok(deleted) => {
+            println!("客户端 <{}> 已退出登录", client.name());
+            if !deleted.is_empty(){
+                println!("以下序号的邮件将被删除:");
+                println!("-> {:?}", deleted);
+            }
+        }
+        Err(error) => { println!("客户端 <{}> 退出失败，错误信息: <{}>", client.name(), describe(&error)); }
+    }
+}
+
+fn delete(client: &mut Client, order_number: usize){
+    if client.delete_list().contains(&order_number){
+        println!("客户端 <{}>的 [{}]号 邮件添加到删除列表中失败，错误信息: 邮件已在删除列表中", client.name(), order_number);
+        return;
+    }
+    match client.delete(order_number){
+        Ok(()) => { println!("客户端 <{}> 已将 [{}]号 邮件添加到删除列表中，将会在退出时执行删除", client.name(), order_number); }
+        Err(error) => { println!("客户端 <{}> 的 [{}]号 邮件添加到删除列表中失败，错误信息: <{}>", client.name(), order_number, describe(&error)); }
+    }
+}
+
+fn reset_delete(client: &mut Client){
+    match client.reset_delete(){
+        Ok(()) => { println!("客户端 <{}> 的删除列表已重置", client.name()); }
+        Err(error) => { println!("客户端 <{}> 删除列表重置失败，错误信息: {}", client.name(), describe(&error)); }
+    }
+}
+
+fn display_delete_list(client: &Client){
+    if !client.delete_list().is_empty(){
+        println!("客户端 <{}> 的删除列表如下: ", client.name());
+        println!("-> {:?}", client.delete_list());
+    }else{
+        println!("客户端 <{}> 的删除列表中无内容", client.name());
+    }
+    println!();
+}
+
+pub fn run(profile: &Profile, password: String){
+    let mut client = match Client::from_profile(profile, password.clone()){
+        Ok(client) => client,
+        Err(error) => { println!("无法创建客户端，错误信息: {}，请检查配置后重试", describe(&error)); return; }
+    };
+    let account = profile.account.clone().unwrap_or_default();
+    loop{
+        let c = ||{
+            return if let State::Authorized = client.state() {
+                "已登录".to_string()
+            } else { "未登录".to_string() }
+        };
+        println!("正在运行: pop3客户端 <{}> ({})，使用 -help 获得帮助", client.name(), c());
+        let _input = input_idle(client.keepalive_interval(), ||{
+            match client.keepalive(){
+                Ok(_) => {}
+                Err(MailError::Reply(error)) => { println!("客户端 <{}> 的保活命令被服务器拒绝，错误信息: {}", client.name(), error); }
+                Err(error) => { println!("客户端 <{}> 保活失败，连接已断开，错误信息: {}", client.name(), describe(&error)); }
+            }
+        });
+        let mut __input = _input.split_whitespace();
+        if let Some(head) = __input.next(){
+            match head{
+                "-help" => {
+                    println!("-login  自动发起连接并尝试登录");
+                    println!("-test  测试连接状况");
+                    println!("-delete [order number(int):邮件列表中序号]  将指定序号的邮件标记为删除");
+                    println!("-list <delete:删除列表>/<mail:邮件列表(default)>");
+                    println!("-detail [order number(int):邮件列表中序号]  显示指定序号邮件的详细内容");
+                    println!("-sync  将服务器上的新邮件下载到本地存储(Maildir)，并按保留策略标记删除");
+                    println!("-local <order number(int):本地邮件序号>  查看本地存储中的邮件列表或指定邮件，无需连接服务器");
+                    println!("-export [path:mbox 文件路径]  将本地存储中的邮件导出到 mbox 文件");
+                    println!("-save [order number(int):邮件列表中序号] <index(int):附件序号，默认全部> <dir:保存目录，默认当前目录>  保存邮件附件");
+                    println!("-reset <delete:删除列表>/<connection:连接信息(default)>  对指定内容进行重置");
+                    println!("-insecure  切换是否允许在未加密连接上登录(默认禁止)");
+                    println!("-debug  切换模式(普通/debug)");
+                    println!("-quit <connection:tcp连接(default)>/<client:客户端>  执行quit命令，此时会执行删除操作");
+                }
+                "-login" => {
+                    connect(&mut client);
+                    login(&mut client, account.clone(), password.clone());
+                }
+                "-test" => {
+                    connection_test(&mut client);
+                }
+                "-delete" => {
+                    if let Some(param) = __input.next(){
+                        let _order_number = param.trim().parse::<usize>();
+                        if let Ok(on) = _order_number{
+                            delete(&mut client, on);
+                        }else { print_error(); }
+                    }
+                }
+                "-list" => {
+                    let k = __input.next();
+                    if let Some("delete") = k{
+                        display_delete_list(&client);
+                    }else if let Some("mail") = k{
+                        mail_list(&mut client);
+                    }else if None == k{
+                        mail_list(&mut client);
+                    }else{
+                        print_error();
+                    }
+                }
+                "-detail" => {
+                    if let Some(_on) = __input.next(){
+                        if let Ok(on) = _on.trim().parse::<usize>(){
+                            get_mail_by_order(&mut client, on);
+                        }else{ print_error(); }
+                    }else{
+                        print_error();
+                    }
+                }
+                "-status" => {
+                    status_query(&mut client);
+                }
+                "-sync" => {
+                    sync(&mut client);
+                }
+                "-export" => {
+                    match __input.next(){
+                        Some(path) => {
+                            match client.export_local(path){
+                                Ok(count) => { println!("客户端 <{}> 已将 [{}] 封本地邮件导出到 {}", client.name(), count, path); }
+                                Err(error) => { println!("客户端 <{}> 导出邮件失败，错误信息: [{}]", client.name(), describe(&error)); }
+                            }
+                        }
+                        None => { print_error(); }
+                    }
+                }
+                "-local" => {
+                    match __input.next().map(|param| param.trim().parse::<usize>()){
+                        None => { local_mail(&mut client, None); }
+                        Some(Ok(index)) => { local_mail(&mut client, Some(index)); }
+                        Some(Err(_)) => { print_error(); }
+                    }
+                }
+                "-save" => {
+                    if let Some(Ok(on)) = __input.next().map(|param| param.trim().parse::<usize>()){
+                        let mut index: Option<usize> = None;
+                        let mut directory = String::from(".");
+                        if let Some(param) = __input.next(){
+                            match param.parse::<usize>(){
+                                Ok(i) => {
+                                    index.replace(i);
+                                    if let Some(dir) = __input.next(){ directory = dir.to_string(); }
+                                }
+                                Err(_) => { directory = param.to_string(); }
+                            }
+                        }
+                        save_attachments(&mut client, on, index, directory);
+                    }else{ print_error(); }
+                }
+                "-reset" => {
+                    let k = __input.next();
+                    if let Some("delete") = k{
+                        reset_delete(&mut client);
+                    }else if let Some("client") = k{
+                        client.reset_client();
+                    }else{
+                        print_error();
+                    }
+                }
+                "-insecure" => {
+                    let allow = !client.allows_plaintext_auth();
+                    client.set_allow_plaintext_auth(allow);
+                    if allow{
+                        println!("已允许在未加密连接上发送身份信息");
+                    }else{
+                        println!("已禁止在未加密连接上发送身份信息");
+                    }
+                }
+                "-debug" => {
+                    let debug = !client.is_debug();
+                    client.set_debug(debug);
+                    if debug{
+                        println!("已切换至 debug 模式");
+                    }else{
+                        println!("已切换至 普通 模式");
+                    }
+                }
+                "-quit" => {
+                    let k = __input.next();
+                    if let Some("connection") = k{
+                        quit(&mut client);
+                    }else if let Some("client") = k{
+                        quit(&mut client);
+                        break;
+                    }else if None == k{
+                        quit(&mut client);
+                    }else{
+                        print_error();
+                    }
+                }
+                _ => {
+                    print_error();
+                }
+            }
+        }else { print_error(); }
+        print_debug_log(client.take_debug_log());
+    }
+
+}
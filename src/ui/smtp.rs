@@ -0,0 +1,231 @@
+use mail_client::smtp::{Client, SMTPMail, Capabilities, State};
+use mail_client::config::Profile;
+use mail_client::tcp::Security;
+use mail_client::error::{MailError, AuthError, MessageError};
+use super::{input, input_idle, print_error, print_debug_log, describe};
+
+fn display_capabilities(capabilities: &Capabilities){
+    let flag = |b: bool| if b { "支持" } else { "不支持" };
+    match capabilities.size{
+        Some(0) => println!("SIZE: 支持 (无固定上限)"),
+        Some(size) => println!("SIZE: 支持 (上限 {} 字节)", size),
+        None => println!("SIZE: 不支持")
+    }
+    if capabilities.auth.is_empty(){
+        println!("AUTH: 不支持");
+    }else{
+        println!("AUTH: {}", capabilities.auth.join(" "));
+    }
+    println!("PIPELINING: {}", flag(capabilities.pipelining));
+    println!("8BITMIME: {}", flag(capabilities.eight_bit_mime));
+    println!("SMTPUTF8: {}", flag(capabilities.smtp_utf8));
+    println!("DSN: {}", flag(capabilities.dsn));
+    println!("CHUNKING: {}", flag(capabilities.chunking));
+    println!("STARTTLS: {}", flag(capabilities.starttls));
+    println!("ENHANCEDSTATUSCODES: {}", flag(capabilities.enhanced_status_codes));
+    if !capabilities.others.is_empty(){
+        println!("其他扩展: {}", capabilities.others.join(", "));
+    }
+}
+
+fn connect(client: &mut Client){
+    if let State::Init = client.state(){
+        match client.open(){
+            Ok(()) => { println!("客户端 <{}> 连接成功", client.name()); }
+            Err(error) => { println!("客户端 <{}> 发起连接失败，错误信息: {}", client.name(), describe(&error)); }
+        }
+    }else{
+        println!("客户端 <{}> 处于已连接状态，若要重置连接，请使用 -reset 命令", client.name());
+    }
+}
+
+fn login(client: &mut Client){
+    match client.state(){
+        State::Init => {
+            println!("客户端 <{}> 尚未发起连接，请先尝试连接", client.name()); return;
+        }
+        State::TCPConnected => {
+
+        }
+        State::Authorized => {
+            if let Some(account) = client.account(){
+                println!("客户端 <{}> 已登录为 <{}> ，若要重新登录请使用 -reset 进行重置", client.name(), account); return;
+            }else{
+                println!("未找到客户端 <{}> 的邮箱账号，请尝试使用 -reset 进行重置", client.name()); return;
+            }
+        }
+    }
+
+    match client.authorize(){
+        Ok(()) => {
+            if client.security() == Security::StartTls && client.is_secure(){
+                println!("客户端 <{}> 已升级为 TLS 加密连接", client.name());
+            }
+            println!("客户端 <{}> 登录成功", client.name());
+        }
+        Err(MailError::Auth(AuthError::TlsRequired(method))) => {
+            if client.security() == Security::StartTls{
+                println!("客户端 <{}> 的服务器不支持 {}，已拒绝在未加密连接上登录，请检查服务器的加密方式配置", client.name(), method);
+            }else{
+                println!("客户端 <{}> 未使用加密连接，已拒绝以 {} 方式发送身份信息，若确需登录请使用 -insecure", client.name(), method);
+            }
+        }
+        Err(MailError::Auth(AuthError::MechanismNotSupported(error))) => {
+            let advertised = client.capabilities().map(|caps| caps.auth.join(" ")).unwrap_or_default();
+            println!("客户端 <{}> 无可用的身份验证方式，服务器支持: [{}]，错误信息: {}", client.name(), advertised, error);
+        }
+        Err(error) => {
+            if let State::Init = client.state(){
+                println!("客户端 <{}> TLS 握手失败，错误信息: {}，请使用 -reset 进行重置", client.name(), describe(&error));
+            }else{
+                println!("客户端 <{}> 登录失败，错误信息: {}", client.name(), describe(&error));
+            }
+        }
+    }
+}
+
+fn send(client: &mut Client, mail_list: &mut Vec<SMTPMail>, order_number: usize){
+    match client.state(){
+        State::Init => {
+            println!("客户端 <{}> 尚未发起连接，请先尝试连接", client.name()); return;
+        }
+        State::TCPConnected => {
+            println!("客户端 <{}> 尚未登录，请先尝试登录", client.name()); return;
+        }
+        State::Authorized => {
+
+        }
+    }
+    if order_number == 0 || order_number > mail_list.len(){
+        println!("客户端 <{}> 未找到序号为 [{}] 的邮件，请重试", client.name(), order_number); return;
+    }
+
+    //发送成功后从已保存邮件列表中移除
+    let target = &mail_list[order_number - 1];
+    match client.deliver(target){
+        Ok(delivery) => {
+            for recipient in &delivery.accepted{
+                println!("客户端 <{}> 的接收方 <{}> 已被服务器接受", client.name(), recipient);
+            }
+            println!("客户端 <{}> 已成功发送邮件 [{}]", client.name(), target.subject.clone().unwrap_or_default());
+            if !delivery.rejected.is_empty(){
+                println!("-> 已送达 {} 个接收方，以下接收方被拒绝: {:?}", delivery.accepted.len(), delivery.rejected);
+            }
+            mail_list.remove(order_number - 1);
+        }
+        Err(MailError::Message(MessageError::RecipientsRejected(rejected))) => {
+            println!("客户端 <{}> 的所有接收方均被拒绝，邮件未发送: {:?}", client.name(), rejected);
+        }
+        Err(error) => {
+            println!("客户端 <{}> 发送邮件失败，错误信息: {}", client.name(), describe(&error));
+        }
+    }
+}
+
+pub fn run(profile: &Profile, password: String, mail_list: &mut Vec<SMTPMail>){
+    let mut client = match Client::from_profile(profile, password){
+        Ok(client) => client,
+        Err(error) => { println!("无法创建客户端，错误信息: {}，请检查配置后重试", describe(&error)); return; }
+    };
+
+    loop{
+        let c = ||{
+            return if let State::Authorized = client.state() {
+                "已登录".to_string()
+            } else { "未登录".to_string() }
+        };
+        println!("正在运行: smtp客户端 <{}> ({})，使用 -help 获得帮助", client.name(), c());
+        let _input = input_idle(client.keepalive_interval(), ||{
+            match client.keepalive(){
+                Ok(_) => {}
+                Err(MailError::Reply(error)) => { println!("客户端 <{}> 的保活命令被服务器拒绝，错误信息: {}", client.name(), error); }
+                Err(error) => { println!("客户端 <{}> 保活失败，连接已断开，错误信息: {}", client.name(), describe(&error)); }
+            }
+        });
+        let mut __input = _input.split_whitespace();
+        if let Some(head) = __input.next(){
+            match head{
+                "-help" => {
+                    println!("-login  自动发起连接并尝试登录");
+                    println!("-list  显示邮件列表中内容");
+                    println!("-send [order number(int):邮件列表序号]  发送指定序号的邮件");
+                    println!("-caps  显示服务器支持的扩展");
+                    println!("-insecure  切换是否允许在未加密连接上登录(默认禁止)");
+                    println!("-reset  重置 smtp 客户端");
+                    println!("-debug  切换模式(普通/debug)");
+                    println!("-quit  退出 smtp 客户端");
+                }
+                "-login" => {
+                    connect(&mut client);
+                    login(&mut client);
+                }
+                "-list" => {
+                    if mail_list.is_empty(){
+                        println!("无已保存的邮件，可使用编辑器创建新的邮件"); continue;
+                    }
+                    println!("---已保存的邮件列表---");
+                    let mut counter: usize = 0;
+                    for mail in mail_list.iter(){
+                        counter += 1;
+                        if let Some(sub) = &mail.subject{
+                            println!("[{}] {}", counter, sub);
+                        }else{
+                            println!("[{}] * Mail No Subject *", counter);
+                        }
+                    }
+                    println!();
+                }
+                "-send" => {
+                    if let Some(param1) = __input.next(){
+                        if let Ok(on) = param1.trim().parse::<usize>(){
+                            send(&mut client, mail_list, on);
+                        }else { print_error(); }
+                    }else { print_error(); }
+                }
+                "-caps" => {
+                    if let Some(caps) = client.capabilities(){
+                        println!("客户端 <{}> 的服务器扩展信息: ", client.name());
+                        display_capabilities(caps);
+                        println!();
+                    }else{
+                        println!("客户端 <{}> 尚未获取服务器扩展信息，请先使用 -login 登录", client.name());
+                    }
+                }
+                "-insecure" => {
+                    let allow = !client.allows_plaintext_auth();
+                    client.set_allow_plaintext_auth(allow);
+                    if allow{
+                        println!("已允许在未加密连接上发送身份信息");
+                    }else{
+                        println!("已禁止在未加密连接上发送身份信息");
+                    }
+                }
+                "-reset" => {
+                    client.reset();
+                }
+                "-debug" => {
+                    let debug = !client.is_debug();
+                    client.set_debug(debug);
+                    if debug{
+                        println!("已切换至 debug 模式");
+                    }else{
+                        println!("已切换至 普通 模式");
+                    }
+                }
+                "-quit" => {
+                    println!("将会退出 smtp 客户端，是否确认？(yes/no)");
+                    let s = input();
+                    if &s == "yes"{
+                        break;
+                    }else if &s == "no"{
+                        continue;
+                    }else { print_error(); }
+                }
+                _ => {
+                    print_error();
+                }
+            }
+        }else{ print_error();}
+        print_debug_log(client.take_debug_log());
+    }
+}